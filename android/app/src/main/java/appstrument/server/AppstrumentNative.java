@@ -1,7 +1,38 @@
 package appstrument.server;
 
+import java.lang.ref.PhantomReference;
+import java.lang.ref.ReferenceQueue;
+import java.util.ArrayList;
+import java.util.Collections;
+import java.util.HashSet;
+import java.util.List;
+import java.util.Set;
+import java.util.concurrent.locks.ReentrantLock;
+
 public class AppstrumentNative {
+    // the proxies of SLAT lambdas, which are enqueued once they are collected so that their
+    // lambdas can be released. Cleaner needs API level 33, so they're tracked by hand
+    private static final ReferenceQueue<Object> collectedLambdas = new ReferenceQueue<>();
+    // keeps the references themselves from being collected before they are enqueued
+    private static final Set<LambdaReference> lambdaReferences = Collections.synchronizedSet(new HashSet<>());
+
     private long contextPtr;
+    // serializes requests and lambda invocations
+    private final ReentrantLock lock = new ReentrantLock();
+    // lambdas whose proxies were collected but that haven't been released yet, because the
+    // session was busy
+    private final List<Integer> releasedLambdas = new ArrayList<>();
+
+    private static final class LambdaReference extends PhantomReference<Object> {
+        private final AppstrumentNative session;
+        private final int lambdaId;
+
+        private LambdaReference(Object proxy, AppstrumentNative session, int lambdaId) {
+            super(proxy, collectedLambdas);
+            this.session = session;
+            this.lambdaId = lambdaId;
+        }
+    }
 
     public AppstrumentNative() {
         contextPtr = nativeCreateContext();
@@ -9,6 +40,23 @@ public class AppstrumentNative {
 
     public static void initialize() {
         nativeInitialize();
+
+        Thread releaseThread = new Thread(AppstrumentNative::releaseCollectedLambdas, "SLAT lambda release");
+        releaseThread.setDaemon(true);
+        releaseThread.start();
+    }
+
+    private static void releaseCollectedLambdas() {
+        while (true) {
+            LambdaReference reference;
+            try {
+                reference = (LambdaReference) collectedLambdas.remove();
+            } catch (InterruptedException e) {
+                return;
+            }
+            lambdaReferences.remove(reference);
+            reference.session.releaseLambda(reference.lambdaId);
+        }
     }
 
     private static native void nativeInitialize();
@@ -19,10 +67,104 @@ public class AppstrumentNative {
 
     private native byte[] nativeHandleRequest(long context, byte[] request, int offset);
 
+    private native byte[] nativeHandleCancelRequest(long context, byte[] request, int offset);
+
     private native void nativeDestroyContext(long context);
 
+    private native Object nativeInvokeLambda(long context, int lambdaId, Object[] args, String returnType);
+
+    private native void nativeReleaseLambdas(long context, int[] lambdaIds);
+
+    private native void nativeCancelRequests(long context);
+
+    // lambdas may still be invoked while the same thread is evaluating SLAT, e.g. by a
+    // method the script called
     public byte[] handleRequest(byte[] request, int offset) {
-        return nativeHandleRequest(contextPtr, request, offset);
+        lock.lock();
+        try {
+            return nativeHandleRequest(contextPtr, request, offset);
+        } finally {
+            lock.unlock();
+            releaseIdleLambdas();
+        }
+    }
+
+    // cancellations must not wait for the request they cancel, so they aren't serialized;
+    // returns null for any other request
+    public byte[] handleCancelRequest(byte[] request, int offset) {
+        return nativeHandleCancelRequest(contextPtr, request, offset);
+    }
+
+    // cancels the running request and every request after it, without waiting for them
+    public void cancelRequests() {
+        nativeCancelRequests(contextPtr);
+    }
+
+    public Object invokeLambda(int lambdaId, Object[] args, String returnType) {
+        // a request may be waiting for the thread invoking the lambda, e.g. if a method the
+        // script called runs the lambda on another thread and joins it. Waiting for the
+        // request would deadlock then, so lambdas fail instead
+        if (!lock.tryLock()) {
+            throw new IllegalStateException("The SLAT session of this lambda is busy evaluating a request on another thread");
+        }
+        try {
+            if (contextPtr == 0) {
+                throw new IllegalStateException("The SLAT session of this lambda has been closed");
+            }
+            return nativeInvokeLambda(contextPtr, lambdaId, args, returnType);
+        } finally {
+            lock.unlock();
+            releaseIdleLambdas();
+        }
+    }
+
+    // called for each proxy created for a lambda, which releases it once it is collected
+    void trackLambda(Object proxy, int lambdaId) {
+        lambdaReferences.add(new LambdaReference(proxy, this, lambdaId));
+    }
+
+    private void releaseLambda(int lambdaId) {
+        synchronized (releasedLambdas) {
+            releasedLambdas.add(lambdaId);
+        }
+        releaseIdleLambdas();
+    }
+
+    // releases the collected lambdas unless the session is busy, in which case whoever is
+    // using it releases them once it's done. Lambdas invoked by a request on this thread
+    // leave that to the request
+    private void releaseIdleLambdas() {
+        while (!lock.isHeldByCurrentThread() && hasReleasedLambdas() && lock.tryLock()) {
+            try {
+                releaseLambdas();
+            } finally {
+                lock.unlock();
+            }
+        }
+    }
+
+    private boolean hasReleasedLambdas() {
+        synchronized (releasedLambdas) {
+            return !releasedLambdas.isEmpty();
+        }
+    }
+
+    // must hold the lock
+    private void releaseLambdas() {
+        int[] lambdaIds;
+        synchronized (releasedLambdas) {
+            // destroying the context has freed every lambda already
+            if (releasedLambdas.isEmpty() || contextPtr == 0) {
+                releasedLambdas.clear();
+                return;
+            }
+            lambdaIds = new int[releasedLambdas.size()];
+            for (int i = 0; i < lambdaIds.length; i++) {
+                lambdaIds[i] = releasedLambdas.get(i);
+            }
+            releasedLambdas.clear();
+        }
+        nativeReleaseLambdas(contextPtr, lambdaIds);
     }
 
     public byte[] createLogcatPacket(String log) {
@@ -30,6 +172,16 @@ public class AppstrumentNative {
     }
 
     public void destroy() {
-        nativeDestroyContext(contextPtr);
+        lock.lock();
+        try {
+            // frees every lambda, including the ones whose proxies are still alive
+            nativeDestroyContext(contextPtr);
+            contextPtr = 0;
+            synchronized (releasedLambdas) {
+                releasedLambdas.clear();
+            }
+        } finally {
+            lock.unlock();
+        }
     }
 }
@@ -11,11 +11,16 @@ import java.io.IOException;
 import java.net.InetSocketAddress;
 import java.nio.ByteBuffer;
 import java.util.HashMap;
+import java.util.concurrent.ExecutorService;
+import java.util.concurrent.Executors;
 import java.util.zip.GZIPOutputStream;
 
 public class AppstrumentServer extends WebSocketServer {
     public static AppstrumentServer instance;
     public HashMap<WebSocket, AppstrumentNative> natives = new HashMap<>();
+    // requests of a connection are handled in order on its own thread, so that the
+    // WebSocket thread stays free to receive cancellations
+    public HashMap<WebSocket, ExecutorService> requestThreads = new HashMap<>();
 
     public AppstrumentServer(int port) {
         super(new InetSocketAddress(port));
@@ -37,11 +42,18 @@ public class AppstrumentServer extends WebSocketServer {
     @Override
     public void onOpen(WebSocket conn, ClientHandshake handshake) {
         this.natives.put(conn, new AppstrumentNative());
+        this.requestThreads.put(conn, Executors.newSingleThreadExecutor());
     }
 
     @Override
     public void onClose(WebSocket conn, int code, String reason, boolean remote) {
-        this.natives.remove(conn).destroy();
+        // the context can only be destroyed once the running request finishes, which
+        // happens on its thread so that this one doesn't wait for it
+        AppstrumentNative appstrumentNative = this.natives.remove(conn);
+        appstrumentNative.cancelRequests();
+        ExecutorService requestThread = this.requestThreads.remove(conn);
+        requestThread.execute(appstrumentNative::destroy);
+        requestThread.shutdown();
     }
 
     @Override
@@ -53,13 +65,28 @@ public class AppstrumentServer extends WebSocketServer {
         AppstrumentNative appstrumentNative = natives.get(conn);
         byte[] req = message.array();
 
-        byte[] res = new byte[0];
         try {
-            res = appstrumentNative.handleRequest(req, 0);
+            byte[] res = appstrumentNative.handleCancelRequest(req, 0);
+            if (res != null) {
+                conn.send(gzipCompress(res));
+                return;
+            }
         } catch (Throwable t) {
             LogUtil.print(Log.getStackTraceString(t));
         }
-        conn.send(gzipCompress(res));
+        requestThreads.get(conn).execute(() -> {
+            // requests still waiting when the connection closed have no one to answer to
+            if (!conn.isOpen()) {
+                return;
+            }
+            byte[] res = new byte[0];
+            try {
+                res = appstrumentNative.handleRequest(req, 0);
+            } catch (Throwable t) {
+                LogUtil.print(Log.getStackTraceString(t));
+            }
+            conn.send(gzipCompress(res));
+        });
     }
 
     @Override
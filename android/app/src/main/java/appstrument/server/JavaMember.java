@@ -0,0 +1,21 @@
+package appstrument.server;
+
+public class JavaMember {
+    public static final int FIELD = 0;
+    public static final int METHOD = 1;
+    public static final int CLASS = 2;
+
+    public String name;
+    public int kind;
+    // the member as declared, e.g. "int indexOf(java.lang.String, int)"
+    public String signature;
+    // the modifiers and declaring class of the member
+    public String documentation;
+
+    public JavaMember(String name, int kind, String signature, String documentation) {
+        this.name = name;
+        this.kind = kind;
+        this.signature = signature;
+        this.documentation = documentation;
+    }
+}
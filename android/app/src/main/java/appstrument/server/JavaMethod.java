@@ -0,0 +1,16 @@
+package appstrument.server;
+
+public class JavaMethod {
+    public String name;
+    public String signature;
+    public String description;
+    // whether the trailing arguments must be collected into the variable arity array
+    public boolean varArgs;
+
+    public JavaMethod(String name, String signature, String description, boolean varArgs) {
+        this.name = name;
+        this.signature = signature;
+        this.description = description;
+        this.varArgs = varArgs;
+    }
+}
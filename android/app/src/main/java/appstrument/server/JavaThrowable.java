@@ -0,0 +1,13 @@
+package appstrument.server;
+
+public class JavaThrowable {
+    public String className;
+    public String message;
+    public String[] stackTrace;
+
+    public JavaThrowable(String className, String message, String[] stackTrace) {
+        this.className = className;
+        this.message = message;
+        this.stackTrace = stackTrace;
+    }
+}
@@ -1,13 +1,18 @@
 package appstrument.server;
 
 import java.lang.reflect.Array;
+import java.lang.reflect.Constructor;
 import java.lang.reflect.Field;
 import java.lang.reflect.Member;
 import java.lang.reflect.Method;
 import java.lang.reflect.Modifier;
 import java.util.ArrayList;
 import java.util.Arrays;
+import java.util.Collections;
+import java.util.HashSet;
+import java.util.IdentityHashMap;
 import java.util.List;
+import java.util.Set;
 import java.util.stream.Collectors;
 
 public class ReflectionUtil {
@@ -77,7 +82,7 @@ public class ReflectionUtil {
         }
     }
 
-    private static String getTypeSignature(Class<?> type) {
+    static String getTypeSignature(Class<?> type) {
         if (type.isArray()) {
             return "[" + getTypeSignature(type.getComponentType());
         } else if (type.isPrimitive()) {
@@ -113,73 +118,320 @@ public class ReflectionUtil {
         return "L" + type.getName().replace('.', '/') + ";";
     }
 
+    // the class and its superclasses, most derived first, followed by every interface they implement
+    private static List<Class<?>> getHierarchy(Class<?> cls) {
+        List<Class<?>> hierarchy = new ArrayList<>();
+        for (Class<?> superclass = cls; superclass != null; superclass = superclass.getSuperclass()) {
+            hierarchy.add(superclass);
+        }
+        for (int i = 0; i < hierarchy.size(); i++) {
+            for (Class<?> iface : hierarchy.get(i).getInterfaces()) {
+                if (!hierarchy.contains(iface)) {
+                    hierarchy.add(iface);
+                }
+            }
+        }
+        return hierarchy;
+    }
+
+    // the methods of a class and its supertypes, skipping those that are overridden or hidden
     private static List<Method> getAllMethods(Class<?> cls, MemberType type) {
-        List<Method> methods = Arrays.stream(cls.getDeclaredMethods()).filter(type::filter).collect(Collectors.toList());
-        if (cls.getSuperclass() != null) {
-            methods.addAll(getAllMethods(cls.getSuperclass(), type));
+        List<Method> methods = new ArrayList<>();
+        Set<String> signatures = new HashSet<>();
+        for (Class<?> declaringClass : getHierarchy(cls)) {
+            // static methods of interfaces aren't inherited, JLS 8.4.8
+            if (type == MemberType.STATIC && declaringClass.isInterface() && declaringClass != cls) {
+                continue;
+            }
+            for (Method method : declaringClass.getDeclaredMethods()) {
+                // a bridge has the signature of the method it bridges to, and is skipped by callers
+                if (type.filter(method) && (method.isBridge()
+                        || signatures.add(method.getName() + getParametersSignature(method.getParameterTypes())))) {
+                    methods.add(method);
+                }
+            }
         }
         return methods;
     }
 
-    private static String getMethodSignature(Method method) {
+    private static String getParametersSignature(Class<?>[] parameterTypes) {
         StringBuilder sb = new StringBuilder("(");
-        for (Class<?> parType : method.getParameterTypes()) {
+        for (Class<?> parType : parameterTypes) {
             sb.append(getTypeSignature(parType));
         }
         sb.append(")");
-        sb.append(getTypeSignature(method.getReturnType()));
         return sb.toString();
     }
 
-    private static Method findMatchingMethod(List<Method> methods, String name, Class<?>[] typeHints) {
-        outer:
-        for (Method method : methods) {
-            if (!method.getName().equals(name)) {
-                continue;
+    private static String getMethodSignature(Method method) {
+        return getParametersSignature(method.getParameterTypes()) + getTypeSignature(method.getReturnType());
+    }
+
+    private static String getConstructorSignature(Constructor<?> constructor) {
+        return getParametersSignature(constructor.getParameterTypes()) + "V";
+    }
+
+    // a lambda argument is hinted as "->" followed by its number of parameters
+    private static final String LAMBDA_HINT = "->";
+
+    private static Class<?>[] parseTypeHints(String[] typeHints) {
+        // a null type hint stands for a null argument, which is assignable to any reference type
+        return Arrays.stream(typeHints)
+                .map(typeHint -> typeHint == null || typeHint.startsWith(LAMBDA_HINT) ? null : parseTypeSignature(typeHint))
+                .toArray(Class[]::new);
+    }
+
+    private static int[] parseLambdaArities(String[] typeHints) {
+        return Arrays.stream(typeHints)
+                .mapToInt(typeHint -> typeHint != null && typeHint.startsWith(LAMBDA_HINT)
+                        ? Integer.parseInt(typeHint.substring(LAMBDA_HINT.length()))
+                        : -1)
+                .toArray();
+    }
+
+    private static boolean isLambdaCompatible(int arity, Class<?> to) {
+        Method functional = SlatInvocationHandler.getFunctionalMethod(to);
+        return functional != null && functional.getParameterTypes().length == arity;
+    }
+
+    private static final Class<?>[][] BOXED_TYPES = new Class<?>[][] {
+            { byte.class, Byte.class },
+            { short.class, Short.class },
+            { int.class, Integer.class },
+            { long.class, Long.class },
+            { float.class, Float.class },
+            { double.class, Double.class },
+            { char.class, Character.class },
+            { boolean.class, Boolean.class },
+    };
+
+    private static Class<?> boxType(Class<?> primitive) {
+        for (Class<?>[] pair : BOXED_TYPES) {
+            if (pair[0] == primitive) {
+                return pair[1];
             }
-            if (method.getParameterTypes().length != typeHints.length) {
-                continue;
+        }
+        return null;
+    }
+
+    private static Class<?> unboxType(Class<?> boxed) {
+        for (Class<?>[] pair : BOXED_TYPES) {
+            if (pair[1] == boxed) {
+                return pair[0];
+            }
+        }
+        return null;
+    }
+
+    // JLS 5.1.2, identity included
+    private static boolean isWideningPrimitive(Class<?> from, Class<?> to) {
+        if (from == to) {
+            return true;
+        }
+        if (from == byte.class) {
+            return to == short.class || to == int.class || to == long.class || to == float.class || to == double.class;
+        }
+        if (from == short.class || from == char.class) {
+            return to == int.class || to == long.class || to == float.class || to == double.class;
+        }
+        if (from == int.class) {
+            return to == long.class || to == float.class || to == double.class;
+        }
+        if (from == long.class) {
+            return to == float.class || to == double.class;
+        }
+        if (from == float.class) {
+            return to == double.class;
+        }
+        return false;
+    }
+
+    // strict invocation context, JLS 5.3: identity and widening conversions only
+    private static boolean isStrictlyConvertible(Class<?> from, Class<?> to) {
+        // a null type hint stands for a null argument, which is assignable to any reference type
+        if (from == null) {
+            return !to.isPrimitive();
+        }
+        if (from.isPrimitive() || to.isPrimitive()) {
+            return from.isPrimitive() && to.isPrimitive() && isWideningPrimitive(from, to);
+        }
+        return to.isAssignableFrom(from);
+    }
+
+    // loose invocation context, JLS 5.3: additionally allows boxing and unboxing
+    private static boolean isLooselyConvertible(Class<?> from, Class<?> to) {
+        if (isStrictlyConvertible(from, to)) {
+            return true;
+        }
+        if (from == null) {
+            return false;
+        }
+        if (from.isPrimitive() && !to.isPrimitive()) {
+            Class<?> boxed = boxType(from);
+            return boxed != null && to.isAssignableFrom(boxed);
+        }
+        if (!from.isPrimitive() && to.isPrimitive()) {
+            Class<?> unboxed = unboxType(from);
+            return unboxed != null && isWideningPrimitive(unboxed, to);
+        }
+        return false;
+    }
+
+    private enum InvocationPhase {
+        STRICT,
+        LOOSE,
+        VARIABLE_ARITY,
+    }
+
+    private static class Candidate {
+        final Member member;
+        final Class<?>[] params;
+        final boolean varArgs;
+        final String signature;
+
+        Candidate(Member member, Class<?>[] params, boolean varArgs, String signature) {
+            this.member = member;
+            this.params = params;
+            this.varArgs = varArgs;
+            this.signature = signature;
+        }
+
+        // the type of the i-th argument, expanding a variable arity parameter when needed
+        Class<?> parameterType(int i, InvocationPhase phase) {
+            if (phase == InvocationPhase.VARIABLE_ARITY && i >= params.length - 1) {
+                return params[params.length - 1].getComponentType();
+            }
+            return params[i];
+        }
+
+        boolean isApplicable(Class<?>[] typeHints, int[] lambdaArities, InvocationPhase phase) {
+            if (phase == InvocationPhase.VARIABLE_ARITY) {
+                if (!varArgs || typeHints.length < params.length - 1) {
+                    return false;
+                }
+            } else if (params.length != typeHints.length) {
+                return false;
             }
 
-            Class<?>[] params = method.getParameterTypes();
             for (int i = 0; i < typeHints.length; i++) {
-                Class<?> typeHint = typeHints[i];
-                if (!params[i].isAssignableFrom(typeHint)) {
-                    continue outer;
+                Class<?> param = parameterType(i, phase);
+                if (lambdaArities[i] >= 0) {
+                    if (!isLambdaCompatible(lambdaArities[i], param)) {
+                        return false;
+                    }
+                    continue;
+                }
+                boolean convertible = phase == InvocationPhase.STRICT
+                        ? isStrictlyConvertible(typeHints[i], param)
+                        : isLooselyConvertible(typeHints[i], param);
+                if (!convertible) {
+                    return false;
                 }
             }
+            return true;
+        }
 
-            return method;
+        // JLS 15.12.2.5
+        boolean isMoreSpecificThan(Candidate other, int arity, InvocationPhase phase) {
+            for (int i = 0; i < arity; i++) {
+                if (!isStrictlyConvertible(parameterType(i, phase), other.parameterType(i, phase))) {
+                    return false;
+                }
+            }
+            return true;
+        }
+
+        JavaMethod toJavaMethod(InvocationPhase phase) {
+            String description = member.getDeclaringClass().getName() + "." + member.getName() + "("
+                    + Arrays.stream(params).map(ReflectionUtil::getTypeName).collect(Collectors.joining(", "))
+                    + ")";
+            return new JavaMethod(member.getName(), signature, description, phase == InvocationPhase.VARIABLE_ARITY);
         }
-        return null;
     }
 
-    public static String findMethodSignature(String className, String methodName, String[] typeHints, MemberType type) {
-        Method m = findMatchingMethod(
-                getAllMethods(parseInternalName(className), type),
-                methodName,
-                Arrays.stream(typeHints).map(ReflectionUtil::parseTypeSignature).toArray(Class[]::new));
-        if (m == null) {
-            return null;
+    private static List<Candidate> getMethodCandidates(List<Method> methods, String name) {
+        List<Candidate> candidates = new ArrayList<>();
+        for (Method method : methods) {
+            if (method.getName().equals(name) && !method.isBridge()) {
+                candidates.add(new Candidate(method, method.getParameterTypes(), method.isVarArgs(), getMethodSignature(method)));
+            }
+        }
+        return candidates;
+    }
+
+    private static List<Candidate> getConstructorCandidates(Class<?> cls) {
+        List<Candidate> candidates = new ArrayList<>();
+        for (Constructor<?> constructor : cls.getDeclaredConstructors()) {
+            candidates.add(new Candidate(constructor, constructor.getParameterTypes(), constructor.isVarArgs(), getConstructorSignature(constructor)));
         }
-        return getMethodSignature(m);
+        return candidates;
     }
 
-    public static String findStaticMethodSignature(String className, String methodName, String[] typeHints) {
-        return findMethodSignature(className, methodName, typeHints, MemberType.STATIC);
+    // JLS 15.12.2: the maximally specific candidates of the first phase that has
+    // any applicable candidate. More than one result means the call is ambiguous.
+    private static JavaMethod[] resolveOverload(List<Candidate> candidates, String[] typeHints) {
+        Class<?>[] hints = parseTypeHints(typeHints);
+        int[] lambdaArities = parseLambdaArities(typeHints);
+        for (InvocationPhase phase : InvocationPhase.values()) {
+            List<Candidate> applicable = candidates.stream()
+                    .filter(candidate -> candidate.isApplicable(hints, lambdaArities, phase))
+                    .collect(Collectors.toList());
+            if (applicable.isEmpty()) {
+                continue;
+            }
+
+            return applicable.stream()
+                    .filter(candidate -> applicable.stream().allMatch(other -> other == candidate
+                            || !other.isMoreSpecificThan(candidate, hints.length, phase)
+                            || candidate.isMoreSpecificThan(other, hints.length, phase)))
+                    .map(candidate -> candidate.toJavaMethod(phase))
+                    .toArray(JavaMethod[]::new);
+        }
+        return new JavaMethod[0];
     }
 
-    public static String findInstanceMethodSignature(Object obj, String methodName, String[] typeHints) {
-        String className = obj.getClass().getName().replace('.', '/');
-        return findMethodSignature(className, methodName, typeHints, MemberType.INSTANCE);
+    public static JavaMethod[] findStaticMethodCandidates(String className, String methodName, String[] typeHints) {
+        List<Method> methods = getAllMethods(parseInternalName(className), MemberType.STATIC);
+        return resolveOverload(getMethodCandidates(methods, methodName), typeHints);
     }
 
+    public static JavaMethod[] findInstanceMethodCandidates(Object obj, String methodName, String[] typeHints) {
+        List<Method> methods = getAllMethods(obj.getClass(), MemberType.INSTANCE);
+        return resolveOverload(getMethodCandidates(methods, methodName), typeHints);
+    }
+
+    public static boolean hasStaticMethod(String className, String methodName) {
+        return getAllMethods(parseInternalName(className), MemberType.STATIC).stream()
+                .anyMatch(method -> method.getName().equals(methodName));
+    }
+
+    public static boolean hasInstanceMethod(Object obj, String methodName) {
+        return getAllMethods(obj.getClass(), MemberType.INSTANCE).stream()
+                .anyMatch(method -> method.getName().equals(methodName));
+    }
+
+    public static JavaMethod[] findConstructorCandidates(String className, String[] typeHints) {
+        return resolveOverload(getConstructorCandidates(parseInternalName(className)), typeHints);
+    }
+
+    // the number of parameters of each overload, negated for those of variable arity,
+    // so that scripts can be checked before they run. "<init>" stands for the constructors
+    public static int[] getParameterCounts(String className, String name, boolean isStatic) {
+        Class<?> cls = parseInternalName(className);
+        List<Candidate> candidates = name.equals("<init>")
+                ? getConstructorCandidates(cls)
+                : getMethodCandidates(getAllMethods(cls, isStatic ? MemberType.STATIC : MemberType.INSTANCE), name);
+        return candidates.stream()
+                .mapToInt(candidate -> candidate.varArgs ? -candidate.params.length : candidate.params.length)
+                .toArray();
+    }
+
+    // constants of interfaces are inherited like the static fields of superclasses
     private static List<Field> getAllFields(Class<?> cls, MemberType type) {
-        List<Field> fields = Arrays.stream(cls.getDeclaredFields()).filter(type::filter).collect(Collectors.toList());
-        if (cls.getSuperclass() != null) {
-            fields.addAll(getAllFields(cls.getSuperclass(), type));
-        }
-        return fields;
+        return getHierarchy(cls).stream()
+                .flatMap(declaringClass -> Arrays.stream(declaringClass.getDeclaredFields()))
+                .filter(type::filter)
+                .collect(Collectors.toList());
     }
 
     private static Field findMatchingField(List<Field> fields, String name) {
@@ -202,6 +454,11 @@ public class ReflectionUtil {
         return findFieldSignature(instance.getClass(), fieldName, MemberType.INSTANCE);
     }
 
+    public static boolean hasField(String className, String fieldName, boolean isStatic) {
+        MemberType type = isStatic ? MemberType.STATIC : MemberType.INSTANCE;
+        return findMatchingField(getAllFields(parseInternalName(className), type), fieldName) != null;
+    }
+
     public static JavaField[] findStaticFields(String className) {
         return getAllFields(parseInternalName(className), MemberType.STATIC)
                 .stream()
@@ -222,15 +479,36 @@ public class ReflectionUtil {
                 .toArray(JavaField[]::new);
     }
 
-    public static boolean doesClassExist(String name) {
-        try {
-            Class.forName(name);
-            return true;
-        } catch (ReflectiveOperationException e) {
-            return false;
+    // resolves a class name as written in source to its binary name, reading trailing
+    // segments as nested classes when needed, e.g. java.util.Map.Entry to java.util.Map$Entry
+    public static String resolveClassName(String name) {
+        String candidate = name;
+        while (true) {
+            try {
+                return Class.forName(candidate).getName();
+            } catch (ClassNotFoundException | NoClassDefFoundError e) {
+                int dot = candidate.lastIndexOf('.');
+                if (dot == -1) {
+                    return null;
+                }
+                candidate = candidate.substring(0, dot) + '$' + candidate.substring(dot + 1);
+            }
         }
     }
 
+    // the throwable followed by its causes, stopping at the first repeated cause
+    public static JavaThrowable[] getThrowableChain(Throwable throwable) {
+        List<JavaThrowable> chain = new ArrayList<>();
+        Set<Throwable> seen = Collections.newSetFromMap(new IdentityHashMap<>());
+        for (Throwable t = throwable; t != null && seen.add(t); t = t.getCause()) {
+            String[] stackTrace = Arrays.stream(t.getStackTrace())
+                    .map(StackTraceElement::toString)
+                    .toArray(String[]::new);
+            chain.add(new JavaThrowable(t.getClass().getName(), t.getMessage(), stackTrace));
+        }
+        return chain.toArray(new JavaThrowable[0]);
+    }
+
     public static boolean isListType(Object value) {
         Class<?> type = value.getClass();
         if (type.isArray()) {
@@ -289,6 +567,150 @@ public class ReflectionUtil {
         return arr;
     }
 
+    // the members of a Class passed to an intrinsic are its static members
+    private static Class<?> getDescribedClass(Object target) {
+        return target instanceof Class ? (Class<?>) target : target.getClass();
+    }
+
+    private static MemberType getDescribedMembers(Object target) {
+        return target instanceof Class ? MemberType.STATIC : MemberType.INSTANCE;
+    }
+
+    // the string form of a value for SLAT's str(), which spells out the elements of arrays
+    public static String toDisplayString(Object value) {
+        if (value != null && value.getClass().isArray()) {
+            String wrapped = Arrays.deepToString(new Object[] { value });
+            return wrapped.substring(1, wrapped.length() - 1);
+        }
+        return String.valueOf(value);
+    }
+
+    public static String[] describeFields(Object target) {
+        Object owner = target instanceof Class ? null : target;
+        return getAllFields(getDescribedClass(target), getDescribedMembers(target))
+                .stream()
+                .map(field -> {
+                    String value;
+                    try {
+                        field.setAccessible(true);
+                        value = toDisplayString(field.get(owner));
+                    } catch (IllegalAccessException | RuntimeException e) {
+                        value = "<inaccessible>";
+                    }
+                    return getTypeName(field.getType()) + " " + field.getName() + " = " + value;
+                })
+                .toArray(String[]::new);
+    }
+
+    public static String[] describeMethods(Object target) {
+        return getAllMethods(getDescribedClass(target), getDescribedMembers(target))
+                .stream()
+                .filter(method -> !method.isBridge())
+                .map(ReflectionUtil::describeMethod)
+                .toArray(String[]::new);
+    }
+
+    private static String describeMethod(Method method) {
+        return getTypeName(method.getReturnType()) + " " + method.getName() + "("
+                + Arrays.stream(method.getParameterTypes()).map(ReflectionUtil::getTypeName).collect(Collectors.joining(", "))
+                + ")";
+    }
+
+    private static String describeDeclaration(int modifiers, Class<?> declaringClass) {
+        String declaredIn = "declared in " + getTypeName(declaringClass);
+        return modifiers == 0 ? declaredIn : Modifier.toString(modifiers) + ", " + declaredIn;
+    }
+
+    // the fields, methods and nested classes that can be accessed on an instance of
+    // the class, or on the class itself
+    public static JavaMember[] getMembers(Class<?> cls, boolean isStatic) {
+        MemberType type = isStatic ? MemberType.STATIC : MemberType.INSTANCE;
+        List<JavaMember> members = new ArrayList<>();
+        Set<String> seen = new HashSet<>();
+        for (Field field : getAllFields(cls, type)) {
+            // fields hidden by a field of a subclass can't be accessed by name
+            if (field.isSynthetic() || !seen.add(field.getName())) {
+                continue;
+            }
+            members.add(new JavaMember(field.getName(), JavaMember.FIELD,
+                    getTypeName(field.getType()) + " " + field.getName(),
+                    describeDeclaration(field.getModifiers(), field.getDeclaringClass())));
+        }
+        for (Method method : getAllMethods(cls, type)) {
+            if (method.isBridge() || method.isSynthetic()
+                    || !seen.add(method.getName() + getParametersSignature(method.getParameterTypes()))) {
+                continue;
+            }
+            members.add(new JavaMember(method.getName(), JavaMember.METHOD, describeMethod(method),
+                    describeDeclaration(method.getModifiers(), method.getDeclaringClass())));
+        }
+        if (isStatic) {
+            for (Class<?> nested : cls.getClasses()) {
+                members.add(new JavaMember(nested.getSimpleName(), JavaMember.CLASS, getTypeName(nested),
+                        describeDeclaration(nested.getModifiers(), nested.getDeclaringClass())));
+            }
+        }
+        return members.toArray(new JavaMember[0]);
+    }
+
+    // the declared type of a field, or the return type of a method. Without the arguments
+    // the overload is unknown, so overloads must return the same type or subtypes of one of them
+    public static Class<?> getMemberType(Class<?> cls, String name, boolean isMethod, boolean isStatic) {
+        MemberType type = isStatic ? MemberType.STATIC : MemberType.INSTANCE;
+        if (isMethod) {
+            List<Class<?>> returnTypes = getAllMethods(cls, type).stream()
+                    .filter(method -> method.getName().equals(name) && !method.isBridge())
+                    .map(Method::getReturnType)
+                    .distinct()
+                    .collect(Collectors.toList());
+            return returnTypes.stream()
+                    .filter(returnType -> returnTypes.stream().allMatch(returnType::isAssignableFrom))
+                    .findFirst()
+                    .orElse(null);
+        }
+        Field field = findMatchingField(getAllFields(cls, type), name);
+        return field == null ? null : field.getType();
+    }
+
+    // the superclasses of a class, most derived first, followed by every interface it implements
+    public static String[] getSupertypes(Object target) {
+        List<String> supertypes = new ArrayList<>();
+        List<Class<?>> interfaces = new ArrayList<>();
+        for (Class<?> cls = getDescribedClass(target); cls != null; cls = cls.getSuperclass()) {
+            if (cls != getDescribedClass(target)) {
+                supertypes.add(cls.getName());
+            }
+            interfaces.addAll(Arrays.asList(cls.getInterfaces()));
+        }
+        for (int i = 0; i < interfaces.size(); i++) {
+            Class<?> iface = interfaces.get(i);
+            if (!supertypes.contains(iface.getName())) {
+                supertypes.add(iface.getName());
+                interfaces.addAll(Arrays.asList(iface.getInterfaces()));
+            }
+        }
+        return supertypes.toArray(new String[0]);
+    }
+
+    public static String describe(Object target) {
+        Class<?> cls = getDescribedClass(target);
+        StringBuilder sb = new StringBuilder(getTypeName(cls));
+        if (!(target instanceof Class)) {
+            sb.append('@').append(Integer.toHexString(System.identityHashCode(target)));
+        }
+        sb.append('\n');
+        for (String supertype : getSupertypes(target)) {
+            sb.append("  super ").append(supertype).append('\n');
+        }
+        for (String field : describeFields(target)) {
+            sb.append("  field ").append(field).append('\n');
+        }
+        for (String method : describeMethods(target)) {
+            sb.append("  method ").append(method).append('\n');
+        }
+        return sb.toString();
+    }
+
     private enum MemberType {
         STATIC,
         INSTANCE;
@@ -0,0 +1,87 @@
+package appstrument.server;
+
+import java.lang.reflect.InvocationHandler;
+import java.lang.reflect.Method;
+import java.lang.reflect.Modifier;
+import java.lang.reflect.Proxy;
+import java.util.Arrays;
+
+// backs a SLAT lambda, which is evaluated by the session that created it
+public class SlatInvocationHandler implements InvocationHandler {
+    private final AppstrumentNative session;
+    private final int lambdaId;
+    private final Class<?> functionalInterface;
+
+    private SlatInvocationHandler(AppstrumentNative session, int lambdaId, Class<?> functionalInterface) {
+        this.session = session;
+        this.lambdaId = lambdaId;
+        this.functionalInterface = functionalInterface;
+    }
+
+    private static boolean isObjectMethod(Method method) {
+        try {
+            Object.class.getMethod(method.getName(), method.getParameterTypes());
+            return true;
+        } catch (NoSuchMethodException e) {
+            return false;
+        }
+    }
+
+    // the single abstract method of a functional interface, JLS 9.8, or null if the type isn't one
+    static Method getFunctionalMethod(Class<?> type) {
+        if (!type.isInterface()) {
+            return null;
+        }
+        Method functional = null;
+        for (Method method : type.getMethods()) {
+            if (!Modifier.isAbstract(method.getModifiers()) || isObjectMethod(method)) {
+                continue;
+            }
+            // the same method may be inherited from several superinterfaces
+            if (functional != null && !(functional.getName().equals(method.getName())
+                    && Arrays.equals(functional.getParameterTypes(), method.getParameterTypes()))) {
+                return null;
+            }
+            functional = method;
+        }
+        return functional;
+    }
+
+    // the number of parameters of a functional interface's method, or -1 if the type isn't one
+    public static int getFunctionalArity(String className) {
+        try {
+            Method method = getFunctionalMethod(Class.forName(className));
+            return method == null ? -1 : method.getParameterTypes().length;
+        } catch (ClassNotFoundException e) {
+            return -1;
+        }
+    }
+
+    public static Object createProxy(String className, AppstrumentNative session, int lambdaId) throws ClassNotFoundException {
+        Class<?> functionalInterface = Class.forName(className);
+        Object proxy = Proxy.newProxyInstance(
+                functionalInterface.getClassLoader(),
+                new Class<?>[] { functionalInterface },
+                new SlatInvocationHandler(session, lambdaId, functionalInterface));
+        session.trackLambda(proxy, lambdaId);
+        return proxy;
+    }
+
+    @Override
+    public Object invoke(Object proxy, Method method, Object[] args) {
+        if (method.getDeclaringClass() == Object.class) {
+            switch (method.getName()) {
+                case "equals":
+                    return proxy == args[0];
+                case "hashCode":
+                    return System.identityHashCode(proxy);
+                default:
+                    return "SLAT lambda implementing " + functionalInterface.getName();
+            }
+        }
+        if (method.isDefault()) {
+            throw new UnsupportedOperationException("SLAT lambdas cannot invoke the default method " + method.getName());
+        }
+        return session.invokeLambda(lambdaId, args == null ? new Object[0] : args, ReflectionUtil.getTypeSignature(method.getReturnType()));
+    }
+}
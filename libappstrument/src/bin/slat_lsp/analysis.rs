@@ -0,0 +1,411 @@
+use appstrument::slat::{
+    ast::{self, Token, TokenKind},
+    diagnostic::Diagnostic,
+    parser::{self, Rule, SlatParser},
+};
+use lsp_types::{
+    DiagnosticSeverity, DocumentSymbol, Range, SemanticToken, SemanticTokenType, SymbolKind,
+};
+use pest::{iterators::Pair, Parser};
+use std::collections::HashSet;
+
+pub const TOKEN_TYPES: &[SemanticTokenType] = &[
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::PARAMETER,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::METHOD,
+    SemanticTokenType::PROPERTY,
+    SemanticTokenType::TYPE,
+    SemanticTokenType::NAMESPACE,
+    SemanticTokenType::STRING,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::OPERATOR,
+    SemanticTokenType::MACRO,
+    SemanticTokenType::COMMENT,
+];
+
+fn token_type(token_type: SemanticTokenType) -> u32 {
+    TOKEN_TYPES
+        .iter()
+        .position(|candidate| *candidate == token_type)
+        .expect("token type missing from the legend") as u32
+}
+
+/// Converts a SLAT position to an LSP one, whose columns count UTF-16 code units.
+pub fn lsp_position(text: &str, position: ast::Position) -> lsp_types::Position {
+    let line = text
+        .lines()
+        .nth(position.line.saturating_sub(1))
+        .unwrap_or("");
+    let character = line
+        .chars()
+        .take(position.column.saturating_sub(1))
+        .map(char::len_utf16)
+        .sum::<usize>();
+    lsp_types::Position::new(position.line.saturating_sub(1) as u32, character as u32)
+}
+
+pub fn lsp_range(text: &str, span: ast::Span) -> Range {
+    Range::new(lsp_position(text, span.start), lsp_position(text, span.end))
+}
+
+/// The number of characters before an LSP position, which is how the Appstrument
+/// protocol counts cursors.
+pub fn char_offset(text: &str, position: lsp_types::Position) -> usize {
+    let mut offset = 0;
+    for (index, line) in text.split('\n').enumerate() {
+        if index == position.line as usize {
+            let mut units = 0;
+            for c in line.chars() {
+                if units >= position.character as usize {
+                    break;
+                }
+                units += c.len_utf16();
+                offset += 1;
+            }
+            return offset;
+        }
+        offset += line.chars().count() + 1;
+    }
+    offset
+}
+
+/// The LSP position of the character at `offset`.
+pub fn position_at(text: &str, offset: usize) -> lsp_types::Position {
+    let mut line = 0;
+    let mut character = 0;
+    for c in text.chars().take(offset) {
+        if c == '\n' {
+            line += 1;
+            character = 0;
+        } else {
+            character += c.len_utf16();
+        }
+    }
+    lsp_types::Position::new(line, character as u32)
+}
+
+pub fn diagnostics(text: &str) -> Vec<lsp_types::Diagnostic> {
+    let error = match parser::parse(text) {
+        Ok(_) => return Vec::new(),
+        Err(error) => error,
+    };
+    let diagnostic = match Diagnostic::from_error(&error) {
+        Some(diagnostic) => diagnostic,
+        None => return Vec::new(),
+    };
+    vec![lsp_types::Diagnostic {
+        range: lsp_range(text, diagnostic.span),
+        severity: Some(DiagnosticSeverity::ERROR),
+        source: Some("slat".to_owned()),
+        message: diagnostic.message,
+        ..Default::default()
+    }]
+}
+
+/// The longest run of whole lines at the start of `text` that parses, so that a
+/// script being edited keeps its highlighting and outline up to the broken line.
+fn parseable_prefix(text: &str) -> &str {
+    let mut prefix = text;
+    while !prefix.trim().is_empty() {
+        if SlatParser::parse(Rule::program, prefix).is_ok() {
+            return prefix;
+        }
+        prefix = match prefix.trim_end_matches(['\r', '\n']).rfind('\n') {
+            Some(end) => &prefix[..end + 1],
+            None => "",
+        };
+    }
+    ""
+}
+
+#[allow(deprecated)]
+fn symbol(
+    text: &str,
+    name: &str,
+    detail: Option<String>,
+    kind: SymbolKind,
+    span: ast::Span,
+    selection: ast::Span,
+) -> DocumentSymbol {
+    DocumentSymbol {
+        name: name.to_owned(),
+        detail,
+        kind,
+        tags: None,
+        deprecated: None,
+        range: lsp_range(text, span),
+        selection_range: lsp_range(text, selection),
+        children: None,
+    }
+}
+
+/// Functions, imports and the first assignment of each variable, nested the way
+/// scopes are: blocks of statements share the scope they are in.
+fn collect_symbols(
+    text: &str,
+    tokens: &[Token],
+    variables: &mut HashSet<String>,
+    symbols: &mut Vec<DocumentSymbol>,
+) {
+    for token in tokens {
+        match &token.kind {
+            TokenKind::Function(function) => {
+                let mut children = Vec::new();
+                let mut locals = function.parameters.iter().cloned().collect();
+                collect_symbols(text, &function.body, &mut locals, &mut children);
+                let mut symbol = symbol(
+                    text,
+                    &function.name,
+                    Some(format!(
+                        "fn {}({})",
+                        function.name,
+                        function.parameters.join(", ")
+                    )),
+                    SymbolKind::FUNCTION,
+                    token.span,
+                    token.span,
+                );
+                symbol.children = Some(children);
+                symbols.push(symbol);
+            }
+            TokenKind::Import(import) if !import.wildcard => {
+                let name = import
+                    .alias
+                    .as_ref()
+                    .or_else(|| import.path.last())
+                    .expect("unreachable");
+                symbols.push(symbol(
+                    text,
+                    name,
+                    Some(import.path.join(".")),
+                    SymbolKind::CLASS,
+                    token.span,
+                    token.span,
+                ));
+            }
+            TokenKind::Assignment(assignment) => {
+                if let TokenKind::Identifier(name) = &assignment.target.kind {
+                    if variables.insert(name.clone()) {
+                        symbols.push(symbol(
+                            text,
+                            name,
+                            None,
+                            SymbolKind::VARIABLE,
+                            token.span,
+                            assignment.target.span,
+                        ));
+                    }
+                }
+            }
+            TokenKind::If(statement) => {
+                collect_symbols(text, &statement.body, variables, symbols);
+                if let Some(else_body) = &statement.else_body {
+                    collect_symbols(text, else_body, variables, symbols);
+                }
+            }
+            TokenKind::While(statement) => {
+                collect_symbols(text, &statement.body, variables, symbols)
+            }
+            TokenKind::ForEach(statement) => {
+                collect_symbols(text, &statement.body, variables, symbols)
+            }
+            TokenKind::Try(statement) => {
+                collect_symbols(text, &statement.body, variables, symbols);
+                for catch_clause in &statement.catch_clauses {
+                    collect_symbols(text, &catch_clause.body, variables, symbols);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn document_symbols(text: &str) -> Vec<DocumentSymbol> {
+    let tokens = match parser::parse(parseable_prefix(text)) {
+        Ok(tokens) => tokens,
+        Err(_) => return Vec::new(),
+    };
+    let mut symbols = Vec::new();
+    collect_symbols(text, &tokens, &mut HashSet::new(), &mut symbols);
+    symbols
+}
+
+/// A highlighted range of a single line, in LSP positions.
+struct Highlight {
+    start: lsp_types::Position,
+    length: u32,
+    token_type: SemanticTokenType,
+}
+
+fn highlight(text: &str, start: usize, end: usize, token_type: SemanticTokenType) -> Highlight {
+    Highlight {
+        start: position_at(text, text[..start].chars().count()),
+        length: text[start..end].chars().map(char::len_utf16).sum::<usize>() as u32,
+        token_type,
+    }
+}
+
+/// What an identifier names, judging by the rule that matched it and that rule's
+/// parent. Qualified class names only highlight their last segment as a type.
+fn ident_type(rule: Rule, parent: Rule, index: usize, is_last: bool) -> SemanticTokenType {
+    match (rule, parent) {
+        (Rule::function, _) if index == 0 => SemanticTokenType::FUNCTION,
+        (Rule::method_call, Rule::new) if index == 0 => SemanticTokenType::TYPE,
+        (Rule::method_call, _) if index == 0 => SemanticTokenType::METHOD,
+        (Rule::field_access, _) => SemanticTokenType::PROPERTY,
+        (Rule::parameters | Rule::lambda, _) => SemanticTokenType::PARAMETER,
+        (Rule::alias, _) => SemanticTokenType::TYPE,
+        (Rule::new, _) => SemanticTokenType::NAMESPACE,
+        (Rule::type_name | Rule::import, _) if is_last => SemanticTokenType::TYPE,
+        (Rule::type_name | Rule::import, _) => SemanticTokenType::NAMESPACE,
+        _ => SemanticTokenType::VARIABLE,
+    }
+}
+
+/// Highlights the keywords in the text a rule matched between its inner pairs,
+/// such as the `else` of an if statement, and the comments there.
+fn highlight_keywords(text: &str, start: usize, end: usize, highlights: &mut Vec<Highlight>) {
+    let gap = &text[start..end];
+    let mut word_start = None;
+    let mut comment_end = 0;
+    for (index, c) in gap.char_indices().chain([(gap.len(), ' ')]) {
+        if index < comment_end {
+            continue;
+        }
+        match (word_start, c.is_ascii_alphabetic()) {
+            (None, true) => word_start = Some(index),
+            (Some(word), false) => {
+                highlights.push(highlight(
+                    text,
+                    start + word,
+                    start + index,
+                    SemanticTokenType::KEYWORD,
+                ));
+                word_start = None;
+            }
+            _ => {}
+        }
+
+        let rest = &gap[index..];
+        comment_end = index
+            + if rest.starts_with("//") {
+                rest.find('\n').unwrap_or(rest.len())
+            } else if rest.starts_with("/*") {
+                rest.find("*/").map_or(rest.len(), |end| end + 2)
+            } else {
+                continue;
+            };
+        // a block comment is highlighted line by line, highlights can't span lines
+        let mut line_start = start + index;
+        for line in text[line_start..start + comment_end].split('\n') {
+            let line_end = line_start + line.trim_end_matches('\r').len();
+            highlights.push(highlight(
+                text,
+                line_start,
+                line_end,
+                SemanticTokenType::COMMENT,
+            ));
+            line_start += line.len() + 1;
+        }
+    }
+}
+
+fn collect_highlights(text: &str, pair: Pair<Rule>, parent: Rule, highlights: &mut Vec<Highlight>) {
+    let span = pair.as_span();
+    let token_type = match pair.as_rule() {
+        Rule::string | Rule::character => Some(SemanticTokenType::STRING),
+        Rule::integer | Rule::decimal => Some(SemanticTokenType::NUMBER),
+        Rule::boolean
+        | Rule::null
+        | Rule::return_keyword
+        | Rule::throw_keyword
+        | Rule::del_keyword
+        | Rule::vars_statement => Some(SemanticTokenType::KEYWORD),
+        Rule::directive_name => Some(SemanticTokenType::MACRO),
+        Rule::object_handle => Some(SemanticTokenType::VARIABLE),
+        Rule::add
+        | Rule::subtract
+        | Rule::multiply
+        | Rule::divide
+        | Rule::remainder
+        | Rule::equal
+        | Rule::not_equal
+        | Rule::less_equal
+        | Rule::less
+        | Rule::greater_equal
+        | Rule::greater
+        | Rule::and
+        | Rule::or
+        | Rule::negate
+        | Rule::not => Some(SemanticTokenType::OPERATOR),
+        _ => None,
+    };
+    if let Some(token_type) = token_type {
+        highlights.push(highlight(text, span.start(), span.end(), token_type));
+        return;
+    }
+
+    let rule = pair.as_rule();
+    let inner: Vec<Pair<Rule>> = pair.into_inner().collect();
+    let ident_count = inner
+        .iter()
+        .filter(|pair| pair.as_rule() == Rule::ident)
+        .count();
+    let mut position = span.start();
+    let mut ident_index = 0;
+    for pair in inner {
+        highlight_keywords(text, position, pair.as_span().start(), highlights);
+        position = pair.as_span().end();
+        if pair.as_rule() == Rule::ident {
+            let is_last = ident_index + 1 == ident_count;
+            let token_type = ident_type(rule, parent, ident_index, is_last);
+            highlights.push(highlight(
+                text,
+                pair.as_span().start(),
+                position,
+                token_type,
+            ));
+            ident_index += 1;
+        } else {
+            collect_highlights(text, pair, rule, highlights);
+        }
+    }
+    highlight_keywords(text, position, span.end(), highlights);
+}
+
+/// Semantic tokens for the part of the document that parses, encoded relative to
+/// each other as LSP expects.
+pub fn semantic_tokens(text: &str) -> Vec<SemanticToken> {
+    let prefix = parseable_prefix(text);
+    let mut highlights = Vec::new();
+    if let Ok(pairs) = SlatParser::parse(Rule::program, prefix) {
+        for pair in pairs {
+            collect_highlights(text, pair, Rule::program, &mut highlights);
+        }
+    }
+
+    let mut tokens = Vec::with_capacity(highlights.len());
+    let mut previous = lsp_types::Position::new(0, 0);
+    for highlight in highlights {
+        if highlight.length == 0 {
+            continue;
+        }
+        let delta_line = highlight.start.line - previous.line;
+        let delta_start = if delta_line == 0 {
+            highlight.start.character - previous.character
+        } else {
+            highlight.start.character
+        };
+        tokens.push(SemanticToken {
+            delta_line,
+            delta_start,
+            length: highlight.length,
+            token_type: token_type(highlight.token_type),
+            token_modifiers_bitset: 0,
+        });
+        previous = highlight.start;
+    }
+    tokens
+}
@@ -0,0 +1,91 @@
+use anyhow::anyhow;
+use appstrument::proto::{
+    appstrument_request, appstrument_response, AppstrumentRequest, AppstrumentResponse,
+    CompleteSlatRequest, CompleteSlatResponse,
+};
+use flate2::read::GzDecoder;
+use prost::Message as _;
+use std::{io::Read, net::TcpStream, time::Duration};
+use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
+
+/// How long to wait for the device before giving up on a request, an editor
+/// waiting on completions blocks typing.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A connection to the Appstrument server of a device, such as `ws://localhost:8000`
+/// when its port is forwarded with adb. It is a session of its own, so variables
+/// defined in the Flutter console aren't visible to it.
+pub struct Device {
+    address: String,
+    socket: Option<WebSocket<MaybeTlsStream<TcpStream>>>,
+    next_id: i32,
+}
+
+impl Device {
+    pub fn new(address: String) -> Device {
+        Device {
+            address,
+            socket: None,
+            next_id: 0,
+        }
+    }
+
+    fn connect(&mut self) -> anyhow::Result<&mut WebSocket<MaybeTlsStream<TcpStream>>> {
+        if self.socket.is_none() {
+            let (socket, _) = tungstenite::connect(self.address.as_str())?;
+            if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+                stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+            }
+            self.socket = Some(socket);
+        }
+        Ok(self.socket.as_mut().expect("unreachable"))
+    }
+
+    fn send(
+        &mut self,
+        body: appstrument_request::Body,
+    ) -> anyhow::Result<appstrument_response::Body> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let request = AppstrumentRequest {
+            id,
+            body: Some(body),
+        };
+        let socket = self.connect()?;
+        socket.send(Message::Binary(request.encode_to_vec()))?;
+        loop {
+            let data = match socket.read()? {
+                Message::Binary(data) => data,
+                Message::Close(_) => return Err(anyhow!("The device closed the connection")),
+                _ => continue,
+            };
+            // responses are gzipped by the server
+            let mut decompressed = Vec::new();
+            GzDecoder::new(data.as_slice()).read_to_end(&mut decompressed)?;
+            let response = AppstrumentResponse::decode(decompressed.as_slice())?;
+            // streamed responses such as logcat lines are for other requests
+            if response.id == id {
+                return response
+                    .body
+                    .ok_or_else(|| anyhow!("The device could not handle the request"));
+            }
+        }
+    }
+
+    /// Completes the word at `cursor`, counted in characters. A broken connection
+    /// is dropped so the next request reconnects.
+    pub fn complete(&mut self, code: &str, cursor: usize) -> anyhow::Result<CompleteSlatResponse> {
+        let request = appstrument_request::Body::CompleteSlat(CompleteSlatRequest {
+            code: code.to_owned(),
+            cursor: cursor as i32,
+        });
+        match self.send(request) {
+            Ok(appstrument_response::Body::CompleteSlat(response)) => Ok(response),
+            Ok(_) => Err(anyhow!("The device answered with another response")),
+            Err(error) => {
+                self.socket = None;
+                Err(error)
+            }
+        }
+    }
+}
@@ -0,0 +1,318 @@
+//! A language server for SLAT scripts. Syntax diagnostics, the outline and
+//! highlighting come from the SLAT parser; completion and hover are answered by
+//! a device when one is given with `--device ws://host:port`.
+
+mod analysis;
+mod device;
+
+use anyhow::anyhow;
+use appstrument::proto::slat_completion;
+use device::Device;
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+        PublishDiagnostics,
+    },
+    request::{
+        Completion, DocumentSymbolRequest, HoverRequest, Request as _, SemanticTokensFullRequest,
+    },
+    CompletionItem, CompletionItemKind, CompletionList, CompletionOptions, CompletionResponse,
+    DocumentSymbolResponse, Documentation, Hover, HoverContents, HoverProviderCapability,
+    MarkupContent, MarkupKind, OneOf, PublishDiagnosticsParams, Range, SemanticTokens,
+    SemanticTokensFullOptions, SemanticTokensLegend, SemanticTokensOptions, SemanticTokensResult,
+    SemanticTokensServerCapabilities, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextEdit, Url,
+};
+use std::collections::HashMap;
+
+struct Server {
+    connection: Connection,
+    documents: HashMap<Url, String>,
+    device: Option<Device>,
+}
+
+fn capabilities(has_device: bool) -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend: SemanticTokensLegend {
+                    token_types: analysis::TOKEN_TYPES.to_vec(),
+                    token_modifiers: Vec::new(),
+                },
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                ..Default::default()
+            },
+        )),
+        completion_provider: has_device.then(|| CompletionOptions {
+            trigger_characters: Some(vec![".".to_owned()]),
+            ..Default::default()
+        }),
+        hover_provider: has_device.then_some(HoverProviderCapability::Simple(true)),
+        ..Default::default()
+    }
+}
+
+fn completion_kind(kind: i32) -> CompletionItemKind {
+    match slat_completion::Kind::from_i32(kind) {
+        Some(slat_completion::Kind::Variable) => CompletionItemKind::VARIABLE,
+        Some(slat_completion::Kind::Field) => CompletionItemKind::FIELD,
+        Some(slat_completion::Kind::Method) => CompletionItemKind::METHOD,
+        Some(slat_completion::Kind::Function) => CompletionItemKind::FUNCTION,
+        Some(slat_completion::Kind::Class) => CompletionItemKind::CLASS,
+        Some(slat_completion::Kind::Package) => CompletionItemKind::MODULE,
+        Some(slat_completion::Kind::Keyword) | None => CompletionItemKind::KEYWORD,
+    }
+}
+
+/// The identifier around `offset`, as character offsets.
+fn word_at(text: &str, offset: usize) -> Option<(usize, usize)> {
+    let chars: Vec<char> = text.chars().collect();
+    let offset = offset.min(chars.len());
+    let is_word = |c: &char| c.is_ascii_alphanumeric() || *c == '_' || *c == '$';
+    let start = offset
+        - chars[..offset]
+            .iter()
+            .rev()
+            .take_while(|c| is_word(c))
+            .count();
+    let end = offset + chars[offset..].iter().take_while(|c| is_word(c)).count();
+    (start < end).then_some((start, end))
+}
+
+impl Server {
+    fn document(&self, uri: &Url) -> anyhow::Result<&str> {
+        self.documents
+            .get(uri)
+            .map(String::as_str)
+            .ok_or_else(|| anyhow!("{} is not open", uri))
+    }
+
+    fn device(&mut self) -> anyhow::Result<&mut Device> {
+        self.device
+            .as_mut()
+            .ok_or_else(|| anyhow!("No device is configured"))
+    }
+
+    fn publish_diagnostics(&self, uri: Url, text: &str) -> anyhow::Result<()> {
+        let params = PublishDiagnosticsParams {
+            uri,
+            diagnostics: analysis::diagnostics(text),
+            version: None,
+        };
+        let notification = Notification::new(PublishDiagnostics::METHOD.to_owned(), params);
+        self.connection
+            .sender
+            .send(Message::Notification(notification))?;
+        Ok(())
+    }
+
+    fn complete(
+        &mut self,
+        params: lsp_types::CompletionParams,
+    ) -> anyhow::Result<CompletionResponse> {
+        let position = params.text_document_position;
+        let text = self.document(&position.text_document.uri)?.to_owned();
+        let cursor = analysis::char_offset(&text, position.position);
+        let completions = self.device()?.complete(&text, cursor)?;
+
+        let range = Range::new(
+            analysis::position_at(&text, completions.replace_start as usize),
+            analysis::position_at(&text, completions.replace_end as usize),
+        );
+        let items = completions
+            .candidates
+            .into_iter()
+            .enumerate()
+            .map(|(index, candidate)| CompletionItem {
+                kind: Some(completion_kind(candidate.kind)),
+                detail: Some(candidate.signature).filter(|signature| !signature.is_empty()),
+                documentation: Some(Documentation::String(candidate.documentation)),
+                // keep the device's ranking
+                sort_text: Some(format!("{:04}", index)),
+                filter_text: Some(candidate.label.clone()),
+                text_edit: Some(lsp_types::CompletionTextEdit::Edit(TextEdit::new(
+                    range,
+                    candidate.insert_text,
+                ))),
+                label: candidate.label,
+                ..Default::default()
+            })
+            .collect();
+        // the device only sends the best candidates for the typed prefix
+        Ok(CompletionResponse::List(CompletionList {
+            is_incomplete: true,
+            items,
+        }))
+    }
+
+    /// Describes the identifier under the cursor with the completions the device
+    /// offers for it, every overload of a method included.
+    fn hover(&mut self, params: lsp_types::HoverParams) -> anyhow::Result<Option<Hover>> {
+        let position = params.text_document_position_params;
+        let text = self.document(&position.text_document.uri)?.to_owned();
+        let offset = analysis::char_offset(&text, position.position);
+        let (start, end) = match word_at(&text, offset) {
+            Some(word) => word,
+            None => return Ok(None),
+        };
+        let word: String = text.chars().skip(start).take(end - start).collect();
+        let completions = self.device()?.complete(&text, end)?;
+
+        let descriptions: Vec<String> = completions
+            .candidates
+            .into_iter()
+            .filter(|candidate| candidate.label == word)
+            .map(|candidate| {
+                format!(
+                    "```\n{}\n```\n{}",
+                    candidate.signature, candidate.documentation
+                )
+            })
+            .collect();
+        if descriptions.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: descriptions.join("\n\n---\n\n"),
+            }),
+            range: Some(Range::new(
+                analysis::position_at(&text, start),
+                analysis::position_at(&text, end),
+            )),
+        }))
+    }
+
+    fn handle_request(&mut self, request: Request) -> anyhow::Result<()> {
+        let (id, result) = match request.method.as_str() {
+            DocumentSymbolRequest::METHOD => {
+                let (id, params) = request
+                    .extract::<lsp_types::DocumentSymbolParams>(DocumentSymbolRequest::METHOD)?;
+                let text = self.document(&params.text_document.uri)?;
+                let symbols = DocumentSymbolResponse::Nested(analysis::document_symbols(text));
+                (id, Ok(serde_json::to_value(symbols)?))
+            }
+            SemanticTokensFullRequest::METHOD => {
+                let (id, params) = request.extract::<lsp_types::SemanticTokensParams>(
+                    SemanticTokensFullRequest::METHOD,
+                )?;
+                let text = self.document(&params.text_document.uri)?;
+                let tokens = SemanticTokensResult::Tokens(SemanticTokens {
+                    result_id: None,
+                    data: analysis::semantic_tokens(text),
+                });
+                (id, Ok(serde_json::to_value(tokens)?))
+            }
+            Completion::METHOD => {
+                let (id, params) = request.extract(Completion::METHOD)?;
+                let result = self.complete(params).map(serde_json::to_value);
+                (id, result.and_then(|value| Ok(value?)))
+            }
+            HoverRequest::METHOD => {
+                let (id, params) = request.extract(HoverRequest::METHOD)?;
+                let result = self.hover(params).map(serde_json::to_value);
+                (id, result.and_then(|value| Ok(value?)))
+            }
+            _ => {
+                let response = Response::new_err(
+                    request.id,
+                    lsp_server::ErrorCode::MethodNotFound as i32,
+                    format!("Unsupported request {}", request.method),
+                );
+                self.connection.sender.send(Message::Response(response))?;
+                return Ok(());
+            }
+        };
+        let response = match result {
+            Ok(value) => Response::new_ok(id, value),
+            Err(error) => Response::new_err(
+                id,
+                lsp_server::ErrorCode::RequestFailed as i32,
+                error.to_string(),
+            ),
+        };
+        self.connection.sender.send(Message::Response(response))?;
+        Ok(())
+    }
+
+    fn handle_notification(&mut self, notification: Notification) -> anyhow::Result<()> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams =
+                    notification.extract(DidOpenTextDocument::METHOD)?;
+                let document = params.text_document;
+                self.publish_diagnostics(document.uri.clone(), &document.text)?;
+                self.documents.insert(document.uri, document.text);
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: lsp_types::DidChangeTextDocumentParams =
+                    notification.extract(DidChangeTextDocument::METHOD)?;
+                // documents are synced in full, so the last change is the whole text
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    let uri = params.text_document.uri;
+                    self.publish_diagnostics(uri.clone(), &change.text)?;
+                    self.documents.insert(uri, change.text);
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams =
+                    notification.extract(DidCloseTextDocument::METHOD)?;
+                self.documents.remove(&params.text_document.uri);
+                self.publish_diagnostics(params.text_document.uri, "")?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn run(&mut self) -> anyhow::Result<()> {
+        while let Ok(message) = self.connection.receiver.recv() {
+            let result = match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    self.handle_request(request)
+                }
+                Message::Notification(notification) => self.handle_notification(notification),
+                Message::Response(_) => Ok(()),
+            };
+            if let Err(error) = result {
+                eprintln!("{:#}", error);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let mut device = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--device" => {
+                let address = args.next().ok_or_else(|| {
+                    anyhow!("--device needs an address such as ws://localhost:8000")
+                })?;
+                device = Some(Device::new(address));
+            }
+            _ => return Err(anyhow!("Unknown argument {}", arg)),
+        }
+    }
+
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = serde_json::to_value(capabilities(device.is_some()))?;
+    connection.initialize(capabilities)?;
+    Server {
+        connection,
+        documents: HashMap::new(),
+        device,
+    }
+    .run()?;
+    io_threads.join()?;
+    Ok(())
+}
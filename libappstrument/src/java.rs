@@ -1,22 +1,45 @@
-use std::{io::Cursor, panic, sync::Mutex};
+use std::{
+    cell::{RefCell, RefMut},
+    collections::HashMap,
+    io::Cursor,
+    panic,
+    sync::Mutex,
+};
 
 use crate::{
     proto::{java_value::JavaValueType, *},
-    slat::interpreter::SlatInterpreter,
+    slat::{
+        cancel::CancelHandle,
+        completion::{self, CompletionKind},
+        diagnostic::Diagnostic,
+        interpreter::{
+            is_cancellation, thrown_exception, Evaluation, LambdaInvoker, SlatInterpreter,
+        },
+    },
 };
 use anyhow::anyhow;
 use jni::{
-    objects::{GlobalRef, JClass, JObject, JString, JValue},
-    sys::{jbyteArray, jint},
+    objects::{GlobalRef, JClass, JObject, JString, JThrowable, JValue},
+    sys::{jbyteArray, jint, jintArray, jlong, jobject, jobjectArray},
     *,
 };
 use lazy_static::lazy_static;
 use prost::*;
 
+/// Only ever shared, as lambdas can call back into the context while it handles a request.
 pub struct JavaNativeContext {
-    pub interpreter: SlatInterpreter,
+    pub interpreter: RefCell<SlatInterpreter>,
+    pub lambdas: LambdaInvoker,
     pub last_error: Option<anyhow::Error>,
-    pub stored_objects: Vec<GlobalRef>,
+    pub stored_objects: RefCell<Vec<GlobalRef>>,
+}
+
+impl JavaNativeContext {
+    fn interpreter(&self) -> anyhow::Result<RefMut<'_, SlatInterpreter>> {
+        self.interpreter
+            .try_borrow_mut()
+            .map_err(|_| anyhow!("the interpreter is already handling a request"))
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -26,6 +49,8 @@ unsafe impl Send for ThreadSafeVM {}
 
 lazy_static! {
     static ref JAVA_VM: Mutex<ThreadSafeVM> = Mutex::new(ThreadSafeVM(std::ptr::null_mut()));
+    // keyed by context address, cancellations are handled while the context is in use
+    static ref CANCEL_HANDLES: Mutex<HashMap<usize, CancelHandle>> = Mutex::new(HashMap::new());
 }
 
 trait WrappableResult {
@@ -58,8 +83,21 @@ impl WrappableResult for anyhow::Error {
 
 pub fn serialize_jvalue(
     env: JNIEnv,
-    mut ctx: Option<&mut JavaNativeContext>,
+    ctx: Option<&JavaNativeContext>,
+    val: JValue<'_>,
+) -> anyhow::Result<JavaValue> {
+    serialize_jvalue_limited(env, ctx, val, u32::MAX, u32::MAX)
+}
+
+/// Serializes a value, expanding at most `depth` levels of nested lists and
+/// at most `limit` items of each list. Lists past the depth limit are
+/// serialized as plain objects.
+pub fn serialize_jvalue_limited(
+    env: JNIEnv,
+    ctx: Option<&JavaNativeContext>,
     val: JValue<'_>,
+    depth: u32,
+    limit: u32,
 ) -> anyhow::Result<JavaValue> {
     Ok(match val {
         JValue::Void => JavaValue {
@@ -120,14 +158,15 @@ pub fn serialize_jvalue(
                         value: Some(java_value::Value::Integer(primitive_value as i64)),
                     }
                 } else {
-                    let is_list_type = env
-                        .call_static_method(
-                            "appstrument/server/ReflectionUtil",
-                            "isListType",
-                            "(Ljava/lang/Object;)Z",
-                            &[JValue::Object(obj)],
-                        )?
-                        .z()?;
+                    let is_list_type = depth > 0
+                        && env
+                            .call_static_method(
+                                "appstrument/server/ReflectionUtil",
+                                "isListType",
+                                "(Ljava/lang/Object;)Z",
+                                &[JValue::Object(obj)],
+                            )?
+                            .z()?;
                     if is_list_type {
                         let list_type = env
                             .call_static_method(
@@ -139,17 +178,16 @@ pub fn serialize_jvalue(
                             .l()?;
                         let array_type = list_type.into_inner();
 
-                        let array_len = env.get_array_length(array_type)?;
+                        let array_len = (env.get_array_length(array_type)? as u32).min(limit);
                         let mut items = Vec::with_capacity(array_len as usize);
                         for i in 0..array_len {
-                            let jvalue = env.get_object_array_element(array_type, i)?;
-                            let serialized = serialize_jvalue(
+                            let jvalue = env.get_object_array_element(array_type, i as i32)?;
+                            let serialized = serialize_jvalue_limited(
                                 env,
-                                match ctx {
-                                    Some(ref mut ctx) => Some(*ctx),
-                                    None => None,
-                                },
+                                ctx,
                                 JValue::Object(jvalue),
+                                depth - 1,
+                                limit,
                             )?;
                             items.push(serialized);
                         }
@@ -164,7 +202,7 @@ pub fn serialize_jvalue(
                     } else {
                         if let Some(ctx) = ctx {
                             let pinned_ref = env.new_global_ref(obj)?;
-                            ctx.stored_objects.push(pinned_ref);
+                            ctx.stored_objects.borrow_mut().push(pinned_ref);
                         }
 
                         JavaValue {
@@ -210,6 +248,15 @@ pub fn serialize_jvalue(
     })
 }
 
+/// Dereferences the context `nativeCreateContext` handed to Java.
+///
+/// # Safety
+///
+/// `context` must come from `nativeCreateContext` and not have been destroyed yet.
+unsafe fn native_context<'a>(context: jlong) -> &'a JavaNativeContext {
+    &*(context as *const JavaNativeContext)
+}
+
 macro_rules! wrap_result {
     ( $env:expr, $result:expr ) => {{
         match $result {
@@ -252,24 +299,40 @@ pub extern "system" fn Java_appstrument_server_AppstrumentNative_nativeInitializ
 #[no_mangle]
 pub extern "system" fn Java_appstrument_server_AppstrumentNative_nativeCreateContext<'a>(
     env: JNIEnv<'static>,
-    _class: JClass,
-) -> *mut JavaNativeContext {
-    let context = Box::new(JavaNativeContext {
-        interpreter: SlatInterpreter::new(env),
+    this: JObject,
+) -> jlong {
+    let session = match env.new_global_ref(this) {
+        Ok(session) => session,
+        Err(err) => {
+            err.throw_wrappable(env);
+            return 0;
+        }
+    };
+    let mut interpreter = SlatInterpreter::new(env);
+    interpreter.set_session(session);
+    let cancel_handle = interpreter.cancel_handle();
+    let context = Box::into_raw(Box::new(JavaNativeContext {
+        lambdas: interpreter.lambda_invoker(),
+        interpreter: RefCell::new(interpreter),
         last_error: None,
-        stored_objects: Vec::new(),
-    });
-    Box::into_raw(context)
+        stored_objects: RefCell::new(Vec::new()),
+    }));
+    CANCEL_HANDLES
+        .lock()
+        .unwrap()
+        .insert(context as usize, cancel_handle);
+    context as jlong
 }
 
 #[no_mangle]
 pub extern "system" fn Java_appstrument_server_AppstrumentNative_nativeDestroyContext<'a>(
     _env: JNIEnv<'a>,
     _this: JObject,
-    context: *mut JavaNativeContext,
+    context: jlong,
 ) {
+    CANCEL_HANDLES.lock().unwrap().remove(&(context as usize));
     unsafe {
-        std::mem::drop(Box::from_raw(context));
+        std::mem::drop(Box::from_raw(context as *mut JavaNativeContext));
     }
 }
 
@@ -277,7 +340,7 @@ pub extern "system" fn Java_appstrument_server_AppstrumentNative_nativeDestroyCo
 pub extern "system" fn Java_appstrument_server_AppstrumentNative_nativeHandleRequest<'a, 'b>(
     env: JNIEnv<'a>,
     this: JObject,
-    context: *mut JavaNativeContext,
+    context: jlong,
     request_byte_array: JObject,
     request_byte_array_offset: jint,
 ) -> jbyteArray {
@@ -296,38 +359,21 @@ pub extern "system" fn Java_appstrument_server_AppstrumentNative_nativeHandleReq
         match request.body.expect("no body sent in request") {
             appstrument_request::Body::LoadedClasses(_) => get_all_loaded_classes(env, this),
             appstrument_request::Body::StaticFields(req) => {
-                let mut ctx = unsafe { Box::from_raw(context) };
-                let static_fields = get_all_static_fields(env, req.class_name, ctx.as_mut());
-                Box::into_raw(ctx);
-                static_fields
+                let ctx = unsafe { native_context(context) };
+                get_all_static_fields(env, req.class_name, ctx)
             }
             appstrument_request::Body::ObjectFields(req) => {
-                let mut ctx = unsafe { Box::from_raw(context) };
-                let object_fields = get_all_object_fields(env, req.object_id, ctx.as_mut());
-                Box::into_raw(ctx);
-                object_fields
+                let ctx = unsafe { native_context(context) };
+                get_all_object_fields(env, req.object_id, ctx)
             }
             appstrument_request::Body::ExecuteSlat(req) => {
-                let mut ctx = unsafe { Box::from_raw(context) };
-                let interpret_result = ctx.interpreter.interpret(&req.code);
-                let (result, error_text) = match interpret_result {
-                    Ok(java_value) => (java_value, String::new()),
-                    Err(err) => (
-                        JavaValue {
-                            value_type: java_value::JavaValueType::NotPresent as i32,
-                            value: None,
-                        },
-                        err.to_string(),
-                    ),
-                };
-                Box::into_raw(ctx);
-                Ok(appstrument_response::Body::ExecuteSlat(
-                    ExecuteSlatResponse {
-                        error: !error_text.is_empty(),
-                        text: error_text,
-                        result: Some(result),
-                    },
-                ))
+                let ctx = unsafe { native_context(context) };
+                execute_slat(env, ctx, request.id, req)
+            }
+            appstrument_request::Body::Cancel(req) => Ok(cancel_request(context, req)),
+            appstrument_request::Body::CompleteSlat(req) => {
+                let ctx = unsafe { native_context(context) };
+                complete_slat(env, this, ctx, req)
             }
             appstrument_request::Body::ProcessStatus(_) => {
                 let threads = wrap_result!(env, get_threads(env));
@@ -346,6 +392,176 @@ pub extern "system" fn Java_appstrument_server_AppstrumentNative_nativeHandleReq
     wrap_result!(env, env.byte_array_from_slice(&response.encode_to_vec()))
 }
 
+fn execute_slat(
+    env: JNIEnv,
+    ctx: &JavaNativeContext,
+    request_id: i32,
+    req: ExecuteSlatRequest,
+) -> anyhow::Result<appstrument_response::Body> {
+    let mut interpreter = ctx.interpreter()?;
+    // requests may be handled on a different thread than the context was created on
+    interpreter.set_env(env);
+    // lambdas the script calls may serialize values, adding to the stored objects
+    let handles = ctx.stored_objects.borrow().clone();
+    let evaluation = Evaluation {
+        handles: &handles,
+        this_handle: req
+            .this_object
+            .map(|execute_slat_request::ThisObject::ThisObjectId(id)| id as usize),
+        timeout: (req.timeout > 0).then_some(req.timeout as u64),
+        steps: (req.steps > 0).then_some(req.steps as u64),
+        request_id: Some(request_id),
+    };
+    let interpret_result = interpreter.interpret_with(&req.code, &evaluation);
+    let cancelled = matches!(&interpret_result, Err(err) if is_cancellation(err));
+    let exception_chain = match &interpret_result {
+        Ok(_) => Vec::new(),
+        Err(err) => thrown_exception(err)
+            .map(|exception| exception.chain.clone())
+            .unwrap_or_default(),
+    };
+    let (result, error_text, diagnostics) = match interpret_result {
+        Ok(java_value) => (java_value, String::new(), Vec::new()),
+        Err(err) => {
+            let not_present = JavaValue {
+                value_type: java_value::JavaValueType::NotPresent as i32,
+                value: None,
+            };
+            let diagnostics = Diagnostic::all_from_error(&err);
+            if diagnostics.is_empty() {
+                (not_present, err.to_string(), Vec::new())
+            } else {
+                let error_text = diagnostics
+                    .iter()
+                    .map(|diagnostic| diagnostic.render(&req.code))
+                    .collect::<Vec<_>>()
+                    .join("\n\n");
+                let diagnostics = diagnostics
+                    .into_iter()
+                    .map(|diagnostic| SlatDiagnostic {
+                        message: diagnostic.message,
+                        start_line: diagnostic.span.start.line as i32,
+                        start_column: diagnostic.span.start.column as i32,
+                        end_line: diagnostic.span.end.line as i32,
+                        end_column: diagnostic.span.end.column as i32,
+                    })
+                    .collect();
+                (not_present, error_text, diagnostics)
+            }
+        }
+    };
+    let history_index = interpreter.history_index() as i32;
+    Ok(appstrument_response::Body::ExecuteSlat(
+        ExecuteSlatResponse {
+            error: !error_text.is_empty(),
+            text: error_text,
+            result: Some(result),
+            diagnostics,
+            exception_chain,
+            history_index,
+            cancelled,
+        },
+    ))
+}
+
+#[no_mangle]
+pub extern "system" fn Java_appstrument_server_AppstrumentNative_nativeCancelRequests(
+    _env: JNIEnv,
+    _this: JObject,
+    context: jlong,
+) {
+    if let Some(cancel_handle) = CANCEL_HANDLES.lock().unwrap().get(&(context as usize)) {
+        cancel_handle.cancel_all();
+    }
+}
+
+fn cancel_request(context: jlong, req: CancelRequest) -> appstrument_response::Body {
+    // the context isn't dereferenced, it may be handling the request being cancelled
+    let running = match CANCEL_HANDLES.lock().unwrap().get(&(context as usize)) {
+        Some(cancel_handle) => cancel_handle.cancel(req.request_id),
+        None => false,
+    };
+    appstrument_response::Body::Cancel(CancelResponse { running })
+}
+
+/// Handles a request if it is a `CancelRequest`, returning null otherwise. Unlike
+/// `nativeHandleRequest`, this can be called while another request is handled.
+#[no_mangle]
+pub extern "system" fn Java_appstrument_server_AppstrumentNative_nativeHandleCancelRequest<'a>(
+    env: JNIEnv<'a>,
+    _this: JObject,
+    context: jlong,
+    request_byte_array: JObject,
+    request_byte_array_offset: jint,
+) -> jbyteArray {
+    let request_bytes = wrap_result!(env, env.convert_byte_array(request_byte_array.into_inner()));
+
+    let mut cursor = Cursor::new(request_bytes);
+    cursor.set_position(request_byte_array_offset as u64);
+    let request = wrap_result!(
+        env,
+        AppstrumentRequest::decode(&mut cursor)
+            .map_err(|_| anyhow!("could not deserialize request"))
+    );
+    let response_body = match request.body {
+        Some(appstrument_request::Body::Cancel(req)) => cancel_request(context, req),
+        _ => return std::ptr::null_mut(),
+    };
+    let response = AppstrumentResponse {
+        id: request.id,
+        body: Some(response_body),
+    };
+
+    wrap_result!(env, env.byte_array_from_slice(&response.encode_to_vec()))
+}
+
+#[no_mangle]
+pub extern "system" fn Java_appstrument_server_AppstrumentNative_nativeInvokeLambda<'a>(
+    env: JNIEnv<'a>,
+    _this: JObject,
+    context: jlong,
+    lambda_id: jint,
+    args: jobjectArray,
+    return_type: JString,
+) -> jobject {
+    // lambdas can be invoked while the context is handling a request, by a method the
+    // script called on the same thread, so this must not borrow the interpreter
+    let ctx = unsafe { native_context(context) };
+    match ctx.lambdas.invoke(env, lambda_id, args, return_type) {
+        Ok(result) => result.into_inner(),
+        Err(err) => {
+            // exceptions thrown by the lambda propagate to its caller unchanged
+            match thrown_exception(&err) {
+                Some(exception) => env
+                    .throw(JThrowable::from(exception.throwable.as_obj().into_inner()))
+                    .expect("could not throw exception"),
+                None => err.throw_wrappable(env),
+            }
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Frees the lambdas whose proxies were collected, while no request is running.
+#[no_mangle]
+pub extern "system" fn Java_appstrument_server_AppstrumentNative_nativeReleaseLambdas(
+    env: JNIEnv,
+    _this: JObject,
+    context: jlong,
+    lambda_ids: jintArray,
+) {
+    let ctx = unsafe { native_context(context) };
+    let released = env.get_array_length(lambda_ids).and_then(|length| {
+        let mut ids = vec![0; length as usize];
+        env.get_int_array_region(lambda_ids, 0, &mut ids)?;
+        Ok(ids)
+    });
+    match released {
+        Ok(ids) => ids.into_iter().for_each(|id| ctx.lambdas.release(id)),
+        Err(err) => err.throw_wrappable(env),
+    }
+}
+
 #[no_mangle]
 pub extern "system" fn Java_appstrument_server_AppstrumentNative_nativeCreateLogcatPacket<
     'a,
@@ -353,7 +569,7 @@ pub extern "system" fn Java_appstrument_server_AppstrumentNative_nativeCreateLog
 >(
     env: JNIEnv<'a>,
     _this: JObject,
-    _context: *mut JavaNativeContext,
+    _context: jlong,
     text: JString,
 ) -> jbyteArray {
     let text: String = wrap_result!(env, env.get_string(text)).into();
@@ -401,7 +617,7 @@ fn get_threads(env: JNIEnv) -> anyhow::Result<Vec<JavaThread>> {
 
 fn get_all_fields<'a, F: Fn(&str, &str) -> jni::errors::Result<JValue<'a>>>(
     env: JNIEnv,
-    ctx: &mut JavaNativeContext,
+    ctx: &JavaNativeContext,
     java_fields: JObject,
     field_accessor: F,
 ) -> anyhow::Result<Vec<JavaField>> {
@@ -430,7 +646,9 @@ fn get_all_fields<'a, F: Fn(&str, &str) -> jni::errors::Result<JValue<'a>>>(
             name,
             r#type,
             object_id: match value.value {
-                Some(java_value::Value::ObjectType(_)) => (ctx.stored_objects.len() - 1) as i32,
+                Some(java_value::Value::ObjectType(_)) => {
+                    (ctx.stored_objects.borrow().len() - 1) as i32
+                }
                 _ => -1,
             },
             value: Some(value),
@@ -443,9 +661,9 @@ fn get_all_fields<'a, F: Fn(&str, &str) -> jni::errors::Result<JValue<'a>>>(
 fn get_all_object_fields(
     env: JNIEnv,
     object_id: i32,
-    ctx: &mut JavaNativeContext,
+    ctx: &JavaNativeContext,
 ) -> anyhow::Result<appstrument_response::Body> {
-    let ref_clone = ctx.stored_objects[object_id as usize].clone();
+    let ref_clone = ctx.stored_objects.borrow()[object_id as usize].clone();
     let object = ref_clone.as_obj();
     let instance_fields = env
         .call_static_method(
@@ -469,7 +687,7 @@ fn get_all_object_fields(
 fn get_all_static_fields(
     env: JNIEnv,
     class_name: String,
-    ctx: &mut JavaNativeContext,
+    ctx: &JavaNativeContext,
 ) -> anyhow::Result<appstrument_response::Body> {
     let class_name = class_name.replace(".", "/");
     let class_name_str = env.new_string(&class_name)?;
@@ -492,10 +710,75 @@ fn get_all_static_fields(
     ))
 }
 
+fn complete_slat(
+    env: JNIEnv,
+    this: JObject,
+    ctx: &JavaNativeContext,
+    req: CompleteSlatRequest,
+) -> anyhow::Result<appstrument_response::Body> {
+    let mut interpreter = ctx.interpreter()?;
+    interpreter.set_env(env);
+    let env = unsafe { JNIEnv::from_raw(env.get_native_interface())? };
+    let importable_classes = || match find_loaded_classes(env, this) {
+        Ok(classes) => classes
+            .into_iter()
+            .filter(|class| class.is_loaded)
+            .map(|class| class.class_name)
+            .collect(),
+        // outside of Android there is no class path to list
+        Err(_) => {
+            let _ = env.exception_clear();
+            Vec::new()
+        }
+    };
+    let completions = completion::complete(
+        env,
+        &mut interpreter,
+        &req.code,
+        req.cursor.max(0) as usize,
+        importable_classes,
+    )?;
+
+    let candidates = completions
+        .candidates
+        .into_iter()
+        .map(|candidate| SlatCompletion {
+            kind: match candidate.kind {
+                CompletionKind::Variable => slat_completion::Kind::Variable,
+                CompletionKind::Field => slat_completion::Kind::Field,
+                CompletionKind::Method => slat_completion::Kind::Method,
+                CompletionKind::Function => slat_completion::Kind::Function,
+                CompletionKind::Class => slat_completion::Kind::Class,
+                CompletionKind::Package => slat_completion::Kind::Package,
+                CompletionKind::Keyword => slat_completion::Kind::Keyword,
+            } as i32,
+            label: candidate.label,
+            insert_text: candidate.insert_text,
+            signature: candidate.signature,
+            documentation: candidate.documentation,
+        })
+        .collect();
+    Ok(appstrument_response::Body::CompleteSlat(
+        CompleteSlatResponse {
+            candidates,
+            replace_start: completions.replace_start as i32,
+            replace_end: completions.replace_end as i32,
+        },
+    ))
+}
+
 fn get_all_loaded_classes(
     env: JNIEnv,
     this: JObject,
 ) -> anyhow::Result<appstrument_response::Body> {
+    Ok(appstrument_response::Body::LoadedClasses(
+        GetLoadedClassesResponse {
+            classes: find_loaded_classes(env, this)?,
+        },
+    ))
+}
+
+fn find_loaded_classes(env: JNIEnv, this: JObject) -> anyhow::Result<Vec<LoadedClass>> {
     let this_class = env.get_object_class(this)?;
     let class_loader = env
         .call_method(
@@ -607,9 +890,5 @@ fn get_all_loaded_classes(
         }
     }
 
-    Ok(appstrument_response::Body::LoadedClasses(
-        GetLoadedClassesResponse {
-            classes: loaded_classes,
-        },
-    ))
+    Ok(loaded_classes)
 }
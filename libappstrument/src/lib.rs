@@ -11,11 +11,25 @@ mod test;
 
 #[cfg(test)]
 mod tests {
+    use jni::objects::JValue;
     use pest::Parser;
+    use crate::proto::java_value::Value;
     use crate::slat::{
-        interpreter::SlatInterpreter,
+        ast::{BinaryOperator, LambdaBody, Literal, MemberKind, Token, TokenKind},
+        cancel::CancelHandle,
+        check::{self, Arity, CheckContext, ReflectionOracle},
+        completion,
+        diagnostic::{Diagnostic, Severity},
+        conversion,
+        interpreter::{self, SlatInterpreter},
+        intrinsics,
+        operators,
         parser::{Rule, SlatParser, self},
+        printer,
+        visit::{self, Visitor, VisitorMut},
     };
+    use proptest::prelude::*;
+    use std::{collections::HashMap, convert::Infallible};
 
     #[test]
     fn it_works() {
@@ -36,4 +50,850 @@ mod tests {
             .interpret("ReflectionUtil.stringArray[1]")
             .unwrap();
     }
+
+    /// Runs a script against the mock JVM, which only knows primitives and strings.
+    fn evaluate(interpreter: &mut SlatInterpreter, code: &str) -> Result<Option<Value>, String> {
+        interpreter.interpret(code).map(|result| result.value).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn limits_loop_iterations() {
+        let mut interpreter = SlatInterpreter::new(crate::test::jvm::create_mock_jvm());
+        assert_eq!(
+            evaluate(&mut interpreter, "#iterations 10\ni = 0\nwhile true { i = i + 1 }"),
+            Err("Loop exceeded the limit of 10 iterations".to_owned())
+        );
+        // the body never runs more times than the limit allows
+        assert_eq!(evaluate(&mut interpreter, "i"), Ok(Some(Value::Integer(10))));
+        assert_eq!(evaluate(&mut interpreter, "i = 0\nwhile i < 10 { i = i + 1 }\ni"), Ok(Some(Value::Integer(10))));
+    }
+
+    #[test]
+    fn limits_steps() {
+        let mut interpreter = SlatInterpreter::new(crate::test::jvm::create_mock_jvm());
+        assert_eq!(
+            evaluate(&mut interpreter, "#steps 100\nwhile true {}"),
+            Err("Execution exceeded the budget of 100 steps".to_owned())
+        );
+        // a budget of 0 leaves only the iteration limit
+        assert_eq!(
+            evaluate(&mut interpreter, "#steps 0\n#iterations 10\nwhile true {}"),
+            Err("Loop exceeded the limit of 10 iterations".to_owned())
+        );
+    }
+
+    #[test]
+    fn limits_recursion() {
+        let mut interpreter = SlatInterpreter::new(crate::test::jvm::create_mock_jvm());
+        assert_eq!(
+            evaluate(&mut interpreter, "#recursion 5\nfn f(n) { return f(n + 1) }\nf(0)"),
+            Err("Function calls exceeded the recursion limit of 5".to_owned())
+        );
+        // the calls aborted by the limit don't count against later ones
+        assert_eq!(
+            evaluate(&mut interpreter, "fn g(n) { if n == 0 { return 0 }\nreturn g(n - 1) + 1 }\ng(4)"),
+            Ok(Some(Value::Integer(4)))
+        );
+        assert_eq!(
+            evaluate(&mut interpreter, "g(5)"),
+            Err("Function calls exceeded the recursion limit of 5".to_owned())
+        );
+    }
+
+    #[test]
+    fn returns_from_loops() {
+        let mut interpreter = SlatInterpreter::new(crate::test::jvm::create_mock_jvm());
+        let source = "fn first_over(limit) {\n    i = 0\n    while true {\n        while true {\n            i = i + 1\n\
+            if i > limit { return i }\n        }\n    }\n}\nfirst_over(3)";
+        assert_eq!(evaluate(&mut interpreter, source), Ok(Some(Value::Integer(4))));
+        // the loop doesn't leave the function's variables behind
+        assert_eq!(evaluate(&mut interpreter, "i"), Err("Unknown identifier 'i'".to_owned()));
+    }
+
+    #[test]
+    fn returns_from_try() {
+        let mut interpreter = SlatInterpreter::new(crate::test::jvm::create_mock_jvm());
+        let source = "fn f(n) {\n    try {\n        if n > 0 { return n }\n    } catch (e) {\n        return -1\n    }\n\
+            return 0\n}\n";
+        assert_eq!(evaluate(&mut interpreter, source), Ok(None));
+        assert_eq!(evaluate(&mut interpreter, "f(2)"), Ok(Some(Value::Integer(2))));
+        assert_eq!(evaluate(&mut interpreter, "f(0)"), Ok(Some(Value::Integer(0))));
+
+        let source = "fn g() {\n    hits = 0\n    while true {\n        try {\n            hits = hits + 1\n\
+            if hits == 3 { return hits }\n        } catch (e) {}\n    }\n}\ng()";
+        assert_eq!(evaluate(&mut interpreter, source), Ok(Some(Value::Integer(3))));
+    }
+
+    #[test]
+    fn indexes_from_the_end() {
+        let mut interpreter = SlatInterpreter::new(crate::test::jvm::create_mock_jvm());
+        assert_eq!(evaluate(&mut interpreter, "\"hello\"[0]"), Ok(Some(Value::Integer('h' as i64))));
+        assert_eq!(evaluate(&mut interpreter, "\"hello\"[-1]"), Ok(Some(Value::Integer('o' as i64))));
+        assert_eq!(evaluate(&mut interpreter, "\"hello\"[1:-1]"), Ok(Some(Value::String("ell".to_owned()))));
+        assert_eq!(evaluate(&mut interpreter, "\"hello\"[-3:]"), Ok(Some(Value::String("llo".to_owned()))));
+        assert_eq!(
+            evaluate(&mut interpreter, "\"hello\"[5]"),
+            Err("Index 5 out of bounds for length 5".to_owned())
+        );
+        assert_eq!(
+            evaluate(&mut interpreter, "\"hello\"[-6]"),
+            Err("Index -6 out of bounds for length 5".to_owned())
+        );
+        assert_eq!(
+            evaluate(&mut interpreter, "\"hello\"[3:1]"),
+            Err("Slice 3:1 out of bounds for length 5".to_owned())
+        );
+    }
+
+    #[test]
+    fn interns_string_literals() {
+        let mut interpreter = SlatInterpreter::new(crate::test::jvm::create_mock_jvm());
+        assert_eq!(evaluate(&mut interpreter, "\"a\" == \"a\""), Ok(Some(Value::Boolean(true))));
+        // any other string is compared by reference, as in Java
+        assert_eq!(evaluate(&mut interpreter, "s = \"ab\"\ns[0:1] == \"a\""), Ok(Some(Value::Boolean(false))));
+        assert_eq!(evaluate(&mut interpreter, "s[0:1] != \"a\""), Ok(Some(Value::Boolean(true))));
+    }
+
+    #[test]
+    fn parses_new_expression() {
+        let ast = parser::parse("new StringBuilder(\"hello\", 16)").expect("parsed");
+        match &ast[..] {
+            [Token { kind: TokenKind::New(new_expr), .. }] => {
+                assert_eq!(new_expr.class_name, "StringBuilder");
+                assert_eq!(new_expr.args.len(), 2);
+            }
+            _ => panic!("expected a single new expression, got {:?}", ast),
+        }
+    }
+
+    #[test]
+    fn parses_directive() {
+        let ast = parser::parse("#depth 3\n#strict true").expect("parsed");
+        match &ast[..] {
+            [
+                Token { kind: TokenKind::Directive(depth), .. },
+                Token { kind: TokenKind::Directive(strict), .. },
+            ] => {
+                assert_eq!(depth.name, "depth");
+                assert_eq!(strict.name, "strict");
+            }
+            _ => panic!("expected two directives, got {:?}", ast),
+        }
+    }
+
+    #[test]
+    fn parses_operator_precedence() {
+        let ast = parser::parse("a || 1 + 2 * 3 < 4 && !b").expect("parsed");
+        match &ast[..] {
+            [Token { kind: TokenKind::BinaryExpression(or), .. }] => {
+                assert_eq!(or.operator, BinaryOperator::Or);
+                match &or.right.kind {
+                    TokenKind::BinaryExpression(and) => {
+                        assert_eq!(and.operator, BinaryOperator::And);
+                        assert!(matches!(&and.left.kind, TokenKind::BinaryExpression(less)
+                            if less.operator == BinaryOperator::Less));
+                    }
+                    other => panic!("expected &&, got {:?}", other),
+                }
+            }
+            _ => panic!("expected a single binary expression, got {:?}", ast),
+        }
+    }
+
+    #[test]
+    fn parses_control_flow() {
+        let source = "for x in list {\n  if x > 2 { a = x } else if x == 2 { b = x } else { c = x }\n}\nwhile a < 10 { a = a + 1 }";
+        let ast = parser::parse(source).expect("parsed");
+        match &ast[..] {
+            [
+                Token { kind: TokenKind::ForEach(for_each), .. },
+                Token { kind: TokenKind::While(while_loop), .. },
+            ] => {
+                assert_eq!(for_each.variable, "x");
+                match &for_each.body[..] {
+                    [Token { kind: TokenKind::If(if_statement), .. }] => {
+                        assert_eq!(if_statement.body.len(), 1);
+                        assert!(matches!(
+                            if_statement.else_body.as_deref(),
+                            Some([Token { kind: TokenKind::If(_), .. }])
+                        ));
+                    }
+                    other => panic!("expected an if statement, got {:?}", other),
+                }
+                assert_eq!(while_loop.body.len(), 1);
+            }
+            _ => panic!("expected a for loop and a while loop, got {:?}", ast),
+        }
+    }
+
+    #[test]
+    fn parses_function_definition() {
+        let ast = parser::parse("fn add(a, b) {\n  return a + b\n}\nreturned = add(1, 2)")
+            .expect("parsed");
+        match &ast[..] {
+            [
+                Token { kind: TokenKind::Function(function), .. },
+                Token { kind: TokenKind::Assignment(_), .. },
+            ] => {
+                assert_eq!(function.name, "add");
+                assert_eq!(function.parameters, ["a", "b"]);
+                assert!(matches!(
+                    &function.body[..],
+                    [Token { kind: TokenKind::Return(ret), .. }] if ret.value.is_some()
+                ));
+            }
+            _ => panic!("expected a function and an assignment, got {:?}", ast),
+        }
+    }
+
+    #[test]
+    fn parses_imports() {
+        let ast = parser::parse("import java.util.*\nimport java.awt.List as AwtList\nnew java.util.ArrayList()")
+            .expect("parsed");
+        match &ast[..] {
+            [
+                Token { kind: TokenKind::Import(wildcard), .. },
+                Token { kind: TokenKind::Import(aliased), .. },
+                Token { kind: TokenKind::New(new_expr), .. },
+            ] => {
+                assert!(wildcard.wildcard && wildcard.path == ["java", "util"]);
+                assert_eq!(aliased.alias.as_deref(), Some("AwtList"));
+                assert_eq!(new_expr.class_name, "java.util.ArrayList");
+            }
+            _ => panic!("expected two imports and a new expression, got {:?}", ast),
+        }
+    }
+
+    #[test]
+    fn parses_try_catch() {
+        let source = "try {\n  throw new IllegalStateException()\n} catch (java.io.IOException e) {\n} catch (e) {\n  e\n}";
+        let ast = parser::parse(source).expect("parsed");
+        match &ast[..] {
+            [Token { kind: TokenKind::Try(try_statement), .. }] => {
+                assert!(matches!(
+                    &try_statement.body[..],
+                    [Token { kind: TokenKind::Throw(_), .. }]
+                ));
+                let clauses = &try_statement.catch_clauses;
+                assert_eq!(clauses.len(), 2);
+                assert_eq!(clauses[0].class_name.as_deref(), Some("java.io.IOException"));
+                assert_eq!(clauses[1].class_name, None);
+                assert_eq!(clauses[1].variable, "e");
+            }
+            _ => panic!("expected a single try statement, got {:?}", ast),
+        }
+    }
+
+    #[test]
+    fn parses_postfix_chain() {
+        let ast = parser::parse("list.get(0).field[2]").expect("parsed");
+        match &ast[..] {
+            [Token { kind: TokenKind::MemberExpression(member_expr), .. }] => {
+                assert!(matches!(&member_expr.owner.kind, TokenKind::Identifier(owner) if owner == "list"));
+                assert!(matches!(
+                    &member_expr.members.iter().map(|member| &member.kind).collect::<Vec<_>>()[..],
+                    [MemberKind::MethodCall(get), MemberKind::Field(field), MemberKind::Index(_)]
+                        if get.name == "get" && field == "field"
+                ));
+            }
+            _ => panic!("expected a single member expression, got {:?}", ast),
+        }
+    }
+
+    #[test]
+    fn tracks_source_spans() {
+        let ast = parser::parse("x = 1\ny = list.get(0)").expect("parsed");
+        let member_expr = match &ast[..] {
+            [_, Token { kind: TokenKind::Assignment(assignment), .. }] => match &assignment.expr.kind {
+                TokenKind::MemberExpression(member_expr) => member_expr,
+                other => panic!("expected a member expression, got {:?}", other),
+            },
+            _ => panic!("expected two assignments, got {:?}", ast),
+        };
+        let get = member_expr.members[0].span;
+        assert_eq!((get.start.line, get.start.column), (2, 9));
+        assert_eq!((get.end.line, get.end.column), (2, 16));
+
+        let err = parser::parse("a = 1\nb = (2 +").expect_err("invalid syntax");
+        let diagnostic = Diagnostic::from_error(&err).expect("located");
+        assert_eq!(diagnostic.span.start.line, 2);
+        assert!(diagnostic.render("a = 1\nb = (2 +").contains("2 | b = (2 +"));
+    }
+
+    #[test]
+    fn parses_java_literals() {
+        let literal = |source: &str| match parser::parse(source) {
+            Ok(mut ast) => match ast.pop().map(|token| token.kind) {
+                Some(TokenKind::Literal(literal)) => literal,
+                other => panic!("expected a literal, got {:?}", other),
+            },
+            Err(err) => panic!("failed to parse {}: {}", source, err),
+        };
+        assert!(matches!(literal("1_700_000_000_000L"), Literal::Long(1_700_000_000_000)));
+        assert!(matches!(literal("-2147483648"), Literal::Int(i32::MIN)));
+        assert!(matches!(literal("0xFFFF_FFFF"), Literal::Int(-1)));
+        assert!(matches!(literal("0b101"), Literal::Int(5)));
+        assert!(matches!(literal("017"), Literal::Int(15)));
+        assert!(matches!(literal("1.5f"), Literal::Float(f) if f == 1.5));
+        assert!(matches!(literal("1e3"), Literal::Double(d) if d == 1000.0));
+        assert!(matches!(literal("2d"), Literal::Double(d) if d == 2.0));
+        assert!(matches!(literal("'\\n'"), Literal::Char(10)));
+        assert!(matches!(literal("'\\u0041'"), Literal::Char(65)));
+        assert!(matches!(literal("\"a\\tb\\\"\\101\""), Literal::String(s) if s == "a\tb\"A"));
+        assert!(matches!(literal("null"), Literal::Null));
+        assert!(parser::parse("2147483648").is_err());
+        assert!(parser::parse("1e999").is_err());
+        assert!(parser::parse("'ab'").is_err());
+    }
+
+    #[test]
+    fn parses_identifiers_starting_with_literals() {
+        let ast = parser::parse("x = trueCount\nprint(falsePositives)\nnullable.size()\ntrue_").expect("parsed");
+        match &ast[..] {
+            [assignment, print, size, last] => {
+                assert!(matches!(&assignment.kind, TokenKind::Assignment(assignment)
+                    if matches!(&assignment.expr.kind, TokenKind::Identifier(name) if name == "trueCount")));
+                assert!(matches!(&print.kind, TokenKind::MethodCall(call)
+                    if matches!(&call.args[..], [Token { kind: TokenKind::Identifier(name), .. }] if name == "falsePositives")));
+                assert!(matches!(&size.kind, TokenKind::MemberExpression(member_expr)
+                    if matches!(&member_expr.owner.kind, TokenKind::Identifier(name) if name == "nullable")));
+                assert!(matches!(&last.kind, TokenKind::Identifier(name) if name == "true_"));
+            }
+            _ => panic!("expected four statements, got {:?}", ast),
+        }
+        assert!(matches!(&parser::parse("true").expect("parsed")[0].kind, TokenKind::Literal(Literal::Boolean(true))));
+    }
+
+    #[test]
+    fn parses_lambdas() {
+        let ast = parser::parse("list.sort((a, b) -> a - b)\nr = (java.lang.Runnable) () -> {\n  x = 1\n}")
+            .expect("parsed");
+        match &ast[..] {
+            [
+                Token { kind: TokenKind::MemberExpression(member_expr), .. },
+                Token { kind: TokenKind::Assignment(assignment), .. },
+            ] => {
+                match &member_expr.members[0].kind {
+                    MemberKind::MethodCall(sort) => assert!(matches!(
+                        &sort.args[0].kind,
+                        TokenKind::Lambda(lambda) if lambda.parameters == ["a", "b"]
+                            && lambda.interface.is_none()
+                            && matches!(lambda.body, LambdaBody::Expression(_))
+                    )),
+                    other => panic!("expected a method call, got {:?}", other),
+                }
+                assert!(matches!(
+                    &assignment.expr.kind,
+                    TokenKind::Lambda(lambda) if lambda.parameters.is_empty()
+                        && lambda.interface.as_deref() == Some("java.lang.Runnable")
+                        && matches!(&lambda.body, LambdaBody::Block(body) if body.len() == 1)
+                ));
+            }
+            _ => panic!("expected a method call and an assignment, got {:?}", ast),
+        }
+        assert!(parser::parse("x -> x + 1").is_ok());
+    }
+
+    #[test]
+    fn parses_array_expressions() {
+        let ast = parser::parse("[1, [2, 3], \"a\"]\nnew java.lang.String[2][n][]").expect("parsed");
+        match &ast[..] {
+            [
+                Token { kind: TokenKind::ArrayLiteral(elements), .. },
+                Token { kind: TokenKind::NewArray(new_array), .. },
+            ] => {
+                assert_eq!(elements.len(), 3);
+                assert!(matches!(&elements[1].kind, TokenKind::ArrayLiteral(nested) if nested.len() == 2));
+                assert_eq!(new_array.class_name, "java.lang.String");
+                assert_eq!(new_array.dimensions.len(), 2);
+                assert_eq!(new_array.unsized_dimensions, 1);
+            }
+            _ => panic!("expected an array literal and an array creation, got {:?}", ast),
+        }
+        assert!(parser::parse("[]").is_ok());
+    }
+
+    #[test]
+    fn resolves_indices_and_slices() {
+        assert_eq!(interpreter::normalize_index(-1, 5).ok(), Some(4));
+        assert_eq!(interpreter::normalize_index(0, 5).ok(), Some(0));
+        assert!(interpreter::normalize_index(5, 5).is_err());
+        assert!(interpreter::normalize_index(-6, 5).is_err());
+        assert_eq!(interpreter::slice_bounds(Some(1), Some(-1), 5).ok(), Some((1, 4)));
+        assert_eq!(interpreter::slice_bounds(None, None, 5).ok(), Some((0, 5)));
+        assert_eq!(interpreter::slice_bounds(Some(-2), None, 5).ok(), Some((3, 5)));
+        assert!(interpreter::slice_bounds(Some(3), Some(2), 5).is_err());
+        assert!(interpreter::slice_bounds(None, Some(6), 5).is_err());
+
+        let ast = parser::parse("list[1:]").expect("parsed");
+        assert!(matches!(
+            &ast[..],
+            [Token { kind: TokenKind::MemberExpression(member_expr), .. }]
+                if matches!(&member_expr.members[0].kind,
+                    MemberKind::Slice(slice) if slice.start.is_some() && slice.end.is_none())
+        ));
+    }
+
+    #[test]
+    fn parses_session_statements() {
+        let ast = parser::parse("vars\ndel a, b\n@12.toString()\ndeleted = _2").expect("parsed");
+        match &ast[..] {
+            [
+                Token { kind: TokenKind::Vars, .. },
+                Token { kind: TokenKind::Delete(names), .. },
+                Token { kind: TokenKind::MemberExpression(member_expr), .. },
+                Token { kind: TokenKind::Assignment(assignment), .. },
+            ] => {
+                assert_eq!(names, &["a", "b"]);
+                assert!(matches!(member_expr.owner.kind, TokenKind::ObjectHandle(12)));
+                assert!(matches!(&assignment.expr.kind, TokenKind::Identifier(ident) if ident == "_2"));
+            }
+            _ => panic!("expected session statements, got {:?}", ast),
+        }
+        assert!(matches!(
+            &parser::parse("vars.size()").expect("parsed")[..],
+            [Token { kind: TokenKind::MemberExpression(_), .. }]
+        ));
+    }
+
+    #[test]
+    fn separates_statements() {
+        let canonical = |source: &str| printer::print(&parser::parse(source).expect("parsed"));
+
+        // comments are kept on their own line or after the statement they followed
+        assert_eq!(canonical("// a\nx = 1 // b\n/* c\nd */ y = 2 /* e */\n"), "// a\nx = 1 // b\n/* c\nd */\ny = 2 /* e */\n");
+        assert_eq!(canonical("s = \"// not a comment\"\nvars // list"), "s = \"// not a comment\"\nvars // list\n");
+        assert_eq!(canonical("x /* a */ // b\n/* c */ // d"), "x /* a */ // b\n/* c */ // d\n");
+        assert_eq!(canonical("// nothing to run"), "// nothing to run\n");
+        assert!(parser::parse("x = 1 /* unterminated").is_err());
+        // in blocks, including ones holding nothing else
+        assert_eq!(
+            canonical("if a { // why\n    b /* c */\n    // end\n} else { /* todo */ }"),
+            "if a {\n    // why\n    b /* c */\n    // end\n} else {\n    /* todo */\n}\n"
+        );
+        // and before the statement they were inside of
+        assert_eq!(canonical("if a /* b */ {\n    f(c /* d */, x -> e) // f\n}"), "/* b */\nif a {\n    /* d */\n    f(c, x -> e) // f\n}\n");
+        assert!(matches!(
+            &parser::parse("/* a */ x = 1 // b").expect("parsed")[..],
+            [Token { kind: TokenKind::Comment(a), .. }, _, Token { kind: TokenKind::Comment(b), .. }]
+                if a == "/* a */" && b == "// b"
+        ));
+
+        // semicolons
+        assert_eq!(canonical("a = 1; b = 2;; vars; if a { b; c };"), "a = 1\nb = 2\nvars\nif a {\n    b\n    c\n}\n");
+
+        // statements continue across lines inside parentheses and brackets only
+        assert_eq!(
+            canonical("f(\n    a, // first\n    b\n)\nx = [\n    1,\n    2\n][\n0]\ny = (a\n    + b)\nfn g(\nc) {\n}"),
+            "// first\nf(a, b)\nx = [1, 2][0]\ny = a + b\nfn g(c) {}\n"
+        );
+        assert_eq!(canonical("a\n-b\nf\n(x)\nreturn\nc"), "a\n-b\nf\nx\nreturn\nc\n");
+        assert_eq!(canonical("g(x -> {\n    a\n    b\n})"), "g(x -> {\n    a\n    b\n})\n");
+        assert!(parser::parse("a +\nb").is_err());
+
+        // like in Java, opening braces, else and catch may start a new line
+        assert_eq!(
+            canonical("if a {\n}\nelse {\n    b\n}\ntry {\n}\ncatch (e) {\n}"),
+            "if a {} else {\n    b\n}\ntry {} catch (e) {}\n"
+        );
+        assert_eq!(canonical("if a {\n} else\n{\n    b\n}"), "if a {} else {\n    b\n}\n");
+        assert_eq!(canonical("if a {}\nelse\n\nif b {}"), "if a {} else if b {}\n");
+        assert_eq!(canonical("if a\n{\n}\nwhile a\n{\n}\nfor i in a\n{\n}"), "if a {}\nwhile a {}\nfor i in a {}\n");
+        assert_eq!(canonical("fn g()\n{\n}\ntry\n{\n}\ncatch (e)\n{\n}"), "fn g() {}\ntry {} catch (e) {}\n");
+        assert_eq!(canonical("f(x ->\n{\n    a\n})"), "f(x -> {\n    a\n})\n");
+        assert!(parser::parse("if a {} else\n").is_err());
+    }
+
+    #[test]
+    fn cancels_requests() {
+        let handle = CancelHandle::default();
+        handle.begin(Some(1));
+        assert!(!handle.is_cancelled());
+        assert!(!handle.clone().cancel(2));
+        assert!(!handle.is_cancelled());
+        assert!(handle.clone().cancel(1));
+        assert!(handle.is_cancelled());
+        handle.finish();
+        assert!(!handle.is_cancelled());
+
+        // a request cancelled while waiting is cancelled as soon as it starts
+        handle.begin(Some(2));
+        assert!(handle.is_cancelled());
+        handle.finish();
+        handle.begin(None);
+        assert!(!handle.is_cancelled());
+        handle.finish();
+    }
+
+    #[test]
+    fn cancels_finished_requests() {
+        let handle = CancelHandle::default();
+        handle.begin(Some(1));
+        handle.finish();
+        // cancelling a finished request does nothing, now or later
+        assert!(!handle.cancel(1));
+        assert!(!handle.cancel(0));
+        assert!(!handle.cancel(4));
+        handle.begin(Some(2));
+        assert!(!handle.is_cancelled());
+        handle.finish();
+
+        // the cancellation of 4 is dropped once a later request starts
+        handle.begin(Some(5));
+        assert!(!handle.is_cancelled());
+        handle.finish();
+        assert!(!handle.cancel(4));
+    }
+
+    #[test]
+    fn cancels_all_requests() {
+        let handle = CancelHandle::default();
+        handle.begin(Some(1));
+        handle.cancel_all();
+        assert!(handle.is_cancelled());
+        handle.finish();
+        assert!(!handle.is_cancelled());
+        handle.begin(Some(2));
+        assert!(handle.is_cancelled());
+        handle.finish();
+    }
+
+    #[test]
+    fn finds_completion_context() {
+        let context = completion::completion_context("x = list.get(0).toSt", 20).expect("context");
+        assert_eq!(context.prefix, "toSt");
+        assert_eq!(context.receiver.as_deref(), Some("list.get(0)"));
+        assert_eq!((context.replace_start, context.replace_end), (16, 20));
+
+        let context = completion::completion_context("print(Sys) ", 9).expect("context");
+        assert_eq!(context.prefix, "Sys");
+        assert_eq!(context.receiver, None);
+
+        // nothing to complete inside strings, numbers, handles or comments
+        assert!(completion::completion_context("s = \"a.b", 9).is_none());
+        assert!(completion::completion_context("1.5", 3).is_none());
+        assert!(completion::completion_context("@1", 2).is_none());
+        assert!(completion::completion_context("// see a.b", 10).is_none());
+        let context = completion::completion_context("/* it's */ Sys", 14).expect("context");
+        assert_eq!(context.prefix, "Sys");
+
+        assert_eq!(completion::match_rank("toString", "toSt"), Some(0));
+        assert_eq!(completion::match_rank("toString", "tost"), Some(1));
+        assert!(completion::match_rank("getClassLoader", "gCL").is_some());
+        assert_eq!(completion::match_rank("toString", "xyz"), None);
+    }
+
+    #[test]
+    fn registers_intrinsics() {
+        let intrinsics = intrinsics::default_intrinsics();
+        for name in [
+            "typeof", "classOf", "len", "str", "identity", "fields", "methods", "supers", "describe",
+            "sleep", "time", "gc",
+        ] {
+            let intrinsic = intrinsics.get(name).expect("registered");
+            assert!(!intrinsic.description.is_empty());
+        }
+        assert_eq!(intrinsics["len"].signature(), "len(x)");
+        assert_eq!(intrinsics["time"].signature(), "time()");
+    }
+
+    #[test]
+    fn applies_binary_numeric_promotion() {
+        let result =
+            operators::apply_binary_operator(BinaryOperator::Add, JValue::Char(1), JValue::Byte(2));
+        assert!(matches!(result, Ok(JValue::Int(3))));
+        let result =
+            operators::apply_binary_operator(BinaryOperator::Divide, JValue::Int(7), JValue::Long(2));
+        assert!(matches!(result, Ok(JValue::Long(3))));
+        let result = operators::apply_binary_operator(
+            BinaryOperator::Multiply,
+            JValue::Float(1.5),
+            JValue::Double(2.0),
+        );
+        assert!(matches!(result, Ok(JValue::Double(d)) if d == 3.0));
+        let result =
+            operators::apply_binary_operator(BinaryOperator::Remainder, JValue::Int(1), JValue::Int(0));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn applies_assignment_conversion() {
+        assert!(matches!(
+            conversion::convert_primitive(JValue::Int(100), 'B', true),
+            Ok(JValue::Byte(100))
+        ));
+        assert!(matches!(
+            conversion::convert_primitive(JValue::Int(300), 'B', false),
+            Ok(JValue::Byte(44))
+        ));
+        assert!(conversion::convert_primitive(JValue::Int(300), 'B', true).is_err());
+        assert!(matches!(
+            conversion::convert_primitive(JValue::Int(2), 'D', true),
+            Ok(JValue::Double(d)) if d == 2.0
+        ));
+        assert!(conversion::convert_primitive(JValue::Int(1), 'Z', false).is_err());
+    }
+
+    #[test]
+    fn splits_parameter_descriptors() {
+        assert_eq!(
+            conversion::parameter_descriptors("(I[JLjava/lang/String;[[Ljava/lang/Object;Z)V"),
+            ["I", "[J", "Ljava/lang/String;", "[[Ljava/lang/Object;", "Z"]
+        );
+        assert!(conversion::parameter_descriptors("()V").is_empty());
+    }
+
+    const IDENTS: &[&str] = &["a", "b", "list", "x_1", "Value$1"];
+
+    fn slat_expr() -> impl Strategy<Value = String> {
+        let leaf = prop_oneof![
+            prop::sample::select(IDENTS).prop_map(str::to_owned),
+            any::<i32>().prop_map(|i| i.to_string()),
+            any::<i64>().prop_map(|l| format!("{}L", l)),
+            any::<f64>().prop_filter("finite", |d| d.is_finite()).prop_map(|d| format!("{:?}", d)),
+            prop::sample::select(&[
+                "0x1F", "0b101", "1_000", "1e3", ".5", "2f", "3.0d", "'a'", "'\\n'", "'\\u0041'",
+                "\"\"", "\"a\\\"b\"", "\"é\\t\\101\"", "true", "false", "null", "@0", "@12",
+            ][..]).prop_map(str::to_owned),
+        ];
+        leaf.prop_recursive(4, 48, 4, |inner| {
+            let args = prop::collection::vec(inner.clone(), 0..3).prop_map(|args| args.join(", "));
+            prop_oneof![
+                (inner.clone(), prop::sample::select(&[
+                    "+", "-", "*", "/", "%", "==", "!=", "<", "<=", ">", ">=", "&&", "||",
+                ][..]), inner.clone()).prop_map(|(l, op, r)| format!("{} {} {}", l, op, r)),
+                inner.clone().prop_map(|e| format!("({})", e)),
+                (prop::sample::select(&["-", "!"][..]), inner.clone()).prop_map(|(op, e)| format!("{}{}", op, e)),
+                (inner.clone(), prop::sample::select(&[".size()", ".length", "[0]", "[1:]", "[:2]", "[:]"][..]))
+                    .prop_map(|(e, member)| format!("({}){}", e, member)),
+                (prop::sample::select(IDENTS), inner.clone(), args.clone())
+                    .prop_map(|(owner, index, args)| format!("{}[{}].get({}).field", owner, index, args)),
+                args.clone().prop_map(|args| format!("f({})", args)),
+                args.clone().prop_map(|args| format!("new java.util.ArrayList({}).iterator()", args)),
+                inner.clone().prop_map(|e| format!("new int[{}][]", e)),
+                args.prop_map(|args| format!("[{}]", args)),
+                inner.clone().prop_map(|e| format!("(x -> {})", e)),
+                inner.clone().prop_map(|e| format!("((a, b) -> {{\nreturn {}\n}})", e)),
+                inner.clone().prop_map(|e| format!("((Runnable) () -> a = {})", e)),
+                inner.clone().prop_map(|e| format!("/* e */ {}", e)),
+                inner.prop_map(|e| format!("f(\n{} // e\n)", e)),
+            ]
+        })
+    }
+
+    fn slat_statement() -> impl Strategy<Value = String> {
+        let leaf = prop_oneof![
+            slat_expr(),
+            slat_expr().prop_map(|e| format!("x = {}", e)),
+            slat_expr().prop_map(|e| format!("a.b[0] = {}", e)),
+            slat_expr().prop_map(|e| format!("#timeout {}", e)),
+            slat_expr().prop_map(|e| format!("throw {}", e)),
+            prop::sample::select(&[
+                "import java.util.*", "import java.util.List as L", "vars", "del a, b", "// c", "/* c */",
+                "/* c\n  d */",
+            ][..]).prop_map(str::to_owned),
+            slat_expr().prop_map(|e| format!("{} // c", e)),
+            slat_expr().prop_map(|e| format!("/* c */ {} /* d */", e)),
+        ];
+        leaf.prop_recursive(3, 16, 3, |inner| {
+            let block = prop::collection::vec(inner, 0..3)
+                .prop_map(|statements| format!("{{\n{}\n}}", statements.join("\n")));
+            prop_oneof![
+                (slat_expr(), block.clone(), block.clone())
+                    .prop_map(|(e, then, otherwise)| format!("if {} {} else {}", e, then, otherwise)),
+                (slat_expr(), block.clone()).prop_map(|(e, body)| format!("while {} {}", e, body)),
+                (slat_expr(), block.clone()).prop_map(|(e, body)| format!("for i in {} {}", e, body)),
+                block.clone().prop_map(|body| format!("fn f(a, b) {}", body)),
+                (block.clone(), block).prop_map(|(body, handler)| {
+                    format!("try {} catch (java.io.IOException e) {} catch (e) {{\nreturn\n}}", body, handler)
+                }),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn prints_parseable_source(statements in prop::collection::vec(slat_statement(), 1..4)) {
+            let source = statements.join("\n");
+            let ast = parser::parse(&source).expect("generated source parses");
+            let printed = printer::print(&ast);
+            let reparsed = parser::parse(&printed)
+                .unwrap_or_else(|error| panic!("{}\nin printed source\n{}", error, printed));
+            prop_assert_eq!(&reparsed, &ast, "printed source\n{}", printed);
+            // printing is idempotent
+            prop_assert_eq!(printer::print(&reparsed), printed);
+        }
+    }
+
+    #[test]
+    fn prints_canonical_source() {
+        let source = "if a{x=-(1)+ (b*c)} else if !a {f((y)->y, @1)}\nz=(a-b)-(c-d)\n(\"s\").length()";
+        let printed = printer::print(&parser::parse(source).expect("parsed"));
+        assert_eq!(
+            printed,
+            "if a {\n    x = -(1) + b * c\n} else if !a {\n    f(y -> y, @1)\n}\nz = a - b - (c - d)\n\"s\".length()\n"
+        );
+    }
+
+    struct Identifiers(Vec<String>);
+
+    impl Visitor for Identifiers {
+        type Error = Infallible;
+
+        fn visit_identifier(&mut self, name: &str) -> Result<(), Infallible> {
+            self.0.push(name.to_owned());
+            Ok(())
+        }
+    }
+
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        type Error = Infallible;
+
+        fn visit_identifier_mut(&mut self, name: &mut String) -> Result<(), Infallible> {
+            if name == "a" {
+                *name = "renamed".to_owned();
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn visits_every_node() {
+        let source = "fn f(x) {\n    return a + x[b:]\n}\nfor y in [c] {\n    g(y, () -> d)\n}\na = f(e)\n";
+        let mut tokens = parser::parse(source).expect("parsed");
+        let copy = tokens.clone();
+
+        let mut identifiers = Identifiers(Vec::new());
+        visit::walk_block(&mut identifiers, &tokens).unwrap();
+        assert_eq!(identifiers.0, ["a", "x", "b", "c", "y", "d", "a", "e"]);
+
+        for token in &mut tokens {
+            Rename.visit_token_mut(token).unwrap();
+        }
+        assert_eq!(
+            printer::print(&tokens),
+            source.replace("a ", "renamed ")
+        );
+        assert_ne!(tokens, copy);
+    }
+
+    struct FakeOracle;
+
+    impl ReflectionOracle for FakeOracle {
+        fn lookup_class(&mut self, name: &str) -> anyhow::Result<Option<String>> {
+            let classes = ["java.lang.String", "java.lang.System", "java.util.ArrayList"];
+            Ok(classes.contains(&name).then(|| name.replace('.', "/")))
+        }
+
+        fn has_field(&mut self, class: &str, name: &str, is_static: bool) -> anyhow::Result<bool> {
+            Ok((class, name, is_static) == ("java/lang/System", "out", true))
+        }
+
+        fn methods(&mut self, class: &str, name: &str, is_static: bool) -> anyhow::Result<Vec<Arity>> {
+            let arity = |parameters, var_args| Arity { parameters, var_args };
+            Ok(match (class, name, is_static) {
+                ("java/lang/String", "length", false) => vec![arity(0, false)],
+                ("java/lang/String", "format", true) => vec![arity(2, true)],
+                ("java/util/ArrayList", "<init>", _) => vec![arity(0, false), arity(1, false)],
+                ("java/util/ArrayList", "add", false) => vec![arity(1, false), arity(2, false)],
+                _ => Vec::new(),
+            })
+        }
+    }
+
+    /// Formats diagnostics as `line:message`, marking warnings.
+    fn messages(diagnostics: Vec<Diagnostic>) -> Vec<String> {
+        diagnostics
+            .into_iter()
+            .map(|diagnostic| match diagnostic.severity {
+                Severity::Error => format!("{}:{}", diagnostic.span.start.line, diagnostic.message),
+                Severity::Warning => {
+                    format!("{}:warning: {}", diagnostic.span.start.line, diagnostic.message)
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn checks_scripts() {
+        let source = "import java.util.ArrayList\nimport java.lang.String as ArrayList\n#dpeth 3\n\
+            list = new ArrayList(1, 2)\nlist.add(x)\n\"s\".length(1)\nString.format(\"%d\")\n\
+            System.out2\nFoo.bar()\nlen(v, v)\n_5\nfn f() {\n    return w\n    g()\n}\nw = f()\n";
+        let tokens = parser::parse(source).expect("parsed");
+        let context = CheckContext {
+            variables: ["v".to_owned()].into(),
+            intrinsics: HashMap::from([("len".to_owned(), 1)]),
+            directives: ["depth".to_owned()].into(),
+            wildcard_imports: vec!["java.lang".to_owned()],
+            results: 1..3,
+            ..Default::default()
+        };
+        let diagnostics = check::check(&tokens, &context, Some(&mut FakeOracle)).unwrap();
+        assert_eq!(
+            messages(diagnostics),
+            [
+                "2:warning: A different class has already been imported with name 'ArrayList', use 'import ... as' to rename one of them",
+                "3:Unknown directive '#dpeth'",
+                "4:Constructor of 'ArrayList' expects 0 or 1 argument(s) but was given 2",
+                "5:Unknown identifier 'x'",
+                "6:Method 'length' expects 0 argument(s) but was given 1",
+                "8:Could not resolve field 'out2'",
+                "9:Unknown identifier 'Foo'",
+                "10:Function 'len' expects 1 argument(s) but was given 2",
+                "11:No result '_5' in the history",
+                "13:Unknown identifier 'w'",
+                "14:warning: Unreachable code",
+                "14:No function with name 'g' has been defined",
+            ]
+        );
+
+        // without an oracle, only what can be told from the script and its session
+        let diagnostics = check::check(&tokens, &context, None).unwrap();
+        assert_eq!(
+            messages(diagnostics),
+            [
+                "2:warning: A different class has already been imported with name 'ArrayList', use 'import ... as' to rename one of them",
+                "3:Unknown directive '#dpeth'",
+                "5:Unknown identifier 'x'",
+                "10:Function 'len' expects 1 argument(s) but was given 2",
+                "11:No result '_5' in the history",
+                "13:Unknown identifier 'w'",
+                "14:warning: Unreachable code",
+                "14:No function with name 'g' has been defined",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_bodies_when_called() {
+        let source = "fn report() {\n    return total\n    total = 0\n}\n";
+        let tokens = parser::parse(source).expect("parsed");
+        let diagnostics = check::check(&tokens, &CheckContext::default(), None).unwrap();
+        // the global may be defined before the function is called
+        assert_eq!(
+            messages(diagnostics),
+            ["2:warning: Unknown identifier 'total'", "3:warning: Unreachable code"]
+        );
+
+        let tokens = parser::parse(&format!("{}report()\n", source)).expect("parsed");
+        let diagnostics = check::check(&tokens, &CheckContext::default(), None).unwrap();
+        assert_eq!(
+            messages(diagnostics),
+            ["2:Unknown identifier 'total'", "3:warning: Unreachable code"]
+        );
+    }
+
+    #[test]
+    fn runs_scripts_with_warnings() {
+        let mut interpreter = SlatInterpreter::new(crate::test::jvm::create_mock_jvm());
+        assert_eq!(evaluate(&mut interpreter, "fn report() {\n    return total\n    total = 0\n}"), Ok(None));
+        assert_eq!(
+            evaluate(&mut interpreter, "report()"),
+            Err("Unknown identifier 'total'".to_owned())
+        );
+        // a global defined by a later request is seen by the function
+        assert_eq!(evaluate(&mut interpreter, "total = 3\nreport()"), Ok(Some(Value::Integer(3))));
+    }
 }
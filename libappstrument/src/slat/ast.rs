@@ -1,42 +1,298 @@
-#[derive(Debug)]
+use std::rc::Rc;
+
+/// A location in SLAT source. Lines and columns start at 1 and columns count characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The source range a node was parsed from; `end` is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    /// The span from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
+
+/// A literal, typed the way Java types it: `1` is an `int`, `1L` a `long`,
+/// `1.5f` a `float` and `1.5` a `double`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Literal {
-    Integer(i64),
-    Decimal(f64),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    Char(u16),
     String(String),
     Boolean(bool),
+    Null,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MethodCall {
     pub name: String,
     pub args: Vec<Token>,
 }
 
-#[derive(Debug)]
-pub struct ArrayExpression {
-    pub array: Box<Token>,
-    pub index: Box<Token>,
+/// `import a.b.C`, `import a.b.*` or `import a.b.C as D`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Import {
+    pub path: Vec<String>,
+    pub wildcard: bool,
+    pub alias: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NewExpression {
+    pub class_name: String,
+    pub args: Vec<Token>,
+}
+
+/// `new int[3][]`: the lengths of the leading dimensions, followed by the
+/// number of dimensions left unallocated.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NewArray {
+    pub class_name: String,
+    pub dimensions: Vec<Token>,
+    pub unsized_dimensions: usize,
+}
+
+/// `[start:end]`, where either bound may be left out.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Slice {
+    pub start: Option<Box<Token>>,
+    pub end: Option<Box<Token>>,
+}
+
+/// One step of a postfix chain such as `owner.field.call()[index]`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MemberKind {
+    Field(String),
+    MethodCall(MethodCall),
+    Index(Box<Token>),
+    Slice(Slice),
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Member {
+    pub kind: MemberKind,
+    pub span: Span,
 }
 
-#[derive(Debug)]
+impl PartialEq for Member {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MemberExpression {
     pub owner: Box<Token>,
-    pub members: Vec<Token>,
+    pub members: Vec<Member>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Assignment {
-    pub variable: String,
+    /// A variable name, or a member expression ending in a field or an index.
+    pub target: Box<Token>,
     pub expr: Box<Token>,
 }
 
-#[derive(Debug)]
-pub enum Token {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnaryOperator {
+    Negate,
+    Not,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BinaryExpression {
+    pub operator: BinaryOperator,
+    pub left: Box<Token>,
+    pub right: Box<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnaryExpression {
+    pub operator: UnaryOperator,
+    pub operand: Box<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Directive {
+    pub name: String,
+    pub value: Box<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IfStatement {
+    pub condition: Box<Token>,
+    pub body: Vec<Token>,
+    pub else_body: Option<Vec<Token>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WhileLoop {
+    pub condition: Box<Token>,
+    pub body: Vec<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ForEachLoop {
+    pub variable: String,
+    pub iterable: Box<Token>,
+    pub body: Vec<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub parameters: Vec<String>,
+    pub body: Vec<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LambdaBody {
+    Expression(Box<Token>),
+    Block(Vec<Token>),
+}
+
+/// `(a, b) -> expr` or `x -> { ... }`. The functional interface it implements is
+/// given by a cast, `(Runnable) () -> ...`, or else inferred from where it is used.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Lambda {
+    pub parameters: Vec<String>,
+    pub body: LambdaBody,
+    pub interface: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReturnStatement {
+    pub value: Option<Box<Token>>,
+}
+
+/// `catch (e) { }` catches any Java exception, `catch (IOException e) { }` only
+/// instances of the given class.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CatchClause {
+    pub class_name: Option<String>,
+    pub variable: String,
+    pub body: Vec<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TryStatement {
+    pub body: Vec<Token>,
+    pub catch_clauses: Vec<CatchClause>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ThrowStatement {
+    pub value: Box<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TokenKind {
     Identifier(String),
     Literal(Literal),
-    Import(Vec<String>),
-    ArrayExpression(ArrayExpression),
+    Import(Import),
     MemberExpression(MemberExpression),
     MethodCall(MethodCall),
+    New(NewExpression),
+    NewArray(NewArray),
+    /// `[1, 2, 3]`, whose component type is inferred from the elements.
+    ArrayLiteral(Vec<Token>),
+    BinaryExpression(BinaryExpression),
+    UnaryExpression(UnaryExpression),
     Assignment(Assignment),
+    Directive(Directive),
+    If(IfStatement),
+    While(WhileLoop),
+    ForEach(ForEachLoop),
+    // shared so that the interpreter can keep definitions after the AST is dropped
+    Function(Rc<FunctionDefinition>),
+    Return(ReturnStatement),
+    Try(TryStatement),
+    Throw(ThrowStatement),
+    // shared so that lambdas can still be invoked after the AST is dropped
+    Lambda(Rc<Lambda>),
+    /// `@N`, an object handed out to the client with the given id.
+    ObjectHandle(usize),
+    /// `vars`, which lists the variables of the current scope.
+    Vars,
+    /// `del a, b`, which removes variables from the current scope.
+    Delete(Vec<String>),
+    /// `// ...` or `/* ... */`, kept as a statement so that printing a script keeps
+    /// its comments. Comments inside a statement come just before it.
+    Comment(String),
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+// nodes compare by what they are rather than where they were parsed from, so the
+// same script formatted differently parses to equal trees
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
 }
@@ -0,0 +1,84 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Cancels requests evaluated by an interpreter from another thread. The
+/// interpreter checks for cancellation between evaluation steps, so a Java
+/// method that never returns can't be cancelled.
+///
+/// Requests are expected to be evaluated in the order of their ids, as clients
+/// number the requests of a connection one after another.
+#[derive(Clone, Default)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+    requests: Arc<Mutex<Requests>>,
+}
+
+#[derive(Default)]
+struct Requests {
+    /// The id of the request being evaluated.
+    running: Option<i32>,
+    /// The id of the latest request whose evaluation started, earlier ones are done.
+    latest: Option<i32>,
+    /// Requests cancelled before their evaluation started.
+    pending: HashSet<i32>,
+    /// Whether every request is cancelled, see `cancel_all`.
+    all: bool,
+}
+
+impl CancelHandle {
+    /// Cancels the request with the given id, either at the next evaluation step
+    /// or as soon as it starts. Returns whether the request is being evaluated.
+    pub fn cancel(&self, request_id: i32) -> bool {
+        let mut requests = self.requests.lock().expect("cancel handle poisoned");
+        if requests.running == Some(request_id) {
+            self.cancelled.store(true, Ordering::Relaxed);
+            true
+        } else {
+            // a request that already finished has nothing left to cancel
+            if requests.latest.is_none_or(|latest| request_id > latest) {
+                requests.pending.insert(request_id);
+            }
+            false
+        }
+    }
+
+    /// Cancels the request being evaluated and every request evaluated
+    /// afterwards, for when no one is waiting for their responses anymore.
+    pub fn cancel_all(&self) {
+        let mut requests = self.requests.lock().expect("cancel handle poisoned");
+        requests.all = true;
+        if requests.running.is_some() {
+            self.cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether the request being evaluated has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Marks the request as being evaluated, which cancels it right away if it
+    /// was cancelled while waiting.
+    pub fn begin(&self, request_id: Option<i32>) {
+        let mut requests = self.requests.lock().expect("cancel handle poisoned");
+        let cancelled = request_id.is_some_and(|id| requests.pending.remove(&id) || requests.all);
+        if let Some(id) = request_id {
+            // requests skipped on the way were no SLAT requests, which can't be cancelled
+            requests.pending.retain(|&pending| pending > id);
+            requests.latest = Some(id);
+        }
+        requests.running = request_id;
+        self.cancelled.store(cancelled, Ordering::Relaxed);
+    }
+
+    pub fn finish(&self) {
+        let mut requests = self.requests.lock().expect("cancel handle poisoned");
+        requests.running = None;
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+}
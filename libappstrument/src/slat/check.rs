@@ -0,0 +1,911 @@
+//! Finds mistakes in a script before any of it runs, so that a typo on its last
+//! line doesn't leave the app half-modified by the lines before it.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    rc::Rc,
+};
+
+use super::{
+    ast::{
+        Assignment, CatchClause, Directive, ForEachLoop, FunctionDefinition, Import, Lambda,
+        LambdaBody, Literal, Member, MemberExpression, MemberKind, MethodCall, NewArray,
+        NewExpression, ReturnStatement, Span, Token, TokenKind, WhileLoop,
+    },
+    conversion,
+    diagnostic::{Diagnostic, Severity},
+    interpreter::InterpreterError,
+    visit::{self, Visitor},
+};
+
+/// What a script can use without defining it, from the session it is checked
+/// against.
+#[derive(Debug, Clone, Default)]
+pub struct CheckContext {
+    /// Variables defined by earlier scripts.
+    pub variables: HashSet<String>,
+    /// Functions defined by earlier scripts.
+    pub functions: HashMap<String, Rc<FunctionDefinition>>,
+    /// Intrinsics with their number of parameters.
+    pub intrinsics: HashMap<String, usize>,
+    /// JNI names of the classes imported by earlier scripts, by the name they were
+    /// imported as.
+    pub imports: HashMap<String, String>,
+    /// Packages imported on demand.
+    pub wildcard_imports: Vec<String>,
+    pub directives: HashSet<String>,
+    /// The N for which `_N` refers to a result.
+    pub results: Range<usize>,
+    /// The JNI name of the class of the object the script is evaluated relative
+    /// to, whose members it can use unqualified.
+    pub this_class: Option<String>,
+}
+
+/// How many arguments a method or constructor takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub parameters: usize,
+    /// Whether the last parameter is of variable arity.
+    pub var_args: bool,
+}
+
+impl Arity {
+    pub fn accepts(&self, arguments: usize) -> bool {
+        arguments == self.parameters || self.var_args && arguments + 1 >= self.parameters
+    }
+}
+
+/// Answers questions about the classes a script uses. Without one, the checker
+/// only finds mistakes it can tell from the script and its session alone.
+pub trait ReflectionOracle {
+    /// Looks up a class by its binary or source name, e.g. `java.util.Map.Entry`,
+    /// returning its JNI name such as `java/util/Map$Entry`.
+    fn lookup_class(&mut self, name: &str) -> anyhow::Result<Option<String>>;
+
+    /// Whether the class with the given JNI name has a static or an instance
+    /// field with the given name.
+    fn has_field(&mut self, class: &str, name: &str, is_static: bool) -> anyhow::Result<bool>;
+
+    /// The overloads of the static or instance methods with the given name, or of
+    /// the constructors for `<init>`. Empty if there are none.
+    fn methods(&mut self, class: &str, name: &str, is_static: bool) -> anyhow::Result<Vec<Arity>>;
+}
+
+/// The errors `check` found in a script, which is then not run.
+#[derive(thiserror::Error, Debug)]
+#[error("{}", .0.iter().map(|diagnostic| diagnostic.message.as_str()).collect::<Vec<_>>().join("\n"))]
+pub struct CheckError(pub Vec<Diagnostic>);
+
+/// What an expression is known to evaluate to.
+#[derive(Debug, Clone)]
+enum Value {
+    /// A class, as the owner of static members.
+    Class(String),
+    /// An object whose class is exactly the one given, rather than a subclass.
+    Object(String),
+}
+
+/// A function or lambda body, which runs after the script around it has.
+enum Deferred {
+    Function(Rc<FunctionDefinition>),
+    /// A lambda, with the variables of the function it was created in.
+    Lambda(Rc<Lambda>, HashSet<String>),
+}
+
+/// The variables and functions a list of statements defines.
+#[derive(Default)]
+struct Definitions {
+    variables: HashSet<String>,
+    functions: HashMap<String, Rc<FunctionDefinition>>,
+    /// Whether to look into function and lambda bodies.
+    nested: bool,
+}
+
+impl Visitor for Definitions {
+    type Error = std::convert::Infallible;
+
+    fn visit_assignment(&mut self, assignment: &Assignment) -> Result<(), Self::Error> {
+        if let TokenKind::Identifier(name) = &assignment.target.kind {
+            self.variables.insert(name.clone());
+        }
+        visit::walk_assignment(self, assignment)
+    }
+
+    fn visit_for_each_loop(&mut self, for_each: &ForEachLoop) -> Result<(), Self::Error> {
+        self.variables.insert(for_each.variable.clone());
+        visit::walk_for_each_loop(self, for_each)
+    }
+
+    fn visit_catch_clause(&mut self, catch_clause: &CatchClause) -> Result<(), Self::Error> {
+        self.variables.insert(catch_clause.variable.clone());
+        visit::walk_catch_clause(self, catch_clause)
+    }
+
+    fn visit_function(&mut self, function: &Rc<FunctionDefinition>) -> Result<(), Self::Error> {
+        self.functions
+            .insert(function.name.clone(), Rc::clone(function));
+        if self.nested {
+            visit::walk_function(self, function)?;
+        }
+        Ok(())
+    }
+
+    fn visit_lambda(&mut self, lambda: &Rc<Lambda>) -> Result<(), Self::Error> {
+        if self.nested {
+            visit::walk_lambda(self, lambda)?;
+        }
+        Ok(())
+    }
+}
+
+fn definitions(body: &[Token], nested: bool) -> Definitions {
+    let mut definitions = Definitions {
+        nested,
+        ..Default::default()
+    };
+    let Ok(()) = visit::walk_block(&mut definitions, body);
+    definitions
+}
+
+/// Whether running the statement never carries on with the one after it.
+fn completes_abruptly(token: &Token) -> bool {
+    let block_completes_abruptly = |body: &[Token]| body.iter().any(completes_abruptly);
+    match &token.kind {
+        TokenKind::Return(_) | TokenKind::Throw(_) => true,
+        TokenKind::If(if_statement) => match &if_statement.else_body {
+            Some(else_body) => {
+                block_completes_abruptly(&if_statement.body) && block_completes_abruptly(else_body)
+            }
+            None => false,
+        },
+        // there is no break, only a return or an exception ends `while true`
+        TokenKind::While(while_loop) => matches!(
+            while_loop.condition.kind,
+            TokenKind::Literal(Literal::Boolean(true))
+        ),
+        TokenKind::Try(try_statement) => {
+            block_completes_abruptly(&try_statement.body)
+                && try_statement
+                    .catch_clauses
+                    .iter()
+                    .all(|catch_clause| block_completes_abruptly(&catch_clause.body))
+        }
+        _ => false,
+    }
+}
+
+/// Whether `name` is spelled like a history binding, `_` or `_N`.
+fn result_index(name: &str) -> Option<Option<usize>> {
+    match name.strip_prefix('_')? {
+        "" => Some(None),
+        digits if digits.bytes().all(|b| b.is_ascii_digit()) => Some(digits.parse().ok()),
+        _ => None,
+    }
+}
+
+/// The argument counts the overloads accept, e.g. `1, 2 or 4 or more`.
+fn describe_arities(arities: &[Arity]) -> String {
+    let mut counts: Vec<(usize, bool)> = arities
+        .iter()
+        .map(|arity| match arity.var_args {
+            true => (arity.parameters.saturating_sub(1), true),
+            false => (arity.parameters, false),
+        })
+        .collect();
+    counts.sort_unstable();
+    counts.dedup();
+    let counts: Vec<String> = counts
+        .into_iter()
+        .map(|(count, var_args)| match var_args {
+            true => format!("{} or more", count),
+            false => count.to_string(),
+        })
+        .collect();
+    match counts.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+        None => "no".to_owned(),
+    }
+}
+
+struct Checker<'a> {
+    context: &'a CheckContext,
+    oracle: Option<&'a mut dyn ReflectionOracle>,
+    diagnostics: Vec<Diagnostic>,
+    /// The span of the token being checked.
+    span: Span,
+    /// Variables of the session, including those the script has defined so far.
+    globals: HashSet<String>,
+    /// Counts the changes to `globals`, which function bodies are checked against
+    /// when the script calls them.
+    globals_version: usize,
+    /// Every variable the top level of the script defines, which function and
+    /// lambda bodies may use since they run afterwards.
+    script_globals: HashSet<String>,
+    /// Functions the script has defined so far.
+    functions: HashMap<String, Rc<FunctionDefinition>>,
+    /// Every function the script defines.
+    script_functions: HashMap<String, Rc<FunctionDefinition>>,
+    /// Imports by the name they are imported as, to the JNI name of the class.
+    imports: HashMap<String, String>,
+    wildcard_imports: Vec<String>,
+    /// The variables of the function or lambda body being checked, if any.
+    frame: Option<HashSet<String>>,
+    /// Whether the body being checked is that of a function the script calls, so
+    /// that it runs against the session as it is at the call.
+    called: bool,
+    /// The functions whose bodies are being checked for a call, innermost last.
+    calls: Vec<String>,
+    /// The functions checked for a call, with the `globals_version` they were
+    /// checked against.
+    checked_calls: HashSet<(String, usize)>,
+    deferred: Vec<Deferred>,
+    /// What the latest expression checked evaluates to, if known.
+    value: Option<Value>,
+}
+
+impl<'a> Checker<'a> {
+    fn report(&mut self, span: Span, message: impl ToString) {
+        self.diagnostics.push(Diagnostic {
+            message: message.to_string(),
+            span,
+            severity: Severity::Error,
+        });
+    }
+
+    fn warn(&mut self, span: Span, message: impl ToString) {
+        self.diagnostics.push(Diagnostic {
+            message: message.to_string(),
+            span,
+            severity: Severity::Warning,
+        });
+    }
+
+    /// Reports a name that isn't defined, which is only a warning in a body that
+    /// runs later, since an earlier request may define it by then.
+    fn report_unknown(&mut self, span: Span, error: InterpreterError) {
+        if self.frame.is_some() && !self.called {
+            self.warn(span, error);
+        } else {
+            self.report(span, error);
+        }
+    }
+
+    fn define(&mut self, name: &str) {
+        match &mut self.frame {
+            Some(frame) => {
+                frame.insert(name.to_owned());
+            }
+            None => {
+                if self.globals.insert(name.to_owned()) {
+                    self.globals_version += 1;
+                }
+            }
+        }
+    }
+
+    /// Defines the variables and functions of a loop body up front, since a later
+    /// iteration may use what an earlier one defined further down.
+    fn define_ahead(&mut self, body: &[Token]) {
+        let definitions = definitions(body, false);
+        for name in &definitions.variables {
+            self.define(name);
+        }
+        if self.frame.is_none() || self.called {
+            self.functions.extend(definitions.functions);
+        }
+    }
+
+    fn is_variable(&self, name: &str) -> bool {
+        match &self.frame {
+            Some(frame) if self.called => frame.contains(name) || self.globals.contains(name),
+            Some(frame) => {
+                frame.contains(name)
+                    || self.context.variables.contains(name)
+                    || self.script_globals.contains(name)
+            }
+            None => self.globals.contains(name),
+        }
+    }
+
+    /// The function a call runs, which a body that runs later may find among every
+    /// function the script defines.
+    fn function(&self, name: &str) -> Option<&Rc<FunctionDefinition>> {
+        let functions = match self.frame {
+            Some(_) if !self.called => &self.script_functions,
+            _ => &self.functions,
+        };
+        functions
+            .get(name)
+            .or_else(|| self.context.functions.get(name))
+    }
+
+    fn function_parameters(&self, name: &str) -> Option<usize> {
+        match self.function(name) {
+            Some(function) => Some(function.parameters.len()),
+            None => self.context.intrinsics.get(name).copied(),
+        }
+    }
+
+    /// Whether `this` has a field with the given name, or `None` if that can't be told.
+    fn is_this_field(&mut self, name: &str) -> anyhow::Result<Option<bool>> {
+        let (this, oracle) = match (&self.context.this_class, self.oracle.as_deref_mut()) {
+            (Some(this), Some(oracle)) => (this, oracle),
+            (Some(_), None) => return Ok(None),
+            (None, _) => return Ok(Some(false)),
+        };
+        Ok(Some(
+            oracle.has_field(this, name, false)? || oracle.has_field(this, name, true)?,
+        ))
+    }
+
+    /// Resolves a class name the way the interpreter does, see
+    /// `SlatInterpreter::resolve_class`. Always `None` without an oracle.
+    fn resolve_class(&mut self, name: &str) -> anyhow::Result<Option<String>> {
+        let oracle = match self.oracle.as_deref_mut() {
+            Some(oracle) => oracle,
+            None => return Ok(None),
+        };
+        if let Some(class) = self.imports.get(name) {
+            return Ok(Some(class.clone()));
+        }
+        let (outer, nested) = name.split_at(name.find(['.', '$']).unwrap_or(name.len()));
+        if let Some(class) = self.imports.get(outer) {
+            return oracle.lookup_class(&format!("{}{}", class.replace('/', "."), nested));
+        }
+        let mut found = Vec::new();
+        for package in &self.wildcard_imports {
+            if let Some(class) = oracle.lookup_class(&format!("{}.{}", package, outer))? {
+                found.push(class);
+            }
+        }
+        match &found[..] {
+            [] if nested.is_empty() => Ok(None),
+            [] => oracle.lookup_class(name),
+            [class] => oracle.lookup_class(&format!("{}{}", class.replace('/', "."), nested)),
+            _ => {
+                let candidates = found
+                    .iter()
+                    .map(|class| class.replace('/', "."))
+                    .collect::<Vec<_>>();
+                let error =
+                    InterpreterError::AmbiguousClass(outer.to_owned(), candidates.join(", "));
+                self.warn(self.span, error);
+                Ok(Some(found.swap_remove(0)))
+            }
+        }
+    }
+
+    /// Reports a class that doesn't exist. Nothing is reported without an oracle.
+    fn check_class(&mut self, name: &str) -> anyhow::Result<Option<String>> {
+        let class = self.resolve_class(name)?;
+        if class.is_none() && self.oracle.is_some() {
+            self.report(self.span, InterpreterError::NoSuchClass(name.to_owned()));
+        }
+        Ok(class)
+    }
+
+    fn check_arguments(
+        &mut self,
+        span: Span,
+        description: String,
+        arities: &[Arity],
+        arguments: usize,
+    ) {
+        if !arities.iter().any(|arity| arity.accepts(arguments)) {
+            let message = format!(
+                "{} expects {} argument(s) but was given {}",
+                description,
+                describe_arities(arities),
+                arguments
+            );
+            self.report(span, message);
+        }
+    }
+
+    /// Checks the owner of a postfix chain, which is a class if it is a name that
+    /// isn't a variable, and returns the members left to check.
+    fn check_member_owner<'m>(
+        &mut self,
+        owner: &Token,
+        members: &'m [Member],
+    ) -> anyhow::Result<&'m [Member]> {
+        let name = match &owner.kind {
+            TokenKind::Identifier(name) => name,
+            _ => {
+                self.visit_token(owner)?;
+                return Ok(members);
+            }
+        };
+        let is_this = name == "this" && self.context.this_class.is_some();
+        if self.is_variable(name) || is_this || result_index(name).is_some() {
+            self.visit_token(owner)?;
+            return Ok(members);
+        }
+        match self.is_this_field(name)? {
+            Some(false) => {}
+            // a field of `this`, or nothing can be told without an oracle
+            _ => return Ok(members),
+        }
+        if self.oracle.is_none() {
+            return Ok(members);
+        }
+
+        let span = self.span;
+        self.span = owner.span;
+        let class = self.resolve_class(name)?;
+        self.span = span;
+        if let Some(class) = class {
+            self.value = Some(Value::Class(class));
+            return Ok(members);
+        }
+        let mut qualified_name = name.clone();
+        for (i, member) in members.iter().enumerate() {
+            let segment = match &member.kind {
+                MemberKind::Field(segment) => segment,
+                _ => break,
+            };
+            qualified_name.push('.');
+            qualified_name.push_str(segment);
+            let oracle = self.oracle.as_deref_mut().expect("unreachable");
+            if let Some(class) = oracle.lookup_class(&qualified_name)? {
+                self.value = Some(Value::Class(class));
+                return Ok(&members[i + 1..]);
+            }
+        }
+        self.report_unknown(
+            owner.span,
+            InterpreterError::UnknownIdentifier(name.clone()),
+        );
+        Ok(members)
+    }
+
+    fn check_member(
+        &mut self,
+        owner: Option<Value>,
+        member: &Member,
+    ) -> anyhow::Result<Option<Value>> {
+        let (class, is_static) = match &owner {
+            Some(Value::Class(class)) => (class.as_str(), true),
+            Some(Value::Object(class)) => (class.as_str(), false),
+            None => ("", false),
+        };
+        match &member.kind {
+            MemberKind::Field(name) => {
+                let oracle = match (&owner, self.oracle.as_deref_mut()) {
+                    (Some(_), Some(oracle)) => oracle,
+                    _ => return Ok(None),
+                };
+                if oracle.has_field(class, name, is_static)? {
+                    return Ok(None);
+                }
+                // like in Java, a field shadows a nested class of the same name
+                if is_static {
+                    let nested = format!("{}${}", class.replace('/', "."), name);
+                    if let Some(nested) = oracle.lookup_class(&nested)? {
+                        return Ok(Some(Value::Class(nested)));
+                    }
+                }
+                self.report(member.span, InterpreterError::NoSuchField(name.clone()));
+            }
+            MemberKind::MethodCall(method_call) => {
+                visit::walk_method_call(self, method_call)?;
+                let oracle = match (&owner, self.oracle.as_deref_mut()) {
+                    (Some(_), Some(oracle)) => oracle,
+                    _ => return Ok(None),
+                };
+                let arities = oracle.methods(class, &method_call.name, is_static)?;
+                if arities.is_empty() {
+                    let error = InterpreterError::NoSuchMethod(method_call.name.clone());
+                    self.report(member.span, error);
+                } else {
+                    let description = format!("Method '{}'", method_call.name);
+                    self.check_arguments(
+                        member.span,
+                        description,
+                        &arities,
+                        method_call.args.len(),
+                    );
+                }
+            }
+            _ => visit::walk_member(self, member)?,
+        }
+        Ok(None)
+    }
+
+    /// Checks an argument of an intrinsic, where a class name stands for its
+    /// `Class` object.
+    fn check_intrinsic_argument(&mut self, arg: &Token) -> anyhow::Result<()> {
+        if let TokenKind::Identifier(name) = &arg.kind {
+            if !self.is_variable(name) && result_index(name).is_none() {
+                let span = self.span;
+                self.span = arg.span;
+                let class = self.resolve_class(name)?;
+                self.span = span;
+                if class.is_some() {
+                    return Ok(());
+                }
+            }
+        }
+        self.visit_token(arg)
+    }
+
+    /// Checks the body of a function the script calls, against the variables and
+    /// functions defined by the time of the call.
+    fn check_call(&mut self, function: &Rc<FunctionDefinition>) -> anyhow::Result<()> {
+        let key = (function.name.clone(), self.globals_version);
+        if self.calls.contains(&function.name) || !self.checked_calls.insert(key) {
+            return Ok(());
+        }
+        let frame = self
+            .frame
+            .replace(function.parameters.iter().cloned().collect());
+        let called = std::mem::replace(&mut self.called, true);
+        let span = self.span;
+        self.calls.push(function.name.clone());
+        let result = self.visit_block(&function.body);
+        self.calls.pop();
+        self.span = span;
+        self.called = called;
+        self.frame = frame;
+        self.value = None;
+        result
+    }
+
+    fn check_deferred(&mut self) -> anyhow::Result<()> {
+        while let Some(deferred) = self.deferred.pop() {
+            match deferred {
+                Deferred::Function(function) => {
+                    self.frame = Some(function.parameters.iter().cloned().collect());
+                    self.visit_block(&function.body)?;
+                }
+                Deferred::Lambda(lambda, mut captured) => {
+                    captured.extend(lambda.parameters.iter().cloned());
+                    self.frame = Some(captured);
+                    match &lambda.body {
+                        LambdaBody::Expression(expr) => self.visit_token(expr)?,
+                        LambdaBody::Block(body) => self.visit_block(body)?,
+                    }
+                }
+            }
+        }
+        self.frame = None;
+        Ok(())
+    }
+}
+
+impl<'a> Visitor for Checker<'a> {
+    type Error = anyhow::Error;
+
+    fn visit_token(&mut self, token: &Token) -> anyhow::Result<()> {
+        let span = self.span;
+        self.span = token.span;
+        self.value = None;
+        let result = visit::walk_token(self, token);
+        self.span = span;
+        // only these leave what they evaluate to behind
+        if !matches!(
+            token.kind,
+            TokenKind::Identifier(_)
+                | TokenKind::Literal(_)
+                | TokenKind::New(_)
+                | TokenKind::MemberExpression(_)
+        ) {
+            self.value = None;
+        }
+        result
+    }
+
+    fn visit_block(&mut self, body: &[Token]) -> anyhow::Result<()> {
+        let mut reachable = true;
+        let is_code = |token: &&Token| !matches!(token.kind, TokenKind::Comment(_));
+        for (i, token) in body.iter().enumerate() {
+            if !is_code(&token) {
+                continue;
+            }
+            if !reachable {
+                let last = body.iter().rfind(is_code).expect("unreachable");
+                let span = token.span.to(last.span);
+                self.warn(span, "Unreachable code");
+                return visit::walk_block(self, &body[i..]);
+            }
+            self.visit_token(token)?;
+            reachable = !completes_abruptly(token);
+        }
+        Ok(())
+    }
+
+    fn visit_identifier(&mut self, name: &str) -> anyhow::Result<()> {
+        if self.is_variable(name) {
+            return Ok(());
+        }
+        if name == "this" {
+            if let Some(this) = &self.context.this_class {
+                self.value = Some(Value::Object(this.clone()));
+                return Ok(());
+            }
+        }
+        match self.is_this_field(name)? {
+            Some(false) => {}
+            _ => return Ok(()),
+        }
+        if let Some(index) = result_index(name) {
+            // the history may have changed by the time a function body runs
+            let results = &self.context.results;
+            let exists = match index {
+                Some(index) => results.contains(&index),
+                None => !results.is_empty(),
+            };
+            if !exists && self.frame.is_none() {
+                self.report(self.span, InterpreterError::UnknownResult(name.to_owned()));
+            }
+            return Ok(());
+        }
+        self.report_unknown(
+            self.span,
+            InterpreterError::UnknownIdentifier(name.to_owned()),
+        );
+        Ok(())
+    }
+
+    fn visit_literal(&mut self, literal: &Literal) -> anyhow::Result<()> {
+        if let Literal::String(_) = literal {
+            self.value = Some(Value::Object("java/lang/String".to_owned()));
+        }
+        Ok(())
+    }
+
+    fn visit_import(&mut self, import: &Import) -> anyhow::Result<()> {
+        let full_name = import.path.join(".");
+        if import.wildcard {
+            if import.alias.is_some() {
+                let message = "an on-demand import cannot be aliased".to_owned();
+                self.warn(self.span, InterpreterError::MalformedSlat(message));
+            } else if !self.wildcard_imports.contains(&full_name) {
+                self.wildcard_imports.push(full_name);
+            }
+            return Ok(());
+        }
+        let class = match self.oracle.as_deref_mut() {
+            Some(oracle) => match oracle.lookup_class(&full_name)? {
+                Some(class) => class,
+                None => {
+                    self.report(self.span, InterpreterError::NoSuchClass(full_name));
+                    return Ok(());
+                }
+            },
+            None => full_name.replace('.', "/"),
+        };
+        let name = match &import.alias {
+            Some(alias) => alias.clone(),
+            None => import.path[import.path.len() - 1].clone(),
+        };
+        match self.imports.get(&name) {
+            // importing the same class twice is harmless, nested classes can only be
+            // told apart from packages with an oracle
+            Some(existing) if existing.replace('$', "/") != class.replace('$', "/") => {
+                self.warn(self.span, InterpreterError::DuplicateImport(name));
+            }
+            _ => {
+                self.imports.insert(name, class);
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_member_expression(&mut self, member_expr: &MemberExpression) -> anyhow::Result<()> {
+        let members = self.check_member_owner(&member_expr.owner, &member_expr.members)?;
+        let mut value = self.value.take();
+        for member in members {
+            value = self.check_member(value, member)?;
+        }
+        self.value = value;
+        Ok(())
+    }
+
+    fn visit_method_call(&mut self, method_call: &MethodCall) -> anyhow::Result<()> {
+        let name = &method_call.name;
+        let arguments = method_call.args.len();
+        // methods of `this` shadow functions
+        if let Some(this) = &self.context.this_class {
+            let oracle = match self.oracle.as_deref_mut() {
+                Some(oracle) => oracle,
+                None => return visit::walk_method_call(self, method_call),
+            };
+            let mut arities = oracle.methods(this, name, false)?;
+            arities.extend(oracle.methods(this, name, true)?);
+            if !arities.is_empty() {
+                let description = format!("Method '{}'", name);
+                self.check_arguments(self.span, description, &arities, arguments);
+                return visit::walk_method_call(self, method_call);
+            }
+        }
+
+        let is_function =
+            self.script_functions.contains_key(name) || self.context.functions.contains_key(name);
+        let is_intrinsic = !is_function && self.context.intrinsics.contains_key(name);
+        match self.function_parameters(name) {
+            Some(parameters) if parameters != arguments => {
+                let error = InterpreterError::ArgumentCount(name.clone(), parameters, arguments);
+                self.report(self.span, error);
+            }
+            Some(_) => {}
+            None => self.report_unknown(self.span, InterpreterError::NoSuchFunction(name.clone())),
+        }
+        if is_intrinsic {
+            for arg in &method_call.args {
+                self.check_intrinsic_argument(arg)?;
+            }
+            return Ok(());
+        }
+        visit::walk_method_call(self, method_call)?;
+        // the body runs now, so it is checked against the session as it is now
+        if self.frame.is_none() || self.called {
+            if let Some(function) = self.function(name).cloned() {
+                self.check_call(&function)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_new(&mut self, new_expr: &NewExpression) -> anyhow::Result<()> {
+        visit::walk_new(self, new_expr)?;
+        let class = match self.check_class(&new_expr.class_name)? {
+            Some(class) => class,
+            None => return Ok(()),
+        };
+        let oracle = self.oracle.as_deref_mut().expect("unreachable");
+        let arities = oracle.methods(&class, "<init>", false)?;
+        if arities.is_empty() {
+            let error = InterpreterError::NoSuchConstructor(new_expr.class_name.clone());
+            self.report(self.span, error);
+        } else {
+            let description = format!("Constructor of '{}'", new_expr.class_name);
+            self.check_arguments(self.span, description, &arities, new_expr.args.len());
+        }
+        self.value = Some(Value::Object(class));
+        Ok(())
+    }
+
+    fn visit_new_array(&mut self, new_array: &NewArray) -> anyhow::Result<()> {
+        if conversion::primitive_descriptor_by_name(&new_array.class_name).is_none() {
+            self.check_class(&new_array.class_name)?;
+        }
+        visit::walk_new_array(self, new_array)
+    }
+
+    fn visit_assignment(&mut self, assignment: &Assignment) -> anyhow::Result<()> {
+        match &assignment.target.kind {
+            TokenKind::Identifier(name) => {
+                self.visit_token(&assignment.expr)?;
+                self.define(name);
+                Ok(())
+            }
+            _ => visit::walk_assignment(self, assignment),
+        }
+    }
+
+    fn visit_directive(&mut self, directive: &Directive) -> anyhow::Result<()> {
+        if !self.context.directives.contains(&directive.name) {
+            let error = InterpreterError::UnknownDirective(directive.name.clone());
+            self.report(self.span, error);
+        }
+        visit::walk_directive(self, directive)
+    }
+
+    fn visit_while_loop(&mut self, while_loop: &WhileLoop) -> anyhow::Result<()> {
+        self.define_ahead(&while_loop.body);
+        visit::walk_while_loop(self, while_loop)
+    }
+
+    fn visit_for_each_loop(&mut self, for_each: &ForEachLoop) -> anyhow::Result<()> {
+        self.visit_token(&for_each.iterable)?;
+        self.define(&for_each.variable);
+        self.define_ahead(&for_each.body);
+        self.visit_block(&for_each.body)
+    }
+
+    fn visit_function(&mut self, function: &Rc<FunctionDefinition>) -> anyhow::Result<()> {
+        if self.frame.is_none() || self.called {
+            self.functions
+                .insert(function.name.clone(), Rc::clone(function));
+        }
+        self.deferred.push(Deferred::Function(Rc::clone(function)));
+        Ok(())
+    }
+
+    fn visit_return(&mut self, return_statement: &ReturnStatement) -> anyhow::Result<()> {
+        if self.frame.is_none() {
+            let message = "return outside of a function".to_owned();
+            self.warn(self.span, InterpreterError::MalformedSlat(message));
+        }
+        visit::walk_return(self, return_statement)
+    }
+
+    fn visit_catch_clause(&mut self, catch_clause: &CatchClause) -> anyhow::Result<()> {
+        if let Some(class_name) = &catch_clause.class_name {
+            self.check_class(class_name)?;
+        }
+        self.define(&catch_clause.variable);
+        self.visit_block(&catch_clause.body)
+    }
+
+    fn visit_lambda(&mut self, lambda: &Rc<Lambda>) -> anyhow::Result<()> {
+        if let Some(interface) = &lambda.interface {
+            self.check_class(interface)?;
+        }
+        let captured = self.frame.clone().unwrap_or_default();
+        self.deferred
+            .push(Deferred::Lambda(Rc::clone(lambda), captured));
+        Ok(())
+    }
+
+    fn visit_delete(&mut self, names: &[String]) -> anyhow::Result<()> {
+        for name in names {
+            // variables can only be deleted from the scope they are defined in
+            let removed = match &mut self.frame {
+                Some(frame) => frame.remove(name),
+                None => {
+                    self.globals_version += 1;
+                    self.globals.remove(name)
+                }
+            };
+            if !removed {
+                self.report(self.span, InterpreterError::UnknownIdentifier(name.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks a parsed script against the session it is about to run in, returning
+/// every problem found, in the order they were found. Only errors stop a script
+/// from running, warnings point out code that runs but likely not as intended.
+///
+/// The body of a function the script calls is checked against the session as
+/// it is at the call. Function and lambda bodies are also checked last, when they
+/// may use any variable the script defines at its top level, but names they use
+/// that aren't defined by then are only warnings, since the session may define
+/// them before the body runs. Without an oracle, classes and their members aren't
+/// checked.
+pub fn check<'a>(
+    tokens: &[Token],
+    context: &'a CheckContext,
+    oracle: Option<&'a mut dyn ReflectionOracle>,
+) -> anyhow::Result<Vec<Diagnostic>> {
+    let mut checker = Checker {
+        context,
+        oracle,
+        diagnostics: Vec::new(),
+        span: Span::default(),
+        globals: context.variables.clone(),
+        globals_version: 0,
+        script_globals: definitions(tokens, false).variables,
+        functions: HashMap::new(),
+        script_functions: definitions(tokens, true).functions,
+        imports: context.imports.clone(),
+        wildcard_imports: context.wildcard_imports.clone(),
+        frame: None,
+        called: false,
+        calls: Vec::new(),
+        checked_calls: HashSet::new(),
+        deferred: Vec::new(),
+        value: None,
+    };
+    checker.visit_block(tokens)?;
+    checker.check_deferred()?;
+
+    // a body checked for a call and again later reports the same problems twice,
+    // and a name that is undefined at a call needn't be warned about as well
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    for diagnostic in checker.diagnostics {
+        let reported = diagnostics.iter().any(|reported| {
+            reported.span == diagnostic.span && reported.message == diagnostic.message
+        });
+        if !reported {
+            diagnostics.push(diagnostic);
+        }
+    }
+    Ok(diagnostics)
+}
@@ -0,0 +1,505 @@
+use std::collections::HashSet;
+
+use jni::{
+    objects::{JObject, JString, JValue},
+    JNIEnv,
+};
+
+use super::{conversion, interpreter::SlatInterpreter, parser};
+
+/// Candidates are ranked by kind after how well they match, in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CompletionKind {
+    Variable,
+    Field,
+    Method,
+    Function,
+    Class,
+    Package,
+    Keyword,
+}
+
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub kind: CompletionKind,
+    /// What the candidate is listed and matched as, e.g. a class's simple name.
+    pub label: String,
+    /// What replaces the word being completed, e.g. a class's qualified name.
+    pub insert_text: String,
+    /// The type of a variable or field, the declaration of a method or function,
+    /// or the qualified name of a class.
+    pub signature: String,
+    pub documentation: String,
+}
+
+/// Candidates for the word around the cursor, best first.
+#[derive(Debug, Default)]
+pub struct Completions {
+    pub candidates: Vec<Completion>,
+    /// The characters a candidate replaces.
+    pub replace_start: usize,
+    pub replace_end: usize,
+}
+
+/// What the members completed after `receiver.` come from.
+pub enum CompletionTarget {
+    /// The instance members of a class, or its static members and nested classes.
+    Members {
+        class: JObject<'static>,
+        is_static: bool,
+    },
+    /// The classes and subpackages of a package.
+    Package(String),
+}
+
+/// The word being completed, found without parsing since the code is incomplete.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CompletionContext {
+    /// The part of the word before the cursor, which candidates must match.
+    pub prefix: String,
+    /// The code of the postfix chain before `.prefix`, if the word is a member.
+    pub receiver: Option<String>,
+    pub replace_start: usize,
+    pub replace_end: usize,
+}
+
+const KEYWORDS: &[&str] = &[
+    "if", "else", "while", "for", "in", "fn", "return", "try", "catch", "throw", "import", "as",
+    "new", "true", "false", "null", "vars", "del",
+];
+
+const MAX_CANDIDATES: usize = 100;
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '$'
+}
+
+/// Whether the quote at `index` is escaped by an odd number of backslashes.
+fn is_escaped(chars: &[char], index: usize) -> bool {
+    chars[..index]
+        .iter()
+        .rev()
+        .take_while(|c| **c == '\\')
+        .count()
+        % 2
+        == 1
+}
+
+fn is_in_literal_or_comment(chars: &[char]) -> bool {
+    let mut quote = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match quote {
+            Some(q) if c == q && !is_escaped(chars, i) => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '/' && next == Some('/') => {
+                match chars[i..].iter().position(|c| *c == '\n') {
+                    Some(end) => i += end,
+                    None => return true,
+                }
+            }
+            None if c == '/' && next == Some('*') => {
+                match chars[i + 2..].windows(2).position(|w| w == ['*', '/']) {
+                    Some(end) => i += end + 3,
+                    None => return true,
+                }
+            }
+            None => {}
+        }
+        i += 1;
+    }
+    quote.is_some()
+}
+
+/// Where the postfix chain ending at `end` starts, skipping over the arguments
+/// of calls, indices and string literals.
+fn receiver_start(chars: &[char], end: usize) -> usize {
+    let mut depth = 0;
+    let mut i = end;
+    while i > 0 {
+        let c = chars[i - 1];
+        match c {
+            '"' | '\'' => {
+                // jump to the opening quote
+                let mut open = i - 1;
+                while open > 0 && (chars[open - 1] != c || is_escaped(chars, open - 1)) {
+                    open -= 1;
+                }
+                if open == 0 {
+                    break;
+                }
+                i = open;
+            }
+            ')' | ']' => depth += 1,
+            '(' | '[' if depth == 0 => break,
+            '(' | '[' => depth -= 1,
+            _ if depth > 0 => {}
+            c if is_identifier_char(c) || c == '.' || c == '@' => {}
+            _ => break,
+        }
+        i -= 1;
+    }
+    i
+}
+
+/// Finds the word at `cursor`, counted in characters. There is nothing to complete
+/// inside literals, comments and numbers.
+pub fn completion_context(code: &str, cursor: usize) -> Option<CompletionContext> {
+    let chars: Vec<char> = code.chars().collect();
+    let cursor = cursor.min(chars.len());
+    if is_in_literal_or_comment(&chars[..cursor]) {
+        return None;
+    }
+
+    let mut start = cursor;
+    while start > 0 && is_identifier_char(chars[start - 1]) {
+        start -= 1;
+    }
+    let mut end = cursor;
+    while end < chars.len() && is_identifier_char(chars[end]) {
+        end += 1;
+    }
+    if chars.get(start).is_some_and(|c| c.is_ascii_digit())
+        || (start > 0 && chars[start - 1] == '@')
+    {
+        return None;
+    }
+
+    let receiver = if start > 0 && chars[start - 1] == '.' {
+        let receiver_end = start - 1;
+        let receiver: String = chars[receiver_start(&chars, receiver_end)..receiver_end]
+            .iter()
+            .collect();
+        // decimals such as `1.5` aren't receivers
+        if receiver.is_empty() || receiver.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        Some(receiver)
+    } else {
+        None
+    };
+
+    Some(CompletionContext {
+        prefix: chars[start..cursor].iter().collect(),
+        receiver,
+        replace_start: start,
+        replace_end: end,
+    })
+}
+
+/// Whether the prefix matches the starts of the words of a camel case or snake
+/// case label, e.g. `gSN` matches `getSimpleName`.
+fn matches_camel_humps(label: &str, prefix: &str) -> bool {
+    let label: Vec<char> = label.chars().collect();
+    let is_hump = |i: usize| i == 0 || label[i].is_uppercase() || label[i - 1] == '_';
+    let mut position = 0;
+    for p in prefix.chars() {
+        let matches = |i: usize| label[i].eq_ignore_ascii_case(&p);
+        if position < label.len() && matches(position) {
+            position += 1;
+            continue;
+        }
+        match (position..label.len()).find(|i| is_hump(*i) && matches(*i)) {
+            Some(hump) => position = hump + 1,
+            None => return false,
+        }
+    }
+    true
+}
+
+/// How well a label matches the prefix, lower is better.
+pub fn match_rank(label: &str, prefix: &str) -> Option<u8> {
+    if label.starts_with(prefix) {
+        Some(0)
+    } else if label.to_lowercase().starts_with(&prefix.to_lowercase()) {
+        Some(1)
+    } else if matches_camel_humps(label, prefix) {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+fn candidate(
+    kind: CompletionKind,
+    label: &str,
+    signature: String,
+    documentation: &str,
+) -> Completion {
+    Completion {
+        kind,
+        label: label.to_owned(),
+        insert_text: label.to_owned(),
+        signature,
+        documentation: documentation.to_owned(),
+    }
+}
+
+fn get_string(env: JNIEnv<'static>, obj: JObject<'static>) -> anyhow::Result<String> {
+    Ok(env.get_string(JString::from(obj))?.into())
+}
+
+fn type_name(env: JNIEnv<'static>, value: JValue<'static>) -> anyhow::Result<String> {
+    Ok(match value {
+        JValue::Object(obj) if obj.is_null() => "null".to_owned(),
+        JValue::Object(obj) => {
+            let class = env.get_object_class(obj)?;
+            get_string(
+                env,
+                env.call_method(class, "getName", "()Ljava/lang/String;", &[])?
+                    .l()?,
+            )?
+        }
+        primitive => match conversion::primitive_descriptor(primitive) {
+            Some(descriptor) => conversion::primitive_name(descriptor).to_owned(),
+            None => "void".to_owned(),
+        },
+    })
+}
+
+fn display_string(env: JNIEnv<'static>, value: JValue<'static>) -> anyhow::Result<String> {
+    let value = conversion::box_value(env, value)?;
+    let display = env
+        .call_static_method(
+            "appstrument/server/ReflectionUtil",
+            "toDisplayString",
+            "(Ljava/lang/Object;)Ljava/lang/String;",
+            &[JValue::Object(value)],
+        )?
+        .l()?;
+    let display = get_string(env, display)?;
+    // a snippet, long strings and collections are cut short
+    Ok(match display.char_indices().nth(80) {
+        Some((end, _)) => format!("{}...", &display[..end]),
+        None => display,
+    })
+}
+
+fn member_candidates(
+    env: JNIEnv<'static>,
+    class: JObject<'static>,
+    is_static: bool,
+) -> anyhow::Result<Vec<Completion>> {
+    let members = env
+        .call_static_method(
+            "appstrument/server/ReflectionUtil",
+            "getMembers",
+            "(Ljava/lang/Class;Z)[Lappstrument/server/JavaMember;",
+            &[JValue::Object(class), JValue::Bool(is_static as u8)],
+        )?
+        .l()?
+        .into_inner();
+    let mut candidates = Vec::new();
+    for i in 0..env.get_array_length(members)? {
+        let member = env.get_object_array_element(members, i)?;
+        let get_field = |field: &str| -> anyhow::Result<String> {
+            let value = env.get_field(member, field, "Ljava/lang/String;")?.l()?;
+            let string = get_string(env, value)?;
+            env.delete_local_ref(value)?;
+            Ok(string)
+        };
+        let kind = match env.get_field(member, "kind", "I")?.i()? {
+            0 => CompletionKind::Field,
+            1 => CompletionKind::Method,
+            _ => CompletionKind::Class,
+        };
+        candidates.push(candidate(
+            kind,
+            &get_field("name")?,
+            get_field("signature")?,
+            &get_field("documentation")?,
+        ));
+        env.delete_local_ref(member)?;
+    }
+    Ok(candidates)
+}
+
+/// The next segment of the classes in a package, as classes or subpackages.
+fn package_candidates(package: &str, classes: &[String]) -> Vec<Completion> {
+    let mut seen = HashSet::new();
+    let mut candidates = Vec::new();
+    for class in classes {
+        let rest = match class.strip_prefix(package) {
+            Some(rest) => match rest.strip_prefix('.') {
+                Some(rest) => rest,
+                None => continue,
+            },
+            None => continue,
+        };
+        let (segment, kind) = match rest.split_once('.') {
+            Some((subpackage, _)) => (subpackage, CompletionKind::Package),
+            None => (rest, CompletionKind::Class),
+        };
+        if seen.insert((segment, kind)) {
+            let qualified_name = format!("{}.{}", package, segment);
+            let documentation = match kind {
+                CompletionKind::Package => "package",
+                _ => "class",
+            };
+            candidates.push(candidate(kind, segment, qualified_name, documentation));
+        }
+    }
+    candidates
+}
+
+fn unqualified_candidates(
+    env: JNIEnv<'static>,
+    interpreter: &SlatInterpreter,
+    importable_classes: &[String],
+) -> anyhow::Result<Vec<Completion>> {
+    let mut candidates = Vec::new();
+    for (name, value) in interpreter.variables() {
+        candidates.push(candidate(
+            CompletionKind::Variable,
+            &name,
+            type_name(env, value)?,
+            &display_string(env, value)?,
+        ));
+    }
+    for function in interpreter.functions() {
+        let signature = format!("fn {}({})", function.name, function.parameters.join(", "));
+        candidates.push(candidate(
+            CompletionKind::Function,
+            &function.name,
+            signature,
+            "function defined in this session",
+        ));
+    }
+    for intrinsic in interpreter.intrinsics() {
+        candidates.push(candidate(
+            CompletionKind::Function,
+            intrinsic.name,
+            intrinsic.signature(),
+            intrinsic.description,
+        ));
+    }
+    for (name, class) in interpreter.imports() {
+        let qualified_name = class.replace('/', ".");
+        candidates.push(candidate(
+            CompletionKind::Class,
+            &name,
+            qualified_name,
+            "imported class",
+        ));
+    }
+    let mut packages = HashSet::new();
+    for class in importable_classes {
+        // nested and anonymous classes are reached through their outer class
+        if class.contains('$') {
+            continue;
+        }
+        let (package, simple_name) = match class.rsplit_once('.') {
+            Some(split) => split,
+            None => continue,
+        };
+        if interpreter
+            .wildcard_imports()
+            .iter()
+            .any(|import| import == package)
+        {
+            candidates.push(candidate(
+                CompletionKind::Class,
+                simple_name,
+                class.clone(),
+                "class from an imported package",
+            ));
+        } else {
+            candidates.push(Completion {
+                kind: CompletionKind::Class,
+                label: simple_name.to_owned(),
+                insert_text: class.clone(),
+                signature: class.clone(),
+                documentation: "loaded class, inserted by its qualified name".to_owned(),
+            });
+        }
+        let root = class.split('.').next().expect("unreachable");
+        if packages.insert(root) {
+            candidates.push(candidate(
+                CompletionKind::Package,
+                root,
+                root.to_owned(),
+                "package",
+            ));
+        }
+    }
+    for keyword in KEYWORDS {
+        candidates.push(candidate(
+            CompletionKind::Keyword,
+            keyword,
+            String::new(),
+            "keyword",
+        ));
+    }
+    Ok(candidates)
+}
+
+/// Completes the word at `cursor`, counted in characters. `importable_classes`
+/// lists the qualified names of classes that can be imported, it is only called
+/// when classes are among the candidates.
+pub fn complete(
+    env: JNIEnv<'static>,
+    interpreter: &mut SlatInterpreter,
+    code: &str,
+    cursor: usize,
+    importable_classes: impl FnOnce() -> Vec<String>,
+) -> anyhow::Result<Completions> {
+    let context = match completion_context(code, cursor) {
+        Some(context) => context,
+        None => return Ok(Completions::default()),
+    };
+
+    let candidates = match &context.receiver {
+        Some(receiver) => {
+            // an incomplete receiver has no members to complete
+            let target = match parser::parse(receiver).as_deref() {
+                Ok([receiver]) => interpreter.completion_target(receiver)?,
+                _ => None,
+            };
+            match target {
+                Some(CompletionTarget::Members { class, is_static }) => {
+                    member_candidates(env, class, is_static)?
+                }
+                Some(CompletionTarget::Package(package)) => {
+                    let mut classes = importable_classes();
+                    classes.extend(
+                        interpreter
+                            .imports()
+                            .into_iter()
+                            .map(|(_, class)| class.replace('/', ".")),
+                    );
+                    package_candidates(&package, &classes)
+                }
+                None => Vec::new(),
+            }
+        }
+        None => unqualified_candidates(env, interpreter, &importable_classes())?,
+    };
+
+    let mut seen = HashSet::new();
+    let mut ranked: Vec<(u8, Completion)> = candidates
+        .into_iter()
+        .filter(|candidate| {
+            seen.insert((
+                candidate.kind,
+                candidate.label.clone(),
+                candidate.signature.clone(),
+            ))
+        })
+        .filter_map(|candidate| {
+            match_rank(&candidate.label, &context.prefix).map(|rank| (rank, candidate))
+        })
+        .collect();
+    ranked.sort_by(|(a_rank, a), (b_rank, b)| {
+        (a_rank, a.kind, a.label.len(), &a.label).cmp(&(b_rank, b.kind, b.label.len(), &b.label))
+    });
+    ranked.truncate(MAX_CANDIDATES);
+
+    Ok(Completions {
+        candidates: ranked.into_iter().map(|(_, candidate)| candidate).collect(),
+        replace_start: context.replace_start,
+        replace_end: context.replace_end,
+    })
+}
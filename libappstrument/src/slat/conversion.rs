@@ -0,0 +1,236 @@
+use jni::{
+    objects::{JObject, JValue},
+    JNIEnv,
+};
+
+use super::interpreter::InterpreterError;
+
+/// The descriptor character of a primitive value, e.g. `I` for an `int`.
+pub fn primitive_descriptor(value: JValue) -> Option<char> {
+    Some(match value {
+        JValue::Byte(_) => 'B',
+        JValue::Short(_) => 'S',
+        JValue::Char(_) => 'C',
+        JValue::Int(_) => 'I',
+        JValue::Long(_) => 'J',
+        JValue::Float(_) => 'F',
+        JValue::Double(_) => 'D',
+        JValue::Bool(_) => 'Z',
+        JValue::Object(_) | JValue::Void => return None,
+    })
+}
+
+/// The name of a primitive type from its descriptor character.
+pub fn primitive_name(descriptor: char) -> &'static str {
+    match descriptor {
+        'B' => "byte",
+        'S' => "short",
+        'C' => "char",
+        'I' => "int",
+        'J' => "long",
+        'F' => "float",
+        'D' => "double",
+        'Z' => "boolean",
+        'V' => "void",
+        _ => "object",
+    }
+}
+
+/// The descriptor character of a primitive type from its name, e.g. `I` for `int`.
+pub fn primitive_descriptor_by_name(name: &str) -> Option<char> {
+    Some(match name {
+        "byte" => 'B',
+        "short" => 'S',
+        "char" => 'C',
+        "int" => 'I',
+        "long" => 'J',
+        "float" => 'F',
+        "double" => 'D',
+        "boolean" => 'Z',
+        _ => return None,
+    })
+}
+
+/// The JNI name of the box class of a primitive type, e.g. `java/lang/Integer` for `I`.
+pub fn box_class(descriptor: char) -> &'static str {
+    match descriptor {
+        'B' => "java/lang/Byte",
+        'S' => "java/lang/Short",
+        'C' => "java/lang/Character",
+        'I' => "java/lang/Integer",
+        'J' => "java/lang/Long",
+        'F' => "java/lang/Float",
+        'D' => "java/lang/Double",
+        'Z' => "java/lang/Boolean",
+        _ => "java/lang/Object",
+    }
+}
+
+/// Wraps a primitive in its box class, e.g. an `int` in a `java.lang.Integer`.
+/// Objects are returned as they are.
+pub fn box_value(env: JNIEnv<'static>, value: JValue<'static>) -> anyhow::Result<JObject<'static>> {
+    let descriptor = match value {
+        JValue::Object(obj) => return Ok(obj),
+        JValue::Void => {
+            return Err(InterpreterError::TypeMismatch("void is not a value".to_owned()).into())
+        }
+        primitive => primitive_descriptor(primitive).expect("unreachable"),
+    };
+    let class = box_class(descriptor);
+    let signature = format!("({})L{};", descriptor, class);
+    Ok(env
+        .call_static_method(class, "valueOf", signature, &[value])?
+        .l()?)
+}
+
+/// Whether converting from one primitive type to another is a widening
+/// primitive conversion, see JLS §5.1.2. Identity conversions count as widening.
+pub fn is_widening(from: char, to: char) -> bool {
+    from == to
+        || match from {
+            'B' => matches!(to, 'S' | 'I' | 'J' | 'F' | 'D'),
+            'S' | 'C' => matches!(to, 'I' | 'J' | 'F' | 'D'),
+            'I' => matches!(to, 'J' | 'F' | 'D'),
+            'J' => matches!(to, 'F' | 'D'),
+            'F' => to == 'D',
+            _ => false,
+        }
+}
+
+/// Converts a numeric value the way a Java cast does.
+fn cast(value: JValue<'static>, to: char) -> JValue<'static> {
+    macro_rules! cast_to {
+        ($value:expr) => {
+            match to {
+                'B' => JValue::Byte($value as i8),
+                'S' => JValue::Short($value as i16),
+                'C' => JValue::Char($value as u16),
+                'I' => JValue::Int($value as i32),
+                'J' => JValue::Long($value as i64),
+                'F' => JValue::Float($value as f32),
+                'D' => JValue::Double($value as f64),
+                _ => unreachable!(),
+            }
+        };
+    }
+    match value {
+        JValue::Byte(b) => cast_to!(b),
+        JValue::Short(s) => cast_to!(s),
+        JValue::Char(c) => cast_to!(c),
+        JValue::Int(i) => cast_to!(i),
+        JValue::Long(l) => cast_to!(l),
+        // Java narrows floating point values to byte, short and char through int
+        JValue::Float(f) if matches!(to, 'B' | 'S' | 'C') => cast(JValue::Int(f as i32), to),
+        JValue::Double(d) if matches!(to, 'B' | 'S' | 'C') => cast(JValue::Int(d as i32), to),
+        // Rust's float to integer casts saturate and map NaN to 0, just like Java's
+        JValue::Float(f) => cast_to!(f),
+        JValue::Double(d) => cast_to!(d),
+        _ => unreachable!(),
+    }
+}
+
+fn display_value(value: JValue) -> String {
+    match value {
+        JValue::Byte(b) => b.to_string(),
+        JValue::Short(s) => s.to_string(),
+        JValue::Char(c) => (c as u32).to_string(),
+        JValue::Int(i) => i.to_string(),
+        JValue::Long(l) => l.to_string(),
+        JValue::Float(f) => f.to_string(),
+        JValue::Double(d) => d.to_string(),
+        JValue::Bool(b) => (b == 1).to_string(),
+        JValue::Object(_) | JValue::Void => value.type_name().to_owned(),
+    }
+}
+
+fn same_value(a: JValue, b: JValue) -> bool {
+    match (a, b) {
+        (JValue::Byte(a), JValue::Byte(b)) => a == b,
+        (JValue::Short(a), JValue::Short(b)) => a == b,
+        (JValue::Char(a), JValue::Char(b)) => a == b,
+        (JValue::Int(a), JValue::Int(b)) => a == b,
+        (JValue::Long(a), JValue::Long(b)) => a == b,
+        (JValue::Float(a), JValue::Float(b)) => a == b || (a.is_nan() && b.is_nan()),
+        (JValue::Double(a), JValue::Double(b)) => a == b || (a.is_nan() && b.is_nan()),
+        _ => false,
+    }
+}
+
+/// Converts a primitive value to the primitive type with the given descriptor,
+/// following Java's assignment conversion. Narrowing is accepted when the value
+/// is representable in the target type, like a constant expression would be.
+/// Otherwise it truncates like a cast, or fails in strict mode.
+pub fn convert_primitive(
+    value: JValue<'static>,
+    to: char,
+    strict: bool,
+) -> Result<JValue<'static>, InterpreterError> {
+    let from = match primitive_descriptor(value) {
+        Some(from) => from,
+        None => {
+            return Err(InterpreterError::TypeMismatch(format!(
+                "cannot convert {} to {}",
+                value.type_name(),
+                primitive_name(to)
+            )))
+        }
+    };
+    if from == 'Z' || to == 'Z' {
+        return if from == to {
+            Ok(value)
+        } else {
+            Err(InterpreterError::TypeMismatch(format!(
+                "cannot convert {} to {}",
+                primitive_name(from),
+                primitive_name(to)
+            )))
+        };
+    }
+
+    let converted = cast(value, to);
+    if is_widening(from, to) || same_value(cast(converted, from), value) {
+        return Ok(converted);
+    }
+    if strict {
+        return Err(InterpreterError::LossyConversion(format!(
+            "converting {} {} to {}",
+            primitive_name(from),
+            display_value(value),
+            primitive_name(to)
+        )));
+    }
+    Ok(converted)
+}
+
+/// Splits the parameter list of a method descriptor such as `(I[JLjava/lang/String;)V`
+/// into the descriptors of its parameters.
+pub fn parameter_descriptors(signature: &str) -> Vec<&str> {
+    let end = signature.find(')').unwrap_or(signature.len());
+    let params = &signature[1.min(end)..end];
+    let mut descriptors = Vec::new();
+    let mut start = 0;
+    let bytes = params.as_bytes();
+    while start < bytes.len() {
+        let mut i = start;
+        while bytes[i] == b'[' {
+            i += 1;
+        }
+        if bytes[i] == b'L' {
+            i = params[i..]
+                .find(';')
+                .map_or(params.len() - 1, |semicolon| i + semicolon);
+        }
+        descriptors.push(&params[start..=i]);
+        start = i + 1;
+    }
+    descriptors
+}
+
+/// The class name JNI expects for a reference type descriptor: `Ljava/lang/String;`
+/// becomes `java/lang/String`, while array descriptors are used as is.
+pub fn descriptor_class_name(descriptor: &str) -> &str {
+    descriptor
+        .strip_prefix('L')
+        .and_then(|class| class.strip_suffix(';'))
+        .unwrap_or(descriptor)
+}
@@ -0,0 +1,94 @@
+use super::{ast::Span, check::CheckError, interpreter::SpannedError, parser::ParserError};
+
+/// How much a problem `check` found matters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The script is not run.
+    Error,
+    /// The script runs anyway.
+    Warning,
+}
+
+/// An error located in the SLAT source it was raised from.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    /// Always `Error`, except for some of the problems found by `check`.
+    pub severity: Severity,
+}
+
+impl Diagnostic {
+    /// Extracts the location of a parse or evaluation error, if it has one. Of the
+    /// problems found by `check`, the first one is returned.
+    pub fn from_error(error: &anyhow::Error) -> Option<Diagnostic> {
+        if let Some(error) = error.downcast_ref::<CheckError>() {
+            error.0.first().cloned()
+        } else if let Some(error) = error.downcast_ref::<ParserError>() {
+            Some(Diagnostic {
+                message: error.to_string(),
+                span: error.span(),
+                severity: Severity::Error,
+            })
+        } else {
+            error
+                .downcast_ref::<SpannedError>()
+                .map(|error| Diagnostic {
+                    message: error.error.to_string(),
+                    span: error.span,
+                    severity: Severity::Error,
+                })
+        }
+    }
+
+    /// Like `from_error`, but returns every problem found by `check`.
+    pub fn all_from_error(error: &anyhow::Error) -> Vec<Diagnostic> {
+        match error.downcast_ref::<CheckError>() {
+            Some(error) => error.0.clone(),
+            None => Diagnostic::from_error(error).into_iter().collect(),
+        }
+    }
+
+    /// Renders the diagnostic with the offending source line and a caret
+    /// underline, e.g.
+    ///
+    /// ```text
+    /// Unknown identifier 'foo'
+    ///   --> 2:5
+    ///   |
+    /// 2 | x = foo.bar()
+    ///   |     ^^^
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let start = self.span.start;
+        let line = source
+            .lines()
+            .nth(start.line.saturating_sub(1))
+            .unwrap_or("");
+        let line_length = line.chars().count();
+        // spans over several lines are underlined up to the end of the first one
+        let end_column = if self.span.end.line == start.line {
+            self.span.end.column
+        } else {
+            line_length + 1
+        };
+        let padding = start.column.saturating_sub(1).min(line_length);
+        let carets = end_column.saturating_sub(start.column).max(1);
+
+        let line_number = start.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+        format!(
+            "{}\n{} --> {}:{}\n{} |\n{} | {}\n{} | {}{}",
+            self.message,
+            gutter,
+            start.line,
+            start.column,
+            gutter,
+            line_number,
+            line,
+            gutter,
+            " ".repeat(padding),
+            "^".repeat(carets)
+        )
+    }
+}
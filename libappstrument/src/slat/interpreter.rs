@@ -1,20 +1,38 @@
-use std::collections::HashMap;
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+    fmt,
+    rc::Rc,
+    time::Instant,
+};
 
 use anyhow::anyhow;
 use jni::{
     objects::{GlobalRef, JObject, JString, JValue},
-    sys::jobject,
+    sys::{jintArray, jobjectArray},
     JNIEnv,
 };
 
 use crate::{
-    java::serialize_jvalue,
-    proto::{java_value::JavaValueType, JavaValue},
+    java::serialize_jvalue_limited,
+    proto::{java_value::JavaValueType, JavaThrowable, JavaValue},
 };
 
 use super::{
-    ast::{ArrayExpression, Assignment, Literal, MemberExpression, MethodCall, Token},
-    parser,
+    ast::{
+        Assignment, BinaryExpression, BinaryOperator, Directive, ForEachLoop, FunctionDefinition,
+        IfStatement, Import, Lambda, LambdaBody, Literal, Member, MemberExpression, MemberKind,
+        MethodCall, NewArray, NewExpression, ReturnStatement, Slice, Span, ThrowStatement, Token,
+        TokenKind, TryStatement, UnaryExpression, WhileLoop,
+    },
+    cancel::CancelHandle,
+    check::{self, Arity, CheckContext, CheckError, ReflectionOracle},
+    completion::CompletionTarget,
+    conversion,
+    diagnostic::Severity,
+    intrinsics::{self, Intrinsic},
+    operators, parser,
+    visit::{self, Visitor},
 };
 
 #[derive(thiserror::Error, Debug)]
@@ -25,14 +43,236 @@ pub enum InterpreterError {
     NoSuchClass(String),
     #[error("Could not resolve method '{0}'")]
     NoSuchMethod(String),
+    #[error("Could not resolve a constructor of '{0}' for the given arguments")]
+    NoSuchConstructor(String),
     #[error("Could not resolve field '{0}'")]
     NoSuchField(String),
-    #[error("A class has already been imported with name '{0}'")]
+    #[error("A different class has already been imported with name '{0}', use 'import ... as' to rename one of them")]
     DuplicateImport(String),
-    #[error("Array index out of bounds")]
-    ArrayIndexOutOfBounds,
+    #[error("Class name '{0}' is ambiguous, it could refer to: {1}")]
+    AmbiguousClass(String, String),
+    #[error("Index {0} out of bounds for length {1}")]
+    IndexOutOfBounds(i32, i32),
+    #[error("Slice {0}:{1} out of bounds for length {2}")]
+    SliceOutOfBounds(i32, i32, i32),
     #[error("Unknown identifier '{0}'")]
     UnknownIdentifier(String),
+    #[error("Unknown directive '#{0}'")]
+    UnknownDirective(String),
+    #[error("Directive '#{0}' expects {1}")]
+    InvalidDirectiveValue(String, &'static str),
+    #[error("Execution timed out after {0} ms")]
+    Timeout(u64),
+    #[error("Execution exceeded the budget of {0} steps")]
+    StepLimit(u64),
+    #[error("Execution was cancelled")]
+    Cancelled,
+    #[error("Lossy conversion in strict mode: {0}")]
+    LossyConversion(String),
+    #[error("Invalid operands: {0}")]
+    InvalidOperands(String),
+    #[error("Array size {0} is negative")]
+    NegativeArraySize(i32),
+    #[error("Division by zero")]
+    DivisionByZero,
+    #[error("Type mismatch: {0}")]
+    TypeMismatch(String),
+    #[error("Cannot iterate over a value of type '{0}'")]
+    NotIterable(String),
+    #[error("Loop exceeded the limit of {0} iterations")]
+    IterationLimit(u64),
+    #[error("No function with name '{0}' has been defined")]
+    NoSuchFunction(String),
+    #[error("Function '{0}' expects {1} argument(s) but was given {2}")]
+    ArgumentCount(String, usize, usize),
+    #[error("Function calls exceeded the recursion limit of {0}")]
+    RecursionLimit(u32),
+    #[error("Call to '{0}' is ambiguous, candidates are: {1}")]
+    AmbiguousCall(String, String),
+    #[error("Cannot access '{0}' on a null reference")]
+    NullReference(String),
+    #[error("Uncaught Java exception {0}")]
+    JavaException(ThrownException),
+    #[error("No object with handle @{0}")]
+    UnknownHandle(usize),
+    #[error("No result '{0}' in the history")]
+    UnknownResult(String),
+}
+
+/// A Java exception thrown by code called from SLAT, or by a `throw` statement.
+#[derive(Clone)]
+pub struct ThrownException {
+    /// The exception object, which `catch` clauses bind to their variable.
+    pub throwable: GlobalRef,
+    /// The exception followed by its causes.
+    pub chain: Vec<JavaThrowable>,
+}
+
+impl fmt::Debug for ThrownException {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThrownException")
+            .field("chain", &self.chain)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for ThrownException {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, throwable) in self.chain.iter().enumerate() {
+            if i > 0 {
+                write!(f, "\nCaused by: ")?;
+            }
+            write!(f, "{}", throwable.class_name)?;
+            if !throwable.message.is_empty() {
+                write!(f, ": {}", throwable.message)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The Java exception an interpreter error was caused by, if any.
+pub fn thrown_exception(error: &anyhow::Error) -> Option<&ThrownException> {
+    let error = match error.downcast_ref::<SpannedError>() {
+        Some(spanned) => &spanned.error,
+        None => error,
+    };
+    match error.downcast_ref::<InterpreterError>() {
+        Some(InterpreterError::JavaException(exception)) => Some(exception),
+        _ => None,
+    }
+}
+
+/// Whether the error is the one evaluations are aborted with when cancelled.
+pub fn is_cancellation(error: &anyhow::Error) -> bool {
+    let error = match error.downcast_ref::<SpannedError>() {
+        Some(spanned) => &spanned.error,
+        None => error,
+    };
+    matches!(
+        error.downcast_ref::<InterpreterError>(),
+        Some(InterpreterError::Cancelled)
+    )
+}
+
+/// An error raised while evaluating a node, along with the node's location.
+#[derive(thiserror::Error, Debug)]
+#[error("{error}")]
+pub struct SpannedError {
+    pub error: anyhow::Error,
+    pub span: Span,
+}
+
+/// Attaches a span to an error, unless a more precise one was already attached
+/// by a nested node.
+fn with_span(error: anyhow::Error, span: Span) -> anyhow::Error {
+    if error.is::<SpannedError>() {
+        error
+    } else {
+        SpannedError { error, span }.into()
+    }
+}
+
+/// Session settings, changed from scripts through `#name value` directives.
+#[derive(Debug, Clone, Copy)]
+pub struct InterpreterOptions {
+    /// How many levels of nested lists are expanded when serializing a result.
+    pub depth: u32,
+    /// How many items of a list are serialized before it is truncated.
+    pub limit: u32,
+    /// Wall-clock budget of a single `interpret` call in milliseconds, or 0 for none.
+    pub timeout: u64,
+    /// How many expressions and statements a single `interpret` call may
+    /// evaluate, or 0 for no limit.
+    pub steps: u64,
+    /// Reject lossy implicit conversions instead of silently truncating values.
+    pub strict: bool,
+    /// How many times a single loop may iterate before it is aborted.
+    pub iterations: u64,
+    /// How deeply SLAT functions may call each other.
+    pub recursion: u32,
+    /// How many results are kept for `_N`, older ones are forgotten first.
+    pub history: u32,
+    /// Check scripts for mistakes before running them, see `check::check`.
+    pub check: bool,
+}
+
+impl Default for InterpreterOptions {
+    fn default() -> Self {
+        InterpreterOptions {
+            depth: 8,
+            limit: 1000,
+            timeout: 0,
+            steps: 10_000_000,
+            strict: false,
+            iterations: 100_000,
+            recursion: 64,
+            history: 100,
+            check: true,
+        }
+    }
+}
+
+type DirectiveHandler = fn(&mut InterpreterOptions, JValue<'static>) -> anyhow::Result<()>;
+
+fn directive_integer(name: &str, value: JValue<'static>) -> anyhow::Result<i64> {
+    match value {
+        JValue::Int(i) if i >= 0 => Ok(i as i64),
+        JValue::Long(l) if l >= 0 => Ok(l),
+        _ => Err(InterpreterError::InvalidDirectiveValue(
+            name.to_owned(),
+            "a non-negative integer",
+        )
+        .into()),
+    }
+}
+
+fn directive_boolean(name: &str, value: JValue<'static>) -> anyhow::Result<bool> {
+    match value {
+        JValue::Bool(b) => Ok(b == 1),
+        _ => Err(InterpreterError::InvalidDirectiveValue(name.to_owned(), "a boolean").into()),
+    }
+}
+
+fn default_directives() -> HashMap<&'static str, DirectiveHandler> {
+    let mut directives: HashMap<&'static str, DirectiveHandler> = HashMap::new();
+    directives.insert("depth", |options, value| {
+        options.depth = directive_integer("depth", value)?.min(u32::MAX as i64) as u32;
+        Ok(())
+    });
+    directives.insert("limit", |options, value| {
+        options.limit = directive_integer("limit", value)?.min(u32::MAX as i64) as u32;
+        Ok(())
+    });
+    directives.insert("timeout", |options, value| {
+        options.timeout = directive_integer("timeout", value)? as u64;
+        Ok(())
+    });
+    directives.insert("steps", |options, value| {
+        options.steps = directive_integer("steps", value)? as u64;
+        Ok(())
+    });
+    directives.insert("strict", |options, value| {
+        options.strict = directive_boolean("strict", value)?;
+        Ok(())
+    });
+    directives.insert("iterations", |options, value| {
+        options.iterations = directive_integer("iterations", value)? as u64;
+        Ok(())
+    });
+    directives.insert("recursion", |options, value| {
+        options.recursion = directive_integer("recursion", value)?.min(u32::MAX as i64) as u32;
+        Ok(())
+    });
+    directives.insert("history", |options, value| {
+        options.history = directive_integer("history", value)?.min(u32::MAX as i64) as u32;
+        Ok(())
+    });
+    directives.insert("check", |options, value| {
+        options.check = directive_boolean("check", value)?;
+        Ok(())
+    });
+    directives
 }
 
 #[derive(Debug, Clone)]
@@ -41,7 +281,127 @@ enum InterpreterValue {
     ObjectRef(JValue<'static>),
 }
 
-static mut OBJ_PTR: jobject = std::ptr::null_mut();
+/// Local reference capacity reserved for each top-level statement, loop iteration
+/// and function call. Each runs in its own local frame so long scripts don't
+/// exhaust the reference table.
+const LOCAL_FRAME_CAPACITY: i32 = 64;
+
+/// The variables of the session or of a single function call.
+#[derive(Default, Clone)]
+struct Scope {
+    primitive_variables: HashMap<String, JValue<'static>>,
+    object_variables: HashMap<String, GlobalRef>,
+}
+
+impl Scope {
+    fn get(&self, name: &str) -> Option<JValue<'static>> {
+        if let Some(object) = self.object_variables.get(name) {
+            // the global reference stays valid for as long as the variable isn't reassigned
+            Some(JValue::Object(JObject::from(object.as_obj().into_inner())))
+        } else {
+            self.primitive_variables.get(name).copied()
+        }
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.object_variables.contains_key(name) || self.primitive_variables.contains_key(name)
+    }
+}
+
+/// A lambda together with the function variables it was created in. Session
+/// variables aren't captured, the lambda sees their current values when invoked.
+struct Closure {
+    lambda: Rc<Lambda>,
+    captured: Option<Scope>,
+    /// Proxies invoking this closure that haven't been collected yet.
+    proxies: usize,
+}
+
+/// A result kept for `_` and `_N`.
+enum HistoryEntry {
+    Primitive(JValue<'static>),
+    Object(GlobalRef),
+}
+
+enum IterationSource {
+    /// A snapshot of an array or a `java.util.List`, see `ReflectionUtil.getListAsArray`.
+    Array {
+        array: jobjectArray,
+        length: i32,
+        unbox: bool,
+    },
+    /// A `java.util.Iterator` over an `Iterable` or a map's entry set.
+    Iterator(JObject<'static>),
+}
+
+/// A method or constructor picked by overload resolution.
+struct ResolvedMethod {
+    signature: String,
+    description: String,
+    /// Whether the trailing arguments are collected into the variable arity array.
+    var_args: bool,
+}
+
+/// Whether a class name such as `[I` names a primitive array, whose elements
+/// `ReflectionUtil.getListAsArray` returns boxed.
+fn is_primitive_array(class_name: &str) -> bool {
+    class_name.len() == 2 && class_name.starts_with('[')
+}
+
+/// The primitive descriptor of a box class such as `java/lang/Integer`.
+fn unboxed_descriptor(class: &str) -> Option<char> {
+    Some(match class {
+        "java/lang/Byte" => 'B',
+        "java/lang/Short" => 'S',
+        "java/lang/Integer" => 'I',
+        "java/lang/Long" => 'J',
+        "java/lang/Float" => 'F',
+        "java/lang/Double" => 'D',
+        "java/lang/Character" => 'C',
+        "java/lang/Boolean" => 'Z',
+        _ => return None,
+    })
+}
+
+/// The lambda passed as an argument or assigned without a cast, whose
+/// functional interface is inferred from the parameter or field type.
+fn lambda_argument(token: &Token) -> Option<&Rc<Lambda>> {
+    match &token.kind {
+        TokenKind::Lambda(lambda) if lambda.interface.is_none() => Some(lambda),
+        _ => None,
+    }
+}
+
+/// Resolves an index into a sequence of the given length, where negative
+/// indices count from the end, as in `list[-1]`.
+pub fn normalize_index(index: i32, length: i32) -> Result<i32, InterpreterError> {
+    let resolved = if index < 0 { index + length } else { index };
+    if resolved < 0 || resolved >= length {
+        return Err(InterpreterError::IndexOutOfBounds(index, length));
+    }
+    Ok(resolved)
+}
+
+/// Resolves the bounds of a `[start:end]` slice of a sequence of the given
+/// length. Omitted bounds default to the whole sequence, and negative bounds
+/// count from the end.
+pub fn slice_bounds(
+    start: Option<i32>,
+    end: Option<i32>,
+    length: i32,
+) -> Result<(i32, i32), InterpreterError> {
+    let resolve = |bound: i32| if bound < 0 { bound + length } else { bound };
+    let resolved_start = start.map_or(0, resolve);
+    let resolved_end = end.map_or(length, resolve);
+    if resolved_start < 0 || resolved_start > resolved_end || resolved_end > length {
+        return Err(InterpreterError::SliceOutOfBounds(
+            start.unwrap_or(0),
+            end.unwrap_or(length),
+            length,
+        ));
+    }
+    Ok((resolved_start, resolved_end))
+}
 
 impl InterpreterValue {
     fn into_object_ref(self) -> anyhow::Result<JValue<'static>> {
@@ -52,12 +412,130 @@ impl InterpreterValue {
     }
 }
 
+/// What a single `interpret_with` call is evaluated against, besides the session.
+#[derive(Default)]
+pub struct Evaluation<'a> {
+    /// Objects the client holds handles to, `@N` refers to `handles[N]`.
+    pub handles: &'a [GlobalRef],
+    /// The handle of the object the script is evaluated as if inside a method of.
+    pub this_handle: Option<usize>,
+    /// Replaces the session's `#timeout` for this call.
+    pub timeout: Option<u64>,
+    /// Replaces the session's `#steps` for this call.
+    pub steps: Option<u64>,
+    /// The id `CancelHandle::cancel` refers to this call by.
+    pub request_id: Option<i32>,
+}
+
+/// What the evaluations of a session share. Java may call a lambda while a script
+/// is being evaluated, which is then evaluated by an interpreter of its own sharing
+/// this state, so the state is only ever borrowed briefly and never across a call
+/// into Java.
+struct SessionState {
+    /// Simple names and aliases of imported classes, mapped to their JNI class names.
+    imports: RefCell<HashMap<String, String>>,
+    /// Packages imported on demand, `java.lang` included.
+    wildcard_imports: RefCell<Vec<String>>,
+    /// Classes found through on-demand imports or qualified names, by the name used in the source.
+    resolved_classes: RefCell<HashMap<String, String>>,
+    globals: RefCell<Scope>,
+    functions: RefCell<HashMap<String, Rc<FunctionDefinition>>>,
+    directives: HashMap<&'static str, DirectiveHandler>,
+    intrinsics: HashMap<&'static str, Intrinsic>,
+    options: Cell<InterpreterOptions>,
+    started: Cell<Instant>,
+    /// Steps taken since `started`.
+    steps: Cell<u64>,
+    /// Limits of the current `interpret_with` call, replacing the session's options.
+    timeout: Cell<Option<u64>>,
+    step_budget: Cell<Option<u64>>,
+    /// Function and lambda calls in progress, across the interpreters evaluating
+    /// lambdas called from Java.
+    depth: Cell<usize>,
+    cancel_handle: CancelHandle,
+    /// Whether a script is being interpreted, as opposed to lambdas invoked afterwards.
+    evaluating: Cell<bool>,
+    /// The `AppstrumentNative` instance lambdas call back into.
+    session: RefCell<Option<GlobalRef>>,
+    /// Lambdas handed out to Java, by the id their proxies invoke.
+    lambdas: RefCell<HashMap<i32, Closure>>,
+    next_lambda_id: Cell<i32>,
+    /// Ids of lambdas that capture nothing, so evaluating one in a loop doesn't
+    /// register it over and over.
+    lambda_ids: RefCell<HashMap<*const Lambda, i32>>,
+    /// Results of previous `interpret` calls, oldest first.
+    history: RefCell<VecDeque<HistoryEntry>>,
+    /// How many results have been forgotten, so `_N` keeps referring to the same result.
+    history_start: Cell<usize>,
+    /// The N of `_N` for the result of the latest `interpret` call, or 0 if it had none.
+    history_index: Cell<usize>,
+    /// Objects the client holds handles to, referred to as `@N`.
+    object_handles: RefCell<Vec<GlobalRef>>,
+    /// The object unqualified names are resolved against first, see `resolve_variable`.
+    this_object: RefCell<Option<GlobalRef>>,
+}
+
 pub struct SlatInterpreter {
     env: JNIEnv<'static>,
     value_stack: Vec<InterpreterValue>,
-    imports: HashMap<String, String>,
-    primitive_variables: HashMap<String, JValue<'static>>,
-    object_variables: HashMap<String, GlobalRef>,
+    /// One scope per active function call, innermost last.
+    locals: Vec<Scope>,
+    /// Set by `return` until the enclosing function call picks it up.
+    return_value: Option<JValue<'static>>,
+    state: Rc<SessionState>,
+}
+
+/// A handle through which Java invokes the lambdas of a session, which it may do
+/// while the session's interpreter is evaluating the script that led to the call.
+#[derive(Clone)]
+pub struct LambdaInvoker(Rc<SessionState>);
+
+impl LambdaInvoker {
+    /// Evaluates a lambda on behalf of its proxy, with the calling thread's
+    /// environment. This may happen while a script is being interpreted, when
+    /// a method it called invokes the lambda, or at any time afterwards.
+    /// The result is boxed, as `InvocationHandler.invoke` returns an `Object`.
+    pub fn invoke(
+        &self,
+        env: JNIEnv,
+        lambda_id: i32,
+        args: jobjectArray,
+        return_type: JString,
+    ) -> anyhow::Result<JObject<'static>> {
+        // the environment is only used for the duration of this call
+        let env = unsafe { JNIEnv::from_raw(env.get_native_interface())? };
+        let mut interpreter = SlatInterpreter {
+            env,
+            value_stack: Vec::new(),
+            locals: Vec::new(),
+            return_value: None,
+            state: Rc::clone(&self.0),
+        };
+        interpreter.invoke_lambda(lambda_id, args, return_type)
+    }
+
+    /// Forgets a proxy of a lambda after Java collected it, freeing the lambda
+    /// once none of its proxies are left.
+    pub fn release(&self, lambda_id: i32) {
+        self.0.release_lambda(lambda_id);
+    }
+}
+
+impl SessionState {
+    fn release_lambda(&self, lambda_id: i32) {
+        let mut lambdas = self.lambdas.borrow_mut();
+        let closure = match lambdas.get_mut(&lambda_id) {
+            Some(closure) => closure,
+            None => return,
+        };
+        closure.proxies -= 1;
+        if closure.proxies == 0 {
+            lambdas.remove(&lambda_id);
+            self.lambda_ids
+                .borrow_mut()
+                .retain(|_, cached_id| *cached_id != lambda_id);
+        }
+    }
 }
 
 impl SlatInterpreter {
@@ -65,26 +543,214 @@ impl SlatInterpreter {
         SlatInterpreter {
             env,
             value_stack: Vec::new(),
-            imports: HashMap::new(),
-            primitive_variables: HashMap::new(),
-            object_variables: HashMap::new(),
+            locals: Vec::new(),
+            return_value: None,
+            state: Rc::new(SessionState {
+                imports: RefCell::new(HashMap::new()),
+                wildcard_imports: RefCell::new(vec!["java.lang".to_owned()]),
+                resolved_classes: RefCell::new(HashMap::new()),
+                globals: RefCell::new(Scope::default()),
+                functions: RefCell::new(HashMap::new()),
+                directives: default_directives(),
+                intrinsics: intrinsics::default_intrinsics(),
+                options: Cell::new(InterpreterOptions::default()),
+                started: Cell::new(Instant::now()),
+                steps: Cell::new(0),
+                timeout: Cell::new(None),
+                step_budget: Cell::new(None),
+                depth: Cell::new(0),
+                cancel_handle: CancelHandle::default(),
+                evaluating: Cell::new(false),
+                session: RefCell::new(None),
+                lambdas: RefCell::new(HashMap::new()),
+                next_lambda_id: Cell::new(0),
+                lambda_ids: RefCell::new(HashMap::new()),
+                history: RefCell::new(VecDeque::new()),
+                history_start: Cell::new(0),
+                history_index: Cell::new(0),
+                object_handles: RefCell::new(Vec::new()),
+                this_object: RefCell::new(None),
+            }),
         }
     }
 
+    /// Sets the `AppstrumentNative` instance that owns this interpreter, which
+    /// is required for creating lambdas.
+    pub fn set_session(&mut self, session: GlobalRef) {
+        *self.state.session.borrow_mut() = Some(session);
+    }
+
+    /// Makes the interpreter use the environment of the calling thread, which is
+    /// required before interpreting on a different thread than the previous call.
+    pub fn set_env(&mut self, env: JNIEnv) {
+        // environments stay valid for as long as their thread is attached
+        self.env = unsafe { JNIEnv::from_raw(env.get_native_interface()) }
+            .expect("environment is not null");
+    }
+
+    /// The variables of the current scope, sorted by name.
+    pub fn variables(&self) -> Vec<(String, JValue<'static>)> {
+        let globals = self.state.globals.borrow();
+        let scope = self.locals.last().unwrap_or(&globals);
+        let mut variables: Vec<(String, JValue<'static>)> = scope
+            .primitive_variables
+            .iter()
+            .map(|(name, value)| (name.clone(), *value))
+            .chain(scope.object_variables.iter().map(|(name, object)| {
+                let object = JObject::from(object.as_obj().into_inner());
+                (name.clone(), JValue::Object(object))
+            }))
+            .collect();
+        variables.sort_by(|(a, _), (b, _)| a.cmp(b));
+        variables
+    }
+
+    pub fn functions(&self) -> Vec<Rc<FunctionDefinition>> {
+        self.state.functions.borrow().values().cloned().collect()
+    }
+
+    pub fn intrinsics(&self) -> impl Iterator<Item = &Intrinsic> {
+        self.state.intrinsics.values()
+    }
+
+    /// Imported classes by the simple name or alias they were imported as.
+    pub fn imports(&self) -> Vec<(String, String)> {
+        let imports = self.state.imports.borrow();
+        imports
+            .iter()
+            .map(|(name, class)| (name.clone(), class.clone()))
+            .collect()
+    }
+
+    /// Packages imported on demand, `java.lang` included.
+    pub fn wildcard_imports(&self) -> Vec<String> {
+        self.state.wildcard_imports.borrow().clone()
+    }
+
+    /// A handle through which other threads can cancel requests evaluated by
+    /// this interpreter.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.state.cancel_handle.clone()
+    }
+
+    /// A handle through which the lambdas this interpreter hands out are invoked.
+    pub fn lambda_invoker(&self) -> LambdaInvoker {
+        LambdaInvoker(Rc::clone(&self.state))
+    }
+
+    fn options(&self) -> InterpreterOptions {
+        self.state.options.get()
+    }
+
+    /// What scripts run next can use without defining it.
+    pub fn check_context(&self) -> anyhow::Result<CheckContext> {
+        let this_class = match self.this_object() {
+            Some(this) => Some(self.get_class_name(this)?.replace('.', "/")),
+            None => None,
+        };
+        let state = &self.state;
+        let scope = state.globals.borrow();
+        let history_start = state.history_start.get();
+        Ok(CheckContext {
+            variables: scope
+                .primitive_variables
+                .keys()
+                .chain(scope.object_variables.keys())
+                .cloned()
+                .collect(),
+            functions: state.functions.borrow().clone(),
+            intrinsics: state
+                .intrinsics
+                .iter()
+                .map(|(name, intrinsic)| (name.to_string(), intrinsic.parameters.len()))
+                .collect(),
+            imports: state.imports.borrow().clone(),
+            wildcard_imports: state.wildcard_imports.borrow().clone(),
+            directives: state
+                .directives
+                .keys()
+                .map(|name| name.to_string())
+                .collect(),
+            results: history_start + 1..history_start + state.history.borrow().len() + 1,
+            this_class,
+        })
+    }
+
     pub fn interpret(&mut self, slat_code: &str) -> anyhow::Result<JavaValue> {
+        self.interpret_with(slat_code, &Evaluation::default())
+    }
+
+    pub fn interpret_with(
+        &mut self,
+        slat_code: &str,
+        evaluation: &Evaluation,
+    ) -> anyhow::Result<JavaValue> {
+        let state = Rc::clone(&self.state);
+        state.history_index.set(0);
         let ast = parser::parse(slat_code)?;
 
-        for token in ast {
-            self.visit(token)?;
+        // kept after the call, lambdas may still refer to them when invoked
+        *state.object_handles.borrow_mut() = evaluation.handles.to_vec();
+        *state.this_object.borrow_mut() = evaluation
+            .this_handle
+            .map(|id| {
+                evaluation
+                    .handles
+                    .get(id)
+                    .cloned()
+                    .ok_or(InterpreterError::UnknownHandle(id))
+            })
+            .transpose()?;
+
+        // nothing runs unless the whole script looks right
+        if self.options().check {
+            let context = self.check_context()?;
+            let errors: Vec<_> =
+                check::check(&ast, &context, Some(self as &mut dyn ReflectionOracle))?
+                    .into_iter()
+                    .filter(|diagnostic| diagnostic.severity == Severity::Error)
+                    .collect();
+            if !errors.is_empty() {
+                return Err(CheckError(errors).into());
+            }
+        }
+
+        state.started.set(Instant::now());
+        state.steps.set(0);
+        state.timeout.set(evaluation.timeout);
+        state.step_budget.set(evaluation.steps);
+        state.cancel_handle.begin(evaluation.request_id);
+        state.evaluating.set(true);
+        let result = ast.iter().try_for_each(|token| self.visit_top_level(token));
+        state.evaluating.set(false);
+        let cancelled = state.cancel_handle.is_cancelled();
+        state.cancel_handle.finish();
+        state.timeout.set(None);
+        state.step_budget.set(None);
+        if let Err(mut err) = result {
+            // a lambda called from Java is aborted with a Java exception, which the
+            // script may have caught or Java may have wrapped
+            if cancelled && !is_cancellation(&err) {
+                err = InterpreterError::Cancelled.into();
+            }
+            self.value_stack.clear();
+            self.pop_scopes(0);
+            self.return_value = None;
+            return Err(err);
         }
 
         if !self.value_stack.is_empty() {
             let last_value = self.value_stack.pop().expect("unreachable");
             self.value_stack.clear();
-            Ok(serialize_jvalue(
+            let last_value = last_value.into_object_ref()?;
+            self.record_result(last_value)?;
+            let options = self.options();
+            Ok(serialize_jvalue_limited(
                 self.env,
                 None,
-                last_value.into_object_ref()?,
+                last_value,
+                options.depth,
+                options.limit,
             )?)
         } else {
             Ok(JavaValue {
@@ -94,323 +760,2519 @@ impl SlatInterpreter {
         }
     }
 
-    fn visit(&mut self, token: Token) -> anyhow::Result<()> {
-        match token {
-            Token::MethodCall(method_call) => self.visit_method_call(method_call)?,
-            Token::Literal(literal) => self.visit_literal(literal)?,
-            Token::Import(import) => self.visit_import(import)?,
-            Token::MemberExpression(member_expr) => self.visit_member_expression(member_expr)?,
-            Token::ArrayExpression(array_expr) => self.visit_array_expression(array_expr)?,
-            Token::Assignment(assignment) => self.visit_assignment(assignment)?,
-            Token::Identifier(ident) => self.visit_identifier(ident)?,
+    /// The N of `_N` for the result of the latest `interpret` call, or 0 if it had none.
+    pub fn history_index(&self) -> usize {
+        self.state.history_index.get()
+    }
+
+    fn record_result(&mut self, value: JValue<'static>) -> anyhow::Result<()> {
+        let entry = match value {
+            JValue::Void => return Ok(()),
+            JValue::Object(obj) if !obj.is_null() => {
+                HistoryEntry::Object(self.env.new_global_ref(obj)?)
+            }
+            other => HistoryEntry::Primitive(other),
         };
+        let state = &self.state;
+        let limit = self.options().history as usize;
+        let mut history = state.history.borrow_mut();
+        history.push_back(entry);
+        while history.len() > limit {
+            history.pop_front();
+            state.history_start.set(state.history_start.get() + 1);
+        }
+        // with a history of 0 the result is forgotten right away
+        if limit > 0 {
+            state
+                .history_index
+                .set(state.history_start.get() + history.len());
+        }
         Ok(())
     }
 
-    fn visit_field_access(
-        &mut self,
-        owner: InterpreterValue,
-        field_name: String,
-    ) -> anyhow::Result<()> {
-        match owner {
-            InterpreterValue::ClassRef(cls) => {
-                let class_name_jstr = self.env.new_string(&cls)?;
-                let field_name_jstr = self.env.new_string(&field_name)?;
-                let signature_object = self
-                    .env
-                    .call_static_method(
-                        "appstrument/server/ReflectionUtil",
-                        "findStaticFieldSignature",
-                        "(Ljava/lang/String;Ljava/lang/String;)Ljava/lang/String;",
-                        &[
-                            JValue::Object(*class_name_jstr),
-                            JValue::Object(*field_name_jstr),
-                        ],
-                    )?
-                    .l()?;
-                if signature_object.is_null() {
-                    return Err(InterpreterError::NoSuchField(field_name).into());
-                }
-                let signature_str: String =
-                    self.env.get_string(JString::from(signature_object))?.into();
-                let result = self.env.get_static_field(cls, field_name, signature_str)?;
-                self.value_stack.push(InterpreterValue::ObjectRef(result));
+    /// Resolves `_` to the latest result and `_N` to the Nth, counting from 1.
+    fn lookup_result(&self, name: &str) -> Option<Option<JValue<'static>>> {
+        let history = self.state.history.borrow();
+        let history_start = self.state.history_start.get();
+        let index = match name.strip_prefix('_')? {
+            "" => (history_start + history.len()).checked_sub(1),
+            digits if digits.bytes().all(|b| b.is_ascii_digit()) => {
+                digits.parse::<usize>().ok().and_then(|n| n.checked_sub(1))
             }
-            InterpreterValue::ObjectRef(obj) => {
-                let field_name_jstr = self.env.new_string(&field_name)?;
-                let signature_object = self
-                    .env
-                    .call_static_method(
-                        "appstrument/server/ReflectionUtil",
-                        "findInstanceFieldSignature",
-                        "(Ljava/lang/Object;Ljava/lang/String;)Ljava/lang/String;",
-                        &[obj, JValue::Object(*field_name_jstr)],
-                    )?
-                    .l()?;
-                if signature_object.is_null() {
-                    return Err(InterpreterError::NoSuchField(field_name).into());
-                }
-                let signature_str: String =
-                    self.env.get_string(JString::from(signature_object))?.into();
-                let result = self.env.get_field(obj.l()?, field_name, signature_str)?;
-                self.value_stack.push(InterpreterValue::ObjectRef(result));
+            _ => return None,
+        };
+        let entry = index
+            .and_then(|index| index.checked_sub(history_start))
+            .and_then(|index| history.get(index));
+        Some(entry.map(|entry| match entry {
+            HistoryEntry::Primitive(value) => *value,
+            HistoryEntry::Object(object) => {
+                JValue::Object(JObject::from(object.as_obj().into_inner()))
             }
-        }
-        Ok(())
+        }))
     }
 
-    fn visit_identifier(&mut self, ident: String) -> anyhow::Result<()> {
-        if let Some(object) = self.object_variables.get(&ident) {
-            unsafe {
-                let obj = object.as_obj();
-                OBJ_PTR = obj.into_inner();
+    /// Runs a top-level statement in its own local frame. Only the value of the
+    /// latest statement can become the result, so it is the only one kept alive.
+    fn visit_top_level(&mut self, token: &Token) -> anyhow::Result<()> {
+        // a trailing comment keeps the value of the statement before it
+        if let TokenKind::Comment(_) = token.kind {
+            return Ok(());
+        }
+        self.env.push_local_frame(LOCAL_FRAME_CAPACITY)?;
+        let result = self.visit_token(token).and_then(|_| self.check_timeout());
+        let last_value = self.value_stack.pop();
+        self.value_stack.clear();
+        match last_value {
+            Some(InterpreterValue::ObjectRef(JValue::Object(obj))) => {
+                let obj = self.env.pop_local_frame(obj)?;
                 self.value_stack
-                    .push(InterpreterValue::ObjectRef(JValue::Object(JObject::from(
-                        OBJ_PTR,
-                    ))));
+                    .push(InterpreterValue::ObjectRef(JValue::Object(obj)));
             }
+            other => {
+                self.env.pop_local_frame(JObject::null())?;
+                self.value_stack.extend(other);
+            }
+        }
+        result
+    }
 
-            Ok(())
-        } else if let Some(primitive) = self.primitive_variables.get(&ident) {
-            self.value_stack
-                .push(InterpreterValue::ObjectRef(*primitive));
-            Ok(())
-        } else {
-            Err(InterpreterError::UnknownIdentifier(ident).into())
+    /// Counts an evaluation step, aborting the evaluation if it has been
+    /// cancelled or has run out of steps or time.
+    fn step(&mut self) -> anyhow::Result<()> {
+        let state = &self.state;
+        if state.cancel_handle.is_cancelled() {
+            return Err(InterpreterError::Cancelled.into());
         }
+        state.steps.set(state.steps.get() + 1);
+        let budget = state.step_budget.get().unwrap_or(self.options().steps);
+        if budget > 0 && state.steps.get() > budget {
+            return Err(InterpreterError::StepLimit(budget).into());
+        }
+        self.check_timeout()
     }
 
-    fn visit_assignment(&mut self, assignment: Assignment) -> anyhow::Result<()> {
-        self.visit(*assignment.expr)?;
-        let value = self
-            .value_stack
-            .pop()
-            .ok_or(InterpreterError::MalformedSlat(
-                "expecting value".to_owned(),
-            ))?
-            .into_object_ref()?;
+    fn check_timeout(&self) -> anyhow::Result<()> {
+        let timeout = self.state.timeout.get().unwrap_or(self.options().timeout);
+        if timeout > 0 && self.state.started.get().elapsed().as_millis() > timeout as u128 {
+            return Err(InterpreterError::Timeout(timeout).into());
+        }
+        Ok(())
+    }
 
-        match value {
-            JValue::Object(obj) => {
-                let pinned_ref = self.env.new_global_ref(obj)?;
-                self.object_variables
-                    .insert(assignment.variable, pinned_ref);
+    fn check_iterations(&self, iterations: &mut u64) -> anyhow::Result<()> {
+        *iterations += 1;
+        let limit = self.options().iterations;
+        if *iterations > limit {
+            return Err(InterpreterError::IterationLimit(limit).into());
+        }
+        self.check_timeout()
+    }
+
+    /// Pops a local frame pushed with `LOCAL_FRAME_CAPACITY`, keeping a pending
+    /// return value alive in the enclosing frame.
+    fn pop_local_frame(&mut self) -> anyhow::Result<()> {
+        match self.return_value {
+            Some(JValue::Object(returned)) => {
+                let returned = self.env.pop_local_frame(returned)?;
+                self.return_value = Some(JValue::Object(returned));
             }
-            primitive => {
-                self.primitive_variables
-                    .insert(assignment.variable, primitive);
+            _ => {
+                self.env.pop_local_frame(JObject::null())?;
             }
         }
-
         Ok(())
     }
 
-    fn visit_array_expression(&mut self, array_expr: ArrayExpression) -> anyhow::Result<()> {
-        self.visit(*array_expr.array)?;
-        let array = self
-            .value_stack
-            .pop()
-            .ok_or(InterpreterError::MalformedSlat(
-                "expecting array".to_owned(),
-            ))?
-            .into_object_ref()?
-            .l()?;
-        self.visit(*array_expr.index)?;
-        let index = self
-            .value_stack
-            .pop()
-            .ok_or(InterpreterError::MalformedSlat(
-                "expecting array index".to_owned(),
-            ))?
-            .into_object_ref()?
-            .i()?;
-        if index < 0 {
-            return Err(InterpreterError::ArrayIndexOutOfBounds.into());
+    /// Clears the Java exception pending on the thread, if any, and makes it the
+    /// error instead of the JNI error it caused, so it can be caught or reported.
+    fn capture_exception(&self, error: anyhow::Error) -> anyhow::Error {
+        let exception = match self.take_pending_exception() {
+            Ok(Some(exception)) => InterpreterError::JavaException(exception),
+            Ok(None) => return error,
+            Err(err) => return err,
+        };
+        match error.downcast::<SpannedError>() {
+            Ok(spanned) => SpannedError {
+                error: exception.into(),
+                span: spanned.span,
+            }
+            .into(),
+            Err(_) => exception.into(),
+        }
+    }
+
+    fn take_pending_exception(&self) -> anyhow::Result<Option<ThrownException>> {
+        if !self.env.exception_check()? {
+            return Ok(None);
         }
+        let throwable = self.env.exception_occurred()?;
+        self.env.exception_clear()?;
+        Ok(Some(self.describe_exception(*throwable)?))
+    }
 
-        let list_type = self
+    fn describe_exception(&self, throwable: JObject) -> anyhow::Result<ThrownException> {
+        let chain_array = self
             .env
             .call_static_method(
                 "appstrument/server/ReflectionUtil",
-                "getListAsArray",
-                "(Ljava/lang/Object;)[Ljava/lang/Object;",
-                &[JValue::Object(array)],
+                "getThrowableChain",
+                "(Ljava/lang/Throwable;)[Lappstrument/server/JavaThrowable;",
+                &[JValue::Object(throwable)],
             )?
-            .l()?;
-        let array_type = list_type.into_inner();
-        let array_length = self.env.get_array_length(array_type)?;
-        if index >= array_length {
-            return Err(InterpreterError::ArrayIndexOutOfBounds.into());
-        }
+            .l()?
+            .into_inner();
+        let chain_length = self.env.get_array_length(chain_array)?;
+        let mut chain = Vec::with_capacity(chain_length as usize);
+        for i in 0..chain_length {
+            let element = self.env.get_object_array_element(chain_array, i)?;
+            let class_name = self
+                .env
+                .get_field(element, "className", "Ljava/lang/String;")?
+                .l()?;
+            let message = self
+                .env
+                .get_field(element, "message", "Ljava/lang/String;")?
+                .l()?;
+            let frames = self
+                .env
+                .get_field(element, "stackTrace", "[Ljava/lang/String;")?
+                .l()?
+                .into_inner();
 
-        let indexed_value = self.env.get_object_array_element(array_type, index)?;
+            let frames_length = self.env.get_array_length(frames)?;
+            let mut stack_trace = Vec::with_capacity(frames_length as usize);
+            for j in 0..frames_length {
+                let frame = self.env.get_object_array_element(frames, j)?;
+                stack_trace.push(self.env.get_string(JString::from(frame))?.into());
+                self.env.delete_local_ref(frame)?;
+            }
+            chain.push(JavaThrowable {
+                class_name: self.env.get_string(JString::from(class_name))?.into(),
+                message: if message.is_null() {
+                    String::new()
+                } else {
+                    self.env.get_string(JString::from(message))?.into()
+                },
+                stack_trace,
+            });
+            self.env.delete_local_ref(element)?;
+        }
+        Ok(ThrownException {
+            throwable: self.env.new_global_ref(throwable)?,
+            chain,
+        })
+    }
 
-        self.value_stack
-            .push(InterpreterValue::ObjectRef(JValue::Object(indexed_value)));
+    fn visit_condition(&mut self, condition: &Token) -> anyhow::Result<bool> {
+        self.visit_token(condition)?;
+        let value = self.pop_value("condition")?;
+        match self.unbox(value)? {
+            JValue::Bool(b) => Ok(b == 1),
+            other => Err(InterpreterError::TypeMismatch(format!(
+                "condition must be a boolean, found {}",
+                other.type_name()
+            ))
+            .into()),
+        }
+    }
 
-        Ok(())
+    fn run_while_iteration(
+        &mut self,
+        while_loop: &WhileLoop,
+        iterations: &mut u64,
+    ) -> anyhow::Result<bool> {
+        if !self.visit_condition(&while_loop.condition)? {
+            return Ok(false);
+        }
+        self.check_iterations(iterations)?;
+        self.visit_block(&while_loop.body)?;
+        Ok(true)
     }
 
-    fn visit_member_expression(&mut self, member_expr: MemberExpression) -> anyhow::Result<()> {
-        let root = match *member_expr.owner {
-            Token::Identifier(class_name) => class_name,
-            _ => {
-                return Err(InterpreterError::MalformedSlat(
-                    "member expression has invalid owner".to_owned(),
-                )
-                .into())
+    fn get_iteration_source(&self, iterable: JValue<'static>) -> anyhow::Result<IterationSource> {
+        let obj = match iterable {
+            JValue::Object(obj) if !obj.is_null() => obj,
+            JValue::Object(_) => {
+                return Err(InterpreterError::NotIterable("null".to_owned()).into())
             }
+            other => return Err(InterpreterError::NotIterable(other.type_name().to_owned()).into()),
         };
-        if let Some(import) = self.imports.get(&root) {
-            self.value_stack
-                .push(InterpreterValue::ClassRef(import.clone()));
-        } else {
-            return Err(InterpreterError::NoSuchClass(root).into());
-        }
-
-        for member in member_expr.members {
-            match member {
-                Token::Identifier(field_name) => {
-                    if let Some(stack_top) = self.value_stack.pop() {
-                        let stack_top = stack_top.clone();
-                        self.visit_field_access(stack_top, field_name)?;
-                    } else {
-                        return Err(InterpreterError::MalformedSlat(format!(
-                            "invalid member access: no value on stack proceeding identifier: {}",
-                            field_name
-                        ))
-                        .into());
-                    }
-                }
-                member => self.visit(member)?,
-            }
-        }
 
-        Ok(())
-    }
-
-    fn visit_import(&mut self, import: Vec<String>) -> anyhow::Result<()> {
-        let class_name = &import[import.len() - 1];
-        if self.imports.contains_key(class_name) {
-            Err(InterpreterError::DuplicateImport(class_name.clone()).into())
-        } else {
-            let full_name = import.join(".");
-            let class_name_jstr = self.env.new_string(&full_name)?;
-            let class_exists = self
+        let is_list_type = self
+            .env
+            .call_static_method(
+                "appstrument/server/ReflectionUtil",
+                "isListType",
+                "(Ljava/lang/Object;)Z",
+                &[JValue::Object(obj)],
+            )?
+            .z()?;
+        if is_list_type {
+            let class_name = self.get_class_name(obj)?;
+            let array = self
                 .env
                 .call_static_method(
                     "appstrument/server/ReflectionUtil",
-                    "doesClassExist",
-                    "(Ljava/lang/String;)Z",
-                    &[JValue::Object(class_name_jstr.into())],
+                    "getListAsArray",
+                    "(Ljava/lang/Object;)[Ljava/lang/Object;",
+                    &[JValue::Object(obj)],
                 )?
-                .z()?;
-            if class_exists {
-                self.imports.insert(class_name.clone(), import.join("/"));
-                Ok(())
-            } else {
-                Err(InterpreterError::NoSuchClass(full_name).into())
-            }
-        }
-    }
-
-    fn visit_method_call(&mut self, mut method_call: MethodCall) -> anyhow::Result<()> {
-        let args_len = method_call.args.len();
-        while !method_call.args.is_empty() {
-            let arg = method_call.args.pop().expect("unreachable");
-            self.visit(arg)?;
-        }
-
-        let mut args_values = Vec::with_capacity(args_len);
-        for _ in 0..args_len {
-            args_values.push(
-                self.value_stack
-                    .pop()
-                    .expect("stack underflow")
-                    .into_object_ref()?,
-            );
+                .l()?
+                .into_inner();
+            return Ok(IterationSource::Array {
+                array,
+                length: self.env.get_array_length(array)?,
+                unbox: is_primitive_array(&class_name),
+            });
         }
 
-        let type_hints =
-            self.env
-                .new_object_array(args_len as i32, "java/lang/String", JObject::null())?;
-        for i in 0..args_len {
-            // let arg = &args_values[i];
-            let arg_type = self.env.new_string("V")?;
+        let iterable = if self.env.is_instance_of(obj, "java/util/Map")? {
             self.env
-                .set_object_array_element(type_hints, i as i32, arg_type)?;
-        }
-        let type_hints = JObject::from(type_hints.to_owned());
+                .call_method(obj, "entrySet", "()Ljava/util/Set;", &[])?
+                .l()?
+        } else if self.env.is_instance_of(obj, "java/lang/Iterable")? {
+            obj
+        } else {
+            return Err(InterpreterError::NotIterable(self.get_class_name(obj)?).into());
+        };
+        let iterator = self
+            .env
+            .call_method(iterable, "iterator", "()Ljava/util/Iterator;", &[])?
+            .l()?;
+        Ok(IterationSource::Iterator(iterator))
+    }
 
-        let value_ref = self.value_stack.pop().expect("stack underflow");
-        match value_ref {
+    fn run_for_each_iteration(
+        &mut self,
+        for_each: &ForEachLoop,
+        source: &IterationSource,
+        index: i32,
+        iterations: &mut u64,
+    ) -> anyhow::Result<bool> {
+        let element = match source {
+            IterationSource::Array {
+                array,
+                length,
+                unbox,
+            } => {
+                if index >= *length {
+                    return Ok(false);
+                }
+                let element = JValue::Object(self.env.get_object_array_element(*array, index)?);
+                if *unbox {
+                    self.unbox(element)?
+                } else {
+                    element
+                }
+            }
+            IterationSource::Iterator(iterator) => {
+                if !self
+                    .env
+                    .call_method(*iterator, "hasNext", "()Z", &[])?
+                    .z()?
+                {
+                    return Ok(false);
+                }
+                self.env
+                    .call_method(*iterator, "next", "()Ljava/lang/Object;", &[])?
+            }
+        };
+        self.check_iterations(iterations)?;
+        self.set_variable(&for_each.variable, element)?;
+        self.visit_block(&for_each.body)?;
+        Ok(true)
+    }
+
+    fn find_static_field_signature(&self, class: &str, field_name: &str) -> anyhow::Result<String> {
+        self.lookup_static_field_signature(class, field_name)?
+            .ok_or_else(|| InterpreterError::NoSuchField(field_name.to_owned()).into())
+    }
+
+    fn lookup_static_field_signature(
+        &self,
+        class: &str,
+        field_name: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let class_name_jstr = self.env.new_string(class)?;
+        let field_name_jstr = self.env.new_string(field_name)?;
+        let signature_object = self
+            .env
+            .call_static_method(
+                "appstrument/server/ReflectionUtil",
+                "findStaticFieldSignature",
+                "(Ljava/lang/String;Ljava/lang/String;)Ljava/lang/String;",
+                &[
+                    JValue::Object(*class_name_jstr),
+                    JValue::Object(*field_name_jstr),
+                ],
+            )?
+            .l()?;
+        if signature_object.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(
+            self.env.get_string(JString::from(signature_object))?.into(),
+        ))
+    }
+
+    fn find_instance_field_signature(
+        &self,
+        obj: JObject<'static>,
+        field_name: &str,
+    ) -> anyhow::Result<String> {
+        self.lookup_instance_field_signature(obj, field_name)?
+            .ok_or_else(|| InterpreterError::NoSuchField(field_name.to_owned()).into())
+    }
+
+    fn lookup_instance_field_signature(
+        &self,
+        obj: JObject<'static>,
+        field_name: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let field_name_jstr = self.env.new_string(field_name)?;
+        let signature_object = self
+            .env
+            .call_static_method(
+                "appstrument/server/ReflectionUtil",
+                "findInstanceFieldSignature",
+                "(Ljava/lang/Object;Ljava/lang/String;)Ljava/lang/String;",
+                &[JValue::Object(obj), JValue::Object(*field_name_jstr)],
+            )?
+            .l()?;
+        if signature_object.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(
+            self.env.get_string(JString::from(signature_object))?.into(),
+        ))
+    }
+
+    fn visit_field_access(
+        &mut self,
+        owner: InterpreterValue,
+        field_name: String,
+    ) -> anyhow::Result<()> {
+        match owner {
+            InterpreterValue::ClassRef(cls) => {
+                let signature_str = match self.find_static_field_signature(&cls, &field_name) {
+                    Ok(signature_str) => signature_str,
+                    // like in Java, a field shadows a nested class of the same name
+                    Err(err) => {
+                        let nested = format!("{}${}", cls.replace('/', "."), field_name);
+                        match self.find_class(&nested)? {
+                            Some(nested) => {
+                                self.value_stack.push(InterpreterValue::ClassRef(nested));
+                                return Ok(());
+                            }
+                            None => return Err(err),
+                        }
+                    }
+                };
+                let result = self.env.get_static_field(cls, field_name, signature_str)?;
+                self.value_stack.push(InterpreterValue::ObjectRef(result));
+            }
+            InterpreterValue::ObjectRef(owner) => {
+                let obj = self.expect_object(owner, &field_name)?;
+                // arrays have no declared fields, their length is read through JNI
+                if field_name == "length" && self.get_class_name(obj)?.starts_with('[') {
+                    let length = self.env.get_array_length(obj.into_inner())?;
+                    self.value_stack
+                        .push(InterpreterValue::ObjectRef(JValue::Int(length)));
+                    return Ok(());
+                }
+                let signature_str = self.find_instance_field_signature(obj, &field_name)?;
+                let result = self.env.get_field(obj, field_name, signature_str)?;
+                self.value_stack.push(InterpreterValue::ObjectRef(result));
+            }
+        }
+        Ok(())
+    }
+
+    fn assign_field(
+        &mut self,
+        owner: InterpreterValue,
+        field_name: &str,
+        value: JValue<'static>,
+    ) -> anyhow::Result<()> {
+        match owner {
+            InterpreterValue::ClassRef(cls) => {
+                let signature_str = self.find_static_field_signature(&cls, field_name)?;
+                let value = self.convert_for_assignment(value, &signature_str)?;
+                self.env.set_static_field(
+                    cls.as_str(),
+                    (cls.as_str(), field_name, signature_str.as_str()),
+                    value,
+                )?;
+            }
+            InterpreterValue::ObjectRef(owner) => {
+                let obj = self.expect_object(owner, field_name)?;
+                let signature_str = self.find_instance_field_signature(obj, field_name)?;
+                let value = self.convert_for_assignment(value, &signature_str)?;
+                self.env.set_field(obj, field_name, signature_str, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn assign_lambda_field(
+        &mut self,
+        owner: InterpreterValue,
+        field_name: &str,
+        lambda: &Rc<Lambda>,
+    ) -> anyhow::Result<()> {
+        let signature_str = match &owner {
+            InterpreterValue::ClassRef(cls) => self.find_static_field_signature(cls, field_name)?,
+            InterpreterValue::ObjectRef(owner) => {
+                let obj = self.expect_object(*owner, field_name)?;
+                self.find_instance_field_signature(obj, field_name)?
+            }
+        };
+        let proxy =
+            self.create_lambda(lambda, conversion::descriptor_class_name(&signature_str))?;
+        self.assign_field(owner, field_name, JValue::Object(proxy))
+    }
+
+    /// Wraps a primitive in its box class, e.g. an `int` in a `java.lang.Integer`.
+    fn box_value(&self, value: JValue<'static>) -> anyhow::Result<JObject<'static>> {
+        conversion::box_value(self.env, value)
+    }
+
+    /// Converts a value for storing into a location of the given type descriptor,
+    /// following Java's assignment conversion: primitives are widened, narrowed
+    /// when representable, unboxed or boxed, and references must be instances of
+    /// the target type.
+    fn convert_for_assignment(
+        &self,
+        value: JValue<'static>,
+        signature: &str,
+    ) -> anyhow::Result<JValue<'static>> {
+        let strict = self.options().strict;
+        if !signature.starts_with('L') && !signature.starts_with('[') {
+            let descriptor = signature.chars().next().unwrap_or('V');
+            return Ok(conversion::convert_primitive(
+                self.unbox(value)?,
+                descriptor,
+                strict,
+            )?);
+        }
+
+        let class = conversion::descriptor_class_name(signature);
+        let value = match value {
+            JValue::Object(_) => value,
+            primitive => {
+                // box to the target's wrapper type when there is one, e.g. 5 to a Long field
+                let primitive = match unboxed_descriptor(class) {
+                    Some(descriptor) => {
+                        conversion::convert_primitive(primitive, descriptor, strict)?
+                    }
+                    None => primitive,
+                };
+                JValue::Object(self.box_value(primitive)?)
+            }
+        };
+        let obj = value.l()?;
+        if !obj.is_null() && !self.env.is_instance_of(obj, class)? {
+            return Err(InterpreterError::TypeMismatch(format!(
+                "cannot assign a value of type '{}' to '{}'",
+                self.get_class_name(obj)?,
+                class.replace('/', ".")
+            ))
+            .into());
+        }
+        Ok(value)
+    }
+
+    fn run_function_body(
+        &mut self,
+        function: &FunctionDefinition,
+        args_values: Vec<JValue<'static>>,
+    ) -> anyhow::Result<()> {
+        for (parameter, value) in function.parameters.iter().zip(args_values) {
+            self.set_variable(parameter, value)?;
+        }
+        self.visit_block(&function.body)
+    }
+
+    /// Creates a `java.lang.reflect.Proxy` implementing the functional interface
+    /// with the given JNI class name, whose method evaluates the lambda.
+    fn create_lambda(
+        &mut self,
+        lambda: &Rc<Lambda>,
+        interface: &str,
+    ) -> anyhow::Result<JObject<'static>> {
+        let class_name = interface.replace('/', ".");
+        let class_name_jstr = self.env.new_string(&class_name)?;
+        let arity = self
+            .env
+            .call_static_method(
+                "appstrument/server/SlatInvocationHandler",
+                "getFunctionalArity",
+                "(Ljava/lang/String;)I",
+                &[JValue::Object(*class_name_jstr)],
+            )?
+            .i()?;
+        if arity < 0 {
+            return Err(InterpreterError::TypeMismatch(format!(
+                "a lambda cannot implement '{}', which is not a functional interface",
+                class_name
+            ))
+            .into());
+        }
+        if arity as usize != lambda.parameters.len() {
+            return Err(InterpreterError::TypeMismatch(format!(
+                "a lambda implementing '{}' takes {} parameter(s), not {}",
+                class_name,
+                arity,
+                lambda.parameters.len()
+            ))
+            .into());
+        }
+        let session = match &*self.state.session.borrow() {
+            Some(session) => session.clone(),
+            None => return Err(anyhow!("lambdas require an Appstrument session")),
+        };
+
+        let captured = self.locals.last().cloned();
+        let key = Rc::as_ptr(lambda);
+        let state = &self.state;
+        let mut lambdas = state.lambdas.borrow_mut();
+        let mut lambda_ids = state.lambda_ids.borrow_mut();
+        let lambda_id = match lambda_ids.get(&key) {
+            Some(&lambda_id) if captured.is_none() => lambda_id,
+            _ => {
+                let lambda_id = state.next_lambda_id.get();
+                state.next_lambda_id.set(lambda_id + 1);
+                if captured.is_none() {
+                    lambda_ids.insert(key, lambda_id);
+                }
+                lambdas.insert(
+                    lambda_id,
+                    Closure {
+                        lambda: Rc::clone(lambda),
+                        captured,
+                        proxies: 0,
+                    },
+                );
+                lambda_id
+            }
+        };
+        if let Some(closure) = lambdas.get_mut(&lambda_id) {
+            closure.proxies += 1;
+        }
+        drop((lambdas, lambda_ids));
+
+        let proxy = self
+            .env
+            .call_static_method(
+                "appstrument/server/SlatInvocationHandler",
+                "createProxy",
+                "(Ljava/lang/String;Lappstrument/server/AppstrumentNative;I)Ljava/lang/Object;",
+                &[
+                    JValue::Object(*class_name_jstr),
+                    JValue::Object(session.as_obj()),
+                    JValue::Int(lambda_id),
+                ],
+            )
+            .and_then(|proxy| proxy.l());
+        if proxy.is_err() {
+            // there is no proxy to release the lambda when it is collected
+            self.state.release_lambda(lambda_id);
+        }
+        Ok(proxy?)
+    }
+
+    /// Evaluates a lambda for `LambdaInvoker::invoke`, on an interpreter of its own.
+    fn invoke_lambda(
+        &mut self,
+        lambda_id: i32,
+        args: jobjectArray,
+        return_type: JString,
+    ) -> anyhow::Result<JObject<'static>> {
+        let state = Rc::clone(&self.state);
+        if !state.evaluating.get() {
+            state.started.set(Instant::now());
+            state.steps.set(0);
+        }
+        let result = self.run_lambda(lambda_id, args, return_type);
+        self.pop_scopes(0);
+        result
+    }
+
+    fn run_lambda(
+        &mut self,
+        lambda_id: i32,
+        args: jobjectArray,
+        return_type: JString,
+    ) -> anyhow::Result<JObject<'static>> {
+        let (lambda, scope) = match self.state.lambdas.borrow().get(&lambda_id) {
+            Some(closure) => (
+                Rc::clone(&closure.lambda),
+                closure.captured.clone().unwrap_or_default(),
+            ),
+            None => return Err(anyhow!("no lambda with id {}", lambda_id)),
+        };
+        self.check_recursion()?;
+        let return_type: String = self.env.get_string(return_type)?.into();
+
+        self.env.push_local_frame(LOCAL_FRAME_CAPACITY)?;
+        self.push_scope(scope);
+        let result = self.run_lambda_body(&lambda, args, &return_type);
+        let returned = match &result {
+            Ok(returned) => *returned,
+            Err(_) => JObject::null(),
+        };
+        let returned = self.env.pop_local_frame(returned)?;
+        result.map(|_| returned)
+    }
+
+    fn run_lambda_body(
+        &mut self,
+        lambda: &Lambda,
+        args: jobjectArray,
+        return_type: &str,
+    ) -> anyhow::Result<JObject<'static>> {
+        let length = self.env.get_array_length(args)?;
+        if length as usize != lambda.parameters.len() {
+            return Err(InterpreterError::ArgumentCount(
+                "lambda".to_owned(),
+                lambda.parameters.len(),
+                length as usize,
+            )
+            .into());
+        }
+        for (i, parameter) in lambda.parameters.iter().enumerate() {
+            let arg = self.env.get_object_array_element(args, i as i32)?;
+            self.set_variable(parameter, JValue::Object(arg))?;
+        }
+
+        let value = match &lambda.body {
+            LambdaBody::Expression(expr) => {
+                let stack_height = self.value_stack.len();
+                self.visit_token(expr)?;
+                // statements such as assignments leave no value behind
+                if self.value_stack.len() > stack_height {
+                    self.pop_value("lambda result")?
+                } else {
+                    JValue::Void
+                }
+            }
+            LambdaBody::Block(body) => {
+                self.visit_block(body)?;
+                self.return_value.take().unwrap_or(JValue::Void)
+            }
+        };
+        if return_type == "V" {
+            return Ok(JObject::null());
+        }
+        let value = self.convert_for_assignment(value, return_type)?;
+        self.box_value(value)
+    }
+
+    fn visit_intrinsic_call(&mut self, intrinsic: Intrinsic, args: &[Token]) -> anyhow::Result<()> {
+        if intrinsic.parameters.len() != args.len() {
+            return Err(InterpreterError::ArgumentCount(
+                intrinsic.name.to_owned(),
+                intrinsic.parameters.len(),
+                args.len(),
+            )
+            .into());
+        }
+        let mut args_values = Vec::with_capacity(args.len());
+        for arg in args {
+            // class names stand for their Class object, as in `fields(System)`
+            let class = match &arg.kind {
+                TokenKind::Identifier(name) if self.resolve_variable(name)?.is_none() => {
+                    self.resolve_class(name)?
+                }
+                _ => None,
+            };
+            if let Some(class) = class {
+                args_values.push(JValue::Object(*self.env.find_class(class)?));
+                continue;
+            }
+            self.visit_token(arg)?;
+            let value = match self.pop_stack_value("argument value")? {
+                InterpreterValue::ClassRef(class) => JValue::Object(*self.env.find_class(class)?),
+                InterpreterValue::ObjectRef(value) => value,
+            };
+            args_values.push(value);
+        }
+        let result = (intrinsic.handler)(self.env, &args_values)?;
+        self.value_stack.push(InterpreterValue::ObjectRef(result));
+        Ok(())
+    }
+
+    /// Changes the scope assignments write to: the innermost function call, or the session.
+    fn with_current_scope<T>(&mut self, f: impl FnOnce(&mut Scope) -> T) -> T {
+        match self.locals.last_mut() {
+            Some(locals) => f(locals),
+            None => f(&mut self.state.globals.borrow_mut()),
+        }
+    }
+
+    fn lookup_variable(&self, name: &str) -> Option<JValue<'static>> {
+        // variables of the current function shadow the session's variables
+        self.locals
+            .last()
+            .and_then(|locals| locals.get(name))
+            .or_else(|| self.state.globals.borrow().get(name))
+    }
+
+    /// Fails if another function call would exceed `#recursion`.
+    fn check_recursion(&self) -> anyhow::Result<()> {
+        let limit = self.options().recursion;
+        if self.state.depth.get() >= limit as usize {
+            return Err(InterpreterError::RecursionLimit(limit).into());
+        }
+        Ok(())
+    }
+
+    fn push_scope(&mut self, scope: Scope) {
+        self.state.depth.set(self.state.depth.get() + 1);
+        self.locals.push(scope);
+    }
+
+    /// Leaves function calls until `depth` are left.
+    fn pop_scopes(&mut self, depth: usize) {
+        let popped = self.locals.len().saturating_sub(depth);
+        self.state.depth.set(self.state.depth.get() - popped);
+        self.locals.truncate(depth);
+    }
+
+    fn this_object(&self) -> Option<JObject<'static>> {
+        self.state
+            .this_object
+            .borrow()
+            .as_ref()
+            .map(|this| JObject::from(this.as_obj().into_inner()))
+    }
+
+    /// Resolves an unqualified name like Java does inside a method of `this`:
+    /// variables of the current function come first, then `this` itself and its
+    /// fields, then the session's variables.
+    fn resolve_variable(&mut self, name: &str) -> anyhow::Result<Option<JValue<'static>>> {
+        let this = match self.this_object() {
+            Some(this) => this,
+            None => return Ok(self.lookup_variable(name)),
+        };
+        if let Some(value) = self.locals.last().and_then(|locals| locals.get(name)) {
+            return Ok(Some(value));
+        }
+        if name == "this" {
+            return Ok(Some(JValue::Object(this)));
+        }
+        if let Some(owner) = self.this_field_owner(name)? {
+            self.visit_field_access(owner, name.to_owned())?;
+            return self.pop_value("field value").map(Some);
+        }
+        Ok(self.state.globals.borrow().get(name))
+    }
+
+    /// Where a field of `this` with the given name is read from: `this` for an
+    /// instance field, or its class for a static one.
+    fn this_field_owner(&self, name: &str) -> anyhow::Result<Option<InterpreterValue>> {
+        let this = match self.this_object() {
+            Some(this) => this,
+            None => return Ok(None),
+        };
+        if self.lookup_instance_field_signature(this, name)?.is_some() {
+            return Ok(Some(InterpreterValue::ObjectRef(JValue::Object(this))));
+        }
+        let class = self.get_class_name(this)?.replace('.', "/");
+        Ok(self
+            .lookup_static_field_signature(&class, name)?
+            .map(|_| InterpreterValue::ClassRef(class)))
+    }
+
+    /// Where methods of `this` with the given name are called on, like `this_field_owner`.
+    fn this_method_owner(&self, name: &str) -> anyhow::Result<Option<InterpreterValue>> {
+        let this = match self.this_object() {
+            Some(this) => this,
+            None => return Ok(None),
+        };
+        let name_jstr = self.env.new_string(name)?;
+        let has_instance_method = self
+            .env
+            .call_static_method(
+                "appstrument/server/ReflectionUtil",
+                "hasInstanceMethod",
+                "(Ljava/lang/Object;Ljava/lang/String;)Z",
+                &[JValue::Object(this), JValue::Object(*name_jstr)],
+            )?
+            .z()?;
+        if has_instance_method {
+            return Ok(Some(InterpreterValue::ObjectRef(JValue::Object(this))));
+        }
+        let class = self.get_class_name(this)?.replace('.', "/");
+        let class_jstr = self.env.new_string(&class)?;
+        let has_static_method = self
+            .env
+            .call_static_method(
+                "appstrument/server/ReflectionUtil",
+                "hasStaticMethod",
+                "(Ljava/lang/String;Ljava/lang/String;)Z",
+                &[JValue::Object(*class_jstr), JValue::Object(*name_jstr)],
+            )?
+            .z()?;
+        Ok(has_static_method.then_some(InterpreterValue::ClassRef(class)))
+    }
+
+    fn set_variable(&mut self, name: &str, value: JValue<'static>) -> anyhow::Result<()> {
+        match value {
+            JValue::Object(obj) => {
+                let pinned_ref = self.env.new_global_ref(obj)?;
+                self.with_current_scope(|scope| {
+                    scope.primitive_variables.remove(name);
+                    scope.object_variables.insert(name.to_owned(), pinned_ref);
+                });
+            }
+            primitive => self.with_current_scope(|scope| {
+                scope.object_variables.remove(name);
+                scope.primitive_variables.insert(name.to_owned(), primitive);
+            }),
+        }
+        Ok(())
+    }
+
+    fn visit_field_assignment(
+        &mut self,
+        owner: InterpreterValue,
+        field_name: &str,
+        expr: &Token,
+    ) -> anyhow::Result<()> {
+        match lambda_argument(expr) {
+            // the field's type is the lambda's functional interface
+            Some(lambda) => self.assign_lambda_field(owner, field_name, lambda),
+            None => {
+                self.visit_token(expr)?;
+                let value = self.pop_value("value")?;
+                self.assign_field(owner, field_name, value)
+            }
+        }
+    }
+
+    fn assign_index(
+        &mut self,
+        array: JObject<'static>,
+        index: JValue<'static>,
+        value: JValue<'static>,
+    ) -> anyhow::Result<()> {
+        if self.env.is_instance_of(array, "java/util/Map")? {
+            let key = self.box_value(index)?;
+            let value = self.box_value(value)?;
+            self.env.call_method(
+                array,
+                "put",
+                "(Ljava/lang/Object;Ljava/lang/Object;)Ljava/lang/Object;",
+                &[JValue::Object(key), JValue::Object(value)],
+            )?;
+            return Ok(());
+        }
+        let index = self.index_to_int(index)?;
+        let class_name = self.get_class_name(array)?;
+        if let Some(component) = class_name.strip_prefix('[') {
+            let length = self.env.get_array_length(array.into_inner())?;
+            let index = normalize_index(index, length)?;
+            let value = self.convert_for_assignment(value, &component.replace('.', "/"))?;
+            let array = array.into_inner();
+            match value {
+                JValue::Object(obj) => self.env.set_object_array_element(array, index, obj)?,
+                JValue::Byte(b) => self.env.set_byte_array_region(array, index, &[b])?,
+                JValue::Short(s) => self.env.set_short_array_region(array, index, &[s])?,
+                JValue::Int(i) => self.env.set_int_array_region(array, index, &[i])?,
+                JValue::Long(l) => self.env.set_long_array_region(array, index, &[l])?,
+                JValue::Float(f) => self.env.set_float_array_region(array, index, &[f])?,
+                JValue::Double(d) => self.env.set_double_array_region(array, index, &[d])?,
+                JValue::Char(c) => self.env.set_char_array_region(array, index, &[c])?,
+                JValue::Bool(z) => self.env.set_boolean_array_region(array, index, &[z])?,
+                JValue::Void => unreachable!(),
+            }
+        } else if self.env.is_instance_of(array, "java/util/List")? {
+            let size = self.env.call_method(array, "size", "()I", &[])?.i()?;
+            let index = normalize_index(index, size)?;
+            let element = self.box_value(value)?;
+            self.env.call_method(
+                array,
+                "set",
+                "(ILjava/lang/Object;)Ljava/lang/Object;",
+                &[JValue::Int(index), JValue::Object(element)],
+            )?;
+        } else {
+            return Err(InterpreterError::TypeMismatch(format!(
+                "cannot assign an element of a value of type '{}'",
+                class_name
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Checks that a member is accessed on a non-null object.
+    fn expect_object(
+        &self,
+        owner: JValue<'static>,
+        member: &str,
+    ) -> anyhow::Result<JObject<'static>> {
+        match owner {
+            JValue::Object(obj) if obj.is_null() => {
+                Err(InterpreterError::NullReference(member.to_owned()).into())
+            }
+            JValue::Object(obj) => Ok(obj),
+            other => Err(InterpreterError::TypeMismatch(format!(
+                "cannot access '{}' on a value of type {}",
+                member,
+                other.type_name()
+            ))
+            .into()),
+        }
+    }
+
+    fn index_to_int(&self, index: JValue<'static>) -> anyhow::Result<i32> {
+        match self.unbox(index)? {
+            JValue::Byte(b) => Ok(b as i32),
+            JValue::Short(s) => Ok(s as i32),
+            JValue::Char(c) => Ok(c as i32),
+            JValue::Int(i) => Ok(i),
+            other => Err(InterpreterError::TypeMismatch(format!(
+                "index must be an int, found {}",
+                other.type_name()
+            ))
+            .into()),
+        }
+    }
+
+    /// Indexes a map by key, a set by membership, a string by char, and an
+    /// array or list by position, counting from the end for negative indices.
+    fn visit_index(&mut self, owner: InterpreterValue, index: &Token) -> anyhow::Result<()> {
+        let obj = self.expect_object(owner.into_object_ref()?, "[]")?;
+        self.visit_token(index)?;
+        let index = self.pop_value("index")?;
+
+        let indexed_value = if self.env.is_instance_of(obj, "java/util/Map")? {
+            let key = self.box_value(index)?;
+            let value = self
+                .env
+                .call_method(
+                    obj,
+                    "get",
+                    "(Ljava/lang/Object;)Ljava/lang/Object;",
+                    &[JValue::Object(key)],
+                )?
+                .l()?;
+            JValue::Object(value)
+        } else if self.env.is_instance_of(obj, "java/util/Set")? {
+            let element = self.box_value(index)?;
+            self.env.call_method(
+                obj,
+                "contains",
+                "(Ljava/lang/Object;)Z",
+                &[JValue::Object(element)],
+            )?
+        } else if self.env.is_instance_of(obj, "java/lang/String")? {
+            let length = self.env.call_method(obj, "length", "()I", &[])?.i()?;
+            let index = normalize_index(self.index_to_int(index)?, length)?;
+            self.env
+                .call_method(obj, "charAt", "(I)C", &[JValue::Int(index)])?
+        } else {
+            self.index_list(obj, index)?
+        };
+        self.value_stack
+            .push(InterpreterValue::ObjectRef(indexed_value));
+        Ok(())
+    }
+
+    fn index_list(
+        &mut self,
+        array: JObject<'static>,
+        index: JValue<'static>,
+    ) -> anyhow::Result<JValue<'static>> {
+        let is_list_type = self
+            .env
+            .call_static_method(
+                "appstrument/server/ReflectionUtil",
+                "isListType",
+                "(Ljava/lang/Object;)Z",
+                &[JValue::Object(array)],
+            )?
+            .z()?;
+        if !is_list_type {
+            return Err(InterpreterError::TypeMismatch(format!(
+                "cannot index into a value of type '{}'",
+                self.get_class_name(array)?
+            ))
+            .into());
+        }
+        let index = self.index_to_int(index)?;
+        let class_name = self.get_class_name(array)?;
+        let list_type = self
+            .env
+            .call_static_method(
+                "appstrument/server/ReflectionUtil",
+                "getListAsArray",
+                "(Ljava/lang/Object;)[Ljava/lang/Object;",
+                &[JValue::Object(array)],
+            )?
+            .l()?;
+        let array_type = list_type.into_inner();
+        let array_length = self.env.get_array_length(array_type)?;
+        let index = normalize_index(index, array_length)?;
+
+        let indexed_value = JValue::Object(self.env.get_object_array_element(array_type, index)?);
+        if is_primitive_array(&class_name) {
+            self.unbox(indexed_value)
+        } else {
+            Ok(indexed_value)
+        }
+    }
+
+    fn visit_slice_bound(&mut self, bound: &Option<Box<Token>>) -> anyhow::Result<Option<i32>> {
+        match bound {
+            Some(bound) => {
+                self.visit_token(bound)?;
+                let bound = self.pop_value("slice bound")?;
+                Ok(Some(self.index_to_int(bound)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Slices a string with `substring`, a list with `subList`, which is a view
+    /// backed by the list, and an array with `Arrays.copyOfRange`.
+    fn visit_slice(&mut self, owner: InterpreterValue, slice: &Slice) -> anyhow::Result<()> {
+        let obj = self.expect_object(owner.into_object_ref()?, "[:]")?;
+        let start = self.visit_slice_bound(&slice.start)?;
+        let end = self.visit_slice_bound(&slice.end)?;
+
+        let class_name = self.get_class_name(obj)?;
+        let sliced = if class_name == "java.lang.String" {
+            let length = self.env.call_method(obj, "length", "()I", &[])?.i()?;
+            let (start, end) = slice_bounds(start, end, length)?;
+            self.env.call_method(
+                obj,
+                "substring",
+                "(II)Ljava/lang/String;",
+                &[JValue::Int(start), JValue::Int(end)],
+            )?
+        } else if self.env.is_instance_of(obj, "java/util/List")? {
+            let length = self.env.call_method(obj, "size", "()I", &[])?.i()?;
+            let (start, end) = slice_bounds(start, end, length)?;
+            self.env.call_method(
+                obj,
+                "subList",
+                "(II)Ljava/util/List;",
+                &[JValue::Int(start), JValue::Int(end)],
+            )?
+        } else if class_name.starts_with('[') {
+            let length = self.env.get_array_length(obj.into_inner())?;
+            let (start, end) = slice_bounds(start, end, length)?;
+            // object arrays are copied through Object[], which keeps their runtime type
+            let array_type = if is_primitive_array(&class_name) {
+                class_name.as_str()
+            } else {
+                "[Ljava/lang/Object;"
+            };
+            self.env.call_static_method(
+                "java/util/Arrays",
+                "copyOfRange",
+                format!("({}II){}", array_type, array_type),
+                &[JValue::Object(obj), JValue::Int(start), JValue::Int(end)],
+            )?
+        } else {
+            return Err(InterpreterError::TypeMismatch(format!(
+                "cannot slice a value of type '{}'",
+                class_name
+            ))
+            .into());
+        };
+        self.value_stack.push(InterpreterValue::ObjectRef(sliced));
+        Ok(())
+    }
+
+    /// Pushes the owner of a member expression, returning the members left to visit.
+    /// An unknown name may start a fully-qualified class name, in which case the
+    /// members making up the rest of it are consumed.
+    fn visit_member_owner<'a>(
+        &mut self,
+        owner: &Token,
+        members: &'a [Member],
+    ) -> anyhow::Result<&'a [Member]> {
+        let name = match &owner.kind {
+            TokenKind::Identifier(name) => name,
+            _ => {
+                self.visit_token(owner)?;
+                return Ok(members);
+            }
+        };
+        // a name is a variable if one is defined, otherwise a class
+        if let Some(value) = self
+            .resolve_variable(name)
+            .map_err(|err| with_span(err, owner.span))?
+        {
+            self.value_stack.push(InterpreterValue::ObjectRef(value));
+            return Ok(members);
+        }
+        let class = self
+            .resolve_class(name)
+            .map_err(|err| with_span(err, owner.span))?;
+        if let Some(class) = class {
+            self.value_stack.push(InterpreterValue::ClassRef(class));
+            return Ok(members);
+        }
+        let mut qualified_name = name.clone();
+        for (i, member) in members.iter().enumerate() {
+            let segment = match &member.kind {
+                MemberKind::Field(segment) => segment,
+                _ => break,
+            };
+            qualified_name.push('.');
+            qualified_name.push_str(segment);
+            if let Some(class) = self.resolve_class(&qualified_name)? {
+                self.value_stack.push(InterpreterValue::ClassRef(class));
+                return Ok(&members[i + 1..]);
+            }
+        }
+        let error = InterpreterError::UnknownIdentifier(name.clone());
+        Err(with_span(error.into(), owner.span))
+    }
+
+    /// What the members completed after `receiver.` come from. The receiver is
+    /// only evaluated as far as that has no side effects: variables, fields and
+    /// indices are read, but the result of a method call is described by the
+    /// method's return type. `None` if the receiver can't be resolved.
+    pub fn completion_target(
+        &mut self,
+        receiver: &Token,
+    ) -> anyhow::Result<Option<CompletionTarget>> {
+        self.state.started.set(Instant::now());
+        self.state.steps.set(0);
+        let stack_height = self.value_stack.len();
+        let target = self.find_completion_target(receiver);
+        self.value_stack.truncate(stack_height);
+        match target {
+            Ok(target) => Ok(target),
+            Err(err) => {
+                // an error only means there is nothing to complete, but a pending
+                // Java exception must be cleared
+                self.capture_exception(err);
+                Ok(None)
+            }
+        }
+    }
+
+    fn find_completion_target(
+        &mut self,
+        receiver: &Token,
+    ) -> anyhow::Result<Option<CompletionTarget>> {
+        let (owner, members) = match &receiver.kind {
+            TokenKind::MemberExpression(member_expr) => {
+                (member_expr.owner.as_ref(), &member_expr.members[..])
+            }
+            _ => (receiver, &[][..]),
+        };
+        let mut members = members.iter();
+        let mut value = match &owner.kind {
+            TokenKind::Identifier(name) => match self.resolve_variable(name)? {
+                Some(value) => InterpreterValue::ObjectRef(value),
+                None => match self.resolve_class(name)? {
+                    Some(class) => InterpreterValue::ClassRef(class),
+                    // a package, or a class qualified by one
+                    None => {
+                        let mut qualified_name = name.clone();
+                        loop {
+                            match members.next().map(|member| &member.kind) {
+                                Some(MemberKind::Field(segment)) => {
+                                    qualified_name.push('.');
+                                    qualified_name.push_str(segment);
+                                    if let Some(class) = self.resolve_class(&qualified_name)? {
+                                        break InterpreterValue::ClassRef(class);
+                                    }
+                                }
+                                None => return Ok(Some(CompletionTarget::Package(qualified_name))),
+                                Some(_) => return Ok(None),
+                            }
+                        }
+                    }
+                },
+            },
+            TokenKind::ObjectHandle(_) | TokenKind::Literal(_) => {
+                self.visit_token(owner)?;
+                self.pop_stack_value("receiver")?
+            }
+            _ => return Ok(None),
+        };
+
+        // read fields and indices until a method would have to be called
+        let mut method_name = None;
+        for member in members.by_ref() {
+            match &member.kind {
+                MemberKind::Field(field_name) => {
+                    self.visit_field_access(value, field_name.clone())?;
+                }
+                MemberKind::Index(index)
+                    if matches!(index.kind, TokenKind::Literal(_) | TokenKind::Identifier(_)) =>
+                {
+                    self.visit_index(value, index)?;
+                }
+                MemberKind::MethodCall(method_call) => {
+                    method_name = Some(&method_call.name);
+                    break;
+                }
+                _ => return Ok(None),
+            }
+            value = self.pop_stack_value("receiver")?;
+        }
+        let (mut class, mut is_static) = match value {
+            InterpreterValue::ClassRef(class) => (*self.env.find_class(class)?, true),
+            InterpreterValue::ObjectRef(JValue::Object(obj)) if !obj.is_null() => {
+                (*self.env.get_object_class(obj)?, false)
+            }
+            InterpreterValue::ObjectRef(_) => return Ok(None),
+        };
+        if let Some(method_name) = method_name {
+            class = match self.find_member_type(class, method_name, true, is_static)? {
+                Some(return_type) => return_type,
+                None => return Ok(None),
+            };
+            is_static = false;
+        }
+
+        // past a method call only declared types are known
+        for member in members {
+            let member_type = match &member.kind {
+                MemberKind::Field(field_name) => {
+                    self.find_member_type(class, field_name, false, false)?
+                }
+                MemberKind::MethodCall(method_call) => {
+                    self.find_member_type(class, &method_call.name, true, false)?
+                }
+                MemberKind::Index(_) => {
+                    let component = self
+                        .env
+                        .call_method(class, "getComponentType", "()Ljava/lang/Class;", &[])?
+                        .l()?;
+                    (!component.is_null()).then_some(component)
+                }
+                MemberKind::Slice(_) => Some(class),
+            };
+            class = match member_type {
+                Some(member_type) => member_type,
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(CompletionTarget::Members { class, is_static }))
+    }
+
+    fn find_member_type(
+        &self,
+        class: JObject<'static>,
+        name: &str,
+        is_method: bool,
+        is_static: bool,
+    ) -> anyhow::Result<Option<JObject<'static>>> {
+        let name_jstr = self.env.new_string(name)?;
+        let member_type = self
+            .env
+            .call_static_method(
+                "appstrument/server/ReflectionUtil",
+                "getMemberType",
+                "(Ljava/lang/Class;Ljava/lang/String;ZZ)Ljava/lang/Class;",
+                &[
+                    JValue::Object(class),
+                    JValue::Object(*name_jstr),
+                    JValue::Bool(is_method as u8),
+                    JValue::Bool(is_static as u8),
+                ],
+            )?
+            .l()?;
+        Ok((!member_type.is_null()).then_some(member_type))
+    }
+
+    /// Looks up a class by its binary or source name, e.g. `java.util.Map.Entry`,
+    /// returning its JNI name such as `java/util/Map$Entry`.
+    fn find_class(&self, name: &str) -> anyhow::Result<Option<String>> {
+        let name_jstr = self.env.new_string(name)?;
+        let class_name = self
+            .env
+            .call_static_method(
+                "appstrument/server/ReflectionUtil",
+                "resolveClassName",
+                "(Ljava/lang/String;)Ljava/lang/String;",
+                &[JValue::Object(*name_jstr)],
+            )?
+            .l()?;
+        if class_name.is_null() {
+            return Ok(None);
+        }
+        let class_name: String = self.env.get_string(JString::from(class_name))?.into();
+        Ok(Some(class_name.replace('.', "/")))
+    }
+
+    /// Resolves a class name as written in SLAT. The first segment of the name is
+    /// looked up through the explicit imports, then the packages imported on demand,
+    /// and any remaining segments name nested classes. Otherwise the name has to be
+    /// fully-qualified.
+    fn resolve_class(&mut self, name: &str) -> anyhow::Result<Option<String>> {
+        let state = &self.state;
+        let known = state.imports.borrow().get(name).cloned();
+        if let Some(class) = known.or_else(|| state.resolved_classes.borrow().get(name).cloned()) {
+            return Ok(Some(class));
+        }
+
+        // nested classes may be written as `Outer.Inner` or `Outer$Inner`
+        let (outer, nested) = name.split_at(name.find(['.', '$']).unwrap_or(name.len()));
+        let imported = state.imports.borrow().get(outer).cloned();
+        let class = if let Some(class) = imported {
+            self.find_class(&format!("{}{}", class.replace('/', "."), nested))?
+        } else {
+            let mut found = Vec::new();
+            let wildcard_imports = state.wildcard_imports.borrow().clone();
+            for package in &wildcard_imports {
+                if let Some(class) = self.find_class(&format!("{}.{}", package, outer))? {
+                    found.push(class);
+                }
+            }
+            match &found[..] {
+                [] if nested.is_empty() => None,
+                [] => self.find_class(name)?,
+                [class] => self.find_class(&format!("{}{}", class.replace('/', "."), nested))?,
+                _ => {
+                    let candidates = found
+                        .iter()
+                        .map(|class| class.replace('/', "."))
+                        .collect::<Vec<_>>();
+                    return Err(InterpreterError::AmbiguousClass(
+                        outer.to_owned(),
+                        candidates.join(", "),
+                    )
+                    .into());
+                }
+            }
+        };
+        if let Some(class) = &class {
+            state
+                .resolved_classes
+                .borrow_mut()
+                .insert(name.to_owned(), class.clone());
+        }
+        Ok(class)
+    }
+
+    fn visit_arguments(&mut self, args: &[Token]) -> anyhow::Result<Vec<JValue<'static>>> {
+        let mut args_values = Vec::with_capacity(args.len());
+        for arg in args {
+            self.visit_token(arg)?;
+            args_values.push(
+                self.value_stack
+                    .pop()
+                    .ok_or(InterpreterError::MalformedSlat(
+                        "expecting argument value".to_owned(),
+                    ))?
+                    .into_object_ref()?,
+            );
+        }
+        Ok(args_values)
+    }
+
+    /// Evaluates the arguments of a Java method or constructor call. Lambdas
+    /// without a cast are left as `Void` until the parameter they are passed to
+    /// is known, see `prepare_arguments`.
+    fn visit_java_arguments(&mut self, args: &[Token]) -> anyhow::Result<Vec<JValue<'static>>> {
+        let mut args_values = Vec::with_capacity(args.len());
+        for arg in args {
+            if lambda_argument(arg).is_some() {
+                args_values.push(JValue::Void);
+            } else {
+                args_values.extend(self.visit_arguments(std::slice::from_ref(arg))?);
+            }
+        }
+        Ok(args_values)
+    }
+
+    fn pop_stack_value(&mut self, expecting: &str) -> anyhow::Result<InterpreterValue> {
+        Ok(self
+            .value_stack
+            .pop()
+            .ok_or_else(|| InterpreterError::MalformedSlat(format!("expecting {}", expecting)))?)
+    }
+
+    fn pop_value(&mut self, expecting: &str) -> anyhow::Result<JValue<'static>> {
+        self.pop_stack_value(expecting)?.into_object_ref()
+    }
+
+    fn get_class_name(&self, obj: JObject) -> anyhow::Result<String> {
+        let class_obj = self.env.get_object_class(obj)?;
+        let class_name = self
+            .env
+            .call_method(class_obj, "getName", "()Ljava/lang/String;", &[])?
+            .l()?;
+        Ok(self.env.get_string(JString::from(class_name))?.into())
+    }
+
+    fn is_string(&self, value: JValue) -> anyhow::Result<bool> {
+        match value {
+            JValue::Object(obj) if !obj.is_null() => {
+                Ok(self.get_class_name(obj)? == "java.lang.String")
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Converts boxed primitives to their primitive values, leaving anything else as is.
+    fn unbox(&self, value: JValue<'static>) -> anyhow::Result<JValue<'static>> {
+        let obj = match value {
+            JValue::Object(obj) if !obj.is_null() => obj,
+            _ => return Ok(value),
+        };
+        let (method, signature) = match self.get_class_name(obj)?.as_str() {
+            "java.lang.Byte" => ("byteValue", "()B"),
+            "java.lang.Short" => ("shortValue", "()S"),
+            "java.lang.Integer" => ("intValue", "()I"),
+            "java.lang.Long" => ("longValue", "()J"),
+            "java.lang.Float" => ("floatValue", "()F"),
+            "java.lang.Double" => ("doubleValue", "()D"),
+            "java.lang.Character" => ("charValue", "()C"),
+            "java.lang.Boolean" => ("booleanValue", "()Z"),
+            _ => return Ok(value),
+        };
+        Ok(self.env.call_method(obj, method, signature, &[])?)
+    }
+
+    /// Converts a value to a string the way Java's string concatenation does.
+    fn to_java_string(&self, value: JValue<'static>) -> anyhow::Result<String> {
+        let (signature, argument) = match value {
+            JValue::Object(_) => ("(Ljava/lang/Object;)Ljava/lang/String;", value),
+            JValue::Byte(b) => ("(I)Ljava/lang/String;", JValue::Int(b as i32)),
+            JValue::Short(s) => ("(I)Ljava/lang/String;", JValue::Int(s as i32)),
+            JValue::Int(_) => ("(I)Ljava/lang/String;", value),
+            JValue::Long(_) => ("(J)Ljava/lang/String;", value),
+            JValue::Float(_) => ("(F)Ljava/lang/String;", value),
+            JValue::Double(_) => ("(D)Ljava/lang/String;", value),
+            JValue::Char(_) => ("(C)Ljava/lang/String;", value),
+            JValue::Bool(_) => ("(Z)Ljava/lang/String;", value),
+            JValue::Void => {
+                return Err(InterpreterError::InvalidOperands(
+                    "void cannot be converted to a string".to_owned(),
+                )
+                .into())
+            }
+        };
+        let string_object = self
+            .env
+            .call_static_method("java/lang/String", "valueOf", signature, &[argument])?
+            .l()?;
+        Ok(self.env.get_string(JString::from(string_object))?.into())
+    }
+
+    fn get_type_signature(&self, value: JValue) -> anyhow::Result<Option<String>> {
+        let signature = match value {
+            JValue::Object(obj) => {
+                if obj.is_null() {
+                    return Ok(None);
+                }
+                let class_name = self.get_class_name(obj)?.replace('.', "/");
+                if class_name.starts_with('[') {
+                    // array class names are already in descriptor form
+                    class_name
+                } else {
+                    format!("L{};", class_name)
+                }
+            }
+            JValue::Byte(_) => "B".to_owned(),
+            JValue::Short(_) => "S".to_owned(),
+            JValue::Int(_) => "I".to_owned(),
+            JValue::Long(_) => "J".to_owned(),
+            JValue::Float(_) => "F".to_owned(),
+            JValue::Double(_) => "D".to_owned(),
+            JValue::Char(_) => "C".to_owned(),
+            JValue::Bool(_) => "Z".to_owned(),
+            JValue::Void => "V".to_owned(),
+        };
+        Ok(Some(signature))
+    }
+
+    fn create_type_hints(
+        &self,
+        args_values: &[JValue],
+        args: &[Token],
+    ) -> anyhow::Result<JObject<'static>> {
+        let type_hints = self.env.new_object_array(
+            args_values.len() as i32,
+            "java/lang/String",
+            JObject::null(),
+        )?;
+        for (i, arg) in args_values.iter().enumerate() {
+            let signature = match lambda_argument(&args[i]) {
+                // lambdas only constrain the number of parameters of the functional interface
+                Some(lambda) => Some(format!("->{}", lambda.parameters.len())),
+                None => self.get_type_signature(*arg)?,
+            };
+            // null arguments are left as null type hints
+            if let Some(signature) = signature {
+                let arg_type = self.env.new_string(signature)?;
+                self.env
+                    .set_object_array_element(type_hints, i as i32, arg_type)?;
+            }
+        }
+        Ok(JObject::from(type_hints))
+    }
+
+    /// Picks the method chosen by `ReflectionUtil`'s overload resolution, or
+    /// `None` when no candidate is applicable.
+    fn resolve_overload(
+        &self,
+        candidates: JObject<'static>,
+        name: &str,
+    ) -> anyhow::Result<Option<ResolvedMethod>> {
+        let candidates = candidates.into_inner();
+        let mut resolved = Vec::new();
+        for i in 0..self.env.get_array_length(candidates)? {
+            let candidate = self.env.get_object_array_element(candidates, i)?;
+            let get_string = |field: &str| -> anyhow::Result<String> {
+                let value = self
+                    .env
+                    .get_field(candidate, field, "Ljava/lang/String;")?
+                    .l()?;
+                Ok(self.env.get_string(JString::from(value))?.into())
+            };
+            resolved.push(ResolvedMethod {
+                signature: get_string("signature")?,
+                description: get_string("description")?,
+                var_args: self.env.get_field(candidate, "varArgs", "Z")?.z()?,
+            });
+        }
+        if resolved.len() > 1 {
+            let descriptions: Vec<String> = resolved
+                .into_iter()
+                .map(|method| method.description)
+                .collect();
+            return Err(
+                InterpreterError::AmbiguousCall(name.to_owned(), descriptions.join(", ")).into(),
+            );
+        }
+        Ok(resolved.pop())
+    }
+
+    /// Converts arguments to the parameter types of a resolved method, collecting
+    /// the trailing ones into an array for variable arity invocations. Lambdas
+    /// implement the functional interface of the parameter they are passed to.
+    fn prepare_arguments(
+        &mut self,
+        method: &ResolvedMethod,
+        mut args_values: Vec<JValue<'static>>,
+        args: &[Token],
+    ) -> anyhow::Result<Vec<JValue<'static>>> {
+        let params = conversion::parameter_descriptors(&method.signature);
+        for (i, arg) in args.iter().enumerate() {
+            if let Some(lambda) = lambda_argument(arg) {
+                let param = match params.get(i) {
+                    Some(param) if !method.var_args || i + 1 < params.len() => param,
+                    _ => &params[params.len() - 1][1..],
+                };
+                let proxy = self.create_lambda(lambda, conversion::descriptor_class_name(param))?;
+                args_values[i] = JValue::Object(proxy);
+            } else if let TokenKind::ArrayLiteral(_) = arg.kind {
+                if let Some(param) = params.get(i) {
+                    args_values[i] = self.retype_array_literal(args_values[i], param)?;
+                }
+            }
+        }
+        if method.var_args {
+            let variable_args = args_values.split_off(params.len() - 1);
+            let component = &params[params.len() - 1][1..];
+            let array = self.new_array(component, variable_args)?;
+            args_values.push(JValue::Object(array));
+        }
+        params
+            .iter()
+            .zip(args_values)
+            .map(|(param, value)| self.convert_for_assignment(value, param))
+            .collect()
+    }
+
+    /// Creates an array with the given component type descriptor holding `values`.
+    fn new_array(
+        &self,
+        component: &str,
+        values: Vec<JValue<'static>>,
+    ) -> anyhow::Result<JObject<'static>> {
+        let values = values
+            .into_iter()
+            .map(|value| self.convert_for_assignment(value, component))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let array = self.allocate_array(component, values.len() as i32)?;
+        let array_raw = array.into_inner();
+
+        macro_rules! set_elements {
+            ($set_region:ident, $variant:ident) => {{
+                let elements: Vec<_> = values
+                    .into_iter()
+                    .map(|value| match value {
+                        JValue::$variant(element) => element,
+                        _ => unreachable!("converted to the component type"),
+                    })
+                    .collect();
+                self.env.$set_region(array_raw, 0, &elements)?;
+            }};
+        }
+        match component {
+            "B" => set_elements!(set_byte_array_region, Byte),
+            "S" => set_elements!(set_short_array_region, Short),
+            "I" => set_elements!(set_int_array_region, Int),
+            "J" => set_elements!(set_long_array_region, Long),
+            "F" => set_elements!(set_float_array_region, Float),
+            "D" => set_elements!(set_double_array_region, Double),
+            "C" => set_elements!(set_char_array_region, Char),
+            "Z" => set_elements!(set_boolean_array_region, Bool),
+            _ => {
+                for (i, value) in values.into_iter().enumerate() {
+                    self.env
+                        .set_object_array_element(array_raw, i as i32, value.l()?)?;
+                }
+            }
+        }
+        Ok(array)
+    }
+
+    /// Creates an array with the given component type descriptor, holding the
+    /// default value of that type.
+    fn allocate_array(&self, component: &str, length: i32) -> anyhow::Result<JObject<'static>> {
+        if length < 0 {
+            return Err(InterpreterError::NegativeArraySize(length).into());
+        }
+        let array = match component {
+            "B" => self.env.new_byte_array(length)?,
+            "S" => self.env.new_short_array(length)?,
+            "I" => self.env.new_int_array(length)?,
+            "J" => self.env.new_long_array(length)?,
+            "F" => self.env.new_float_array(length)?,
+            "D" => self.env.new_double_array(length)?,
+            "C" => self.env.new_char_array(length)?,
+            "Z" => self.env.new_boolean_array(length)?,
+            _ => {
+                let class = conversion::descriptor_class_name(component);
+                self.env.new_object_array(length, class, JObject::null())?
+            }
+        };
+        Ok(JObject::from(array))
+    }
+
+    /// Allocates a possibly multi-dimensional array, where `descriptor` is the
+    /// type of the whole array, e.g. `[[I` for `new int[2][3]`.
+    fn allocate_dimensions(
+        &self,
+        descriptor: &str,
+        dimensions: &[i32],
+    ) -> anyhow::Result<JObject<'static>> {
+        let component = &descriptor[1..];
+        let array = self.allocate_array(component, dimensions[0])?;
+        if dimensions.len() > 1 {
+            for i in 0..dimensions[0] {
+                let element = self.allocate_dimensions(component, &dimensions[1..])?;
+                self.env
+                    .set_object_array_element(array.into_inner(), i, element)?;
+                self.env.delete_local_ref(element)?;
+            }
+        }
+        Ok(array)
+    }
+
+    /// The component type of an array literal. Numeric elements are promoted to
+    /// a common primitive type, as in `[1, 2.5]` making a `double[]`, and other
+    /// elements make an array of their most specific common superclass.
+    fn infer_component_type(&self, values: &[JValue<'static>]) -> anyhow::Result<String> {
+        let primitives = values
+            .iter()
+            .map(|value| conversion::primitive_descriptor(*value))
+            .collect::<Option<Vec<_>>>();
+        if let Some(primitives) = primitives.filter(|primitives| !primitives.is_empty()) {
+            let promoted = ['Z', 'B', 'S', 'C', 'I', 'J', 'F', 'D']
+                .into_iter()
+                .find(|to| {
+                    primitives
+                        .iter()
+                        .all(|from| conversion::is_widening(*from, *to))
+                });
+            if let Some(promoted) = promoted {
+                return Ok(promoted.to_string());
+            }
+        }
+
+        let mut objects = Vec::with_capacity(values.len());
+        for value in values {
+            let obj = self.box_value(*value)?;
+            if !obj.is_null() {
+                objects.push(obj);
+            }
+        }
+        let mut class = match objects.first() {
+            Some(obj) => self.env.get_object_class(*obj)?,
+            None => return Ok("Ljava/lang/Object;".to_owned()),
+        };
+        // every object is an instance of java.lang.Object, so this terminates
+        while !objects
+            .iter()
+            .map(|obj| self.env.is_instance_of(*obj, class))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .all(|is_instance| is_instance)
+        {
+            class = self.env.get_superclass(class)?;
+        }
+        let class_name = self
+            .env
+            .call_method(class, "getName", "()Ljava/lang/String;", &[])?
+            .l()?;
+        let class_name: String = self.env.get_string(JString::from(class_name))?.into();
+        let class_name = class_name.replace('.', "/");
+        Ok(if class_name.starts_with('[') {
+            class_name
+        } else {
+            format!("L{};", class_name)
+        })
+    }
+
+    /// Copies an array literal into an array of the parameter's type when its
+    /// inferred type doesn't fit, e.g. `[1, 2]` passed to a `long[]` parameter.
+    fn retype_array_literal(
+        &self,
+        array: JValue<'static>,
+        param: &str,
+    ) -> anyhow::Result<JValue<'static>> {
+        let obj = array.l()?;
+        let class = conversion::descriptor_class_name(param);
+        if !param.starts_with('[') || self.env.is_instance_of(obj, class)? {
+            return Ok(array);
+        }
+        let elements = self
+            .env
+            .call_static_method(
+                "appstrument/server/ReflectionUtil",
+                "getListAsArray",
+                "(Ljava/lang/Object;)[Ljava/lang/Object;",
+                &[array],
+            )?
+            .l()?
+            .into_inner();
+        let length = self.env.get_array_length(elements)?;
+        let values = (0..length)
+            .map(|i| {
+                Ok(JValue::Object(
+                    self.env.get_object_array_element(elements, i)?,
+                ))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(JValue::Object(self.new_array(&param[1..], values)?))
+    }
+
+    fn visit_member_call(&mut self, method_call: &MethodCall) -> anyhow::Result<()> {
+        let args_values = self.visit_java_arguments(&method_call.args)?;
+        let type_hints = self.create_type_hints(&args_values, &method_call.args)?;
+
+        let value_ref = self.pop_stack_value("method owner")?;
+        let method_name_jstr = self.env.new_string(&method_call.name)?;
+        let candidates = match &value_ref {
             InterpreterValue::ClassRef(class) => {
-                let class_name_jstr = self.env.new_string(&class)?;
-                let method_name_jstr = self.env.new_string(&method_call.name)?;
-                let signature_object = self.env.call_static_method(
+                let class_name_jstr = self.env.new_string(class)?;
+                let candidates = self.env.call_static_method(
                     "appstrument/server/ReflectionUtil",
-                    "findStaticMethodSignature",
-                    "(Ljava/lang/String;Ljava/lang/String;[Ljava/lang/String;)Ljava/lang/String;",
+                    "findStaticMethodCandidates",
+                    "(Ljava/lang/String;Ljava/lang/String;[Ljava/lang/String;)[Lappstrument/server/JavaMethod;",
                     &[
                         JValue::Object(*class_name_jstr),
                         JValue::Object(*method_name_jstr),
                         JValue::Object(type_hints),
                     ],
                 )?.l()?;
-                if signature_object.is_null() {
-                    return Err(InterpreterError::NoSuchMethod(method_call.name).into());
-                }
-                let signature_str: String =
-                    self.env.get_string(JString::from(signature_object))?.into();
-                let result = self.env.call_static_method(
-                    class,
-                    method_call.name,
-                    signature_str,
-                    &args_values,
-                )?;
-                self.value_stack.push(InterpreterValue::ObjectRef(result));
+                candidates
             }
-            InterpreterValue::ObjectRef(object_instance) => {
-                let method_name_jstr = self.env.new_string(&method_call.name)?;
-                let signature_object = self.env.call_static_method(
+            InterpreterValue::ObjectRef(owner) => {
+                let object_instance = self.expect_object(*owner, &method_call.name)?;
+                let candidates = self.env.call_static_method(
                     "appstrument/server/ReflectionUtil",
-                    "findInstanceMethodSignature",
-                    "(Ljava/lang/Object;Ljava/lang/String;[Ljava/lang/String;)Ljava/lang/String;",
+                    "findInstanceMethodCandidates",
+                    "(Ljava/lang/Object;Ljava/lang/String;[Ljava/lang/String;)[Lappstrument/server/JavaMethod;",
                     &[
-                        object_instance,
+                        JValue::Object(object_instance),
                         JValue::Object(*method_name_jstr),
                         JValue::Object(type_hints),
                     ],
                 )?.l()?;
-                if signature_object.is_null() {
-                    return Err(InterpreterError::NoSuchMethod(method_call.name).into());
-                }
-                let signature_str: String =
-                    self.env.get_string(JString::from(signature_object))?.into();
-                let result = self.env.call_method(
-                    object_instance.l()?,
-                    method_call.name,
-                    signature_str,
-                    &args_values,
-                )?;
-                self.value_stack.push(InterpreterValue::ObjectRef(result));
+                candidates
+            }
+        };
+        let method = match self.resolve_overload(candidates, &method_call.name)? {
+            Some(method) => method,
+            None => return Err(InterpreterError::NoSuchMethod(method_call.name.clone()).into()),
+        };
+        let args_values = self.prepare_arguments(&method, args_values, &method_call.args)?;
+
+        let result = match value_ref {
+            InterpreterValue::ClassRef(class) => self.env.call_static_method(
+                class,
+                &method_call.name,
+                method.signature,
+                &args_values,
+            )?,
+            InterpreterValue::ObjectRef(owner) => self.env.call_method(
+                owner.l()?,
+                &method_call.name,
+                method.signature,
+                &args_values,
+            )?,
+        };
+        self.value_stack.push(InterpreterValue::ObjectRef(result));
+        Ok(())
+    }
+}
+
+/// Answers from the classes loaded in the app, with each question asked in a
+/// local frame of its own.
+impl ReflectionOracle for SlatInterpreter {
+    fn lookup_class(&mut self, name: &str) -> anyhow::Result<Option<String>> {
+        self.env.push_local_frame(LOCAL_FRAME_CAPACITY)?;
+        let result = self.find_class(name);
+        self.env.pop_local_frame(JObject::null())?;
+        result
+    }
+
+    fn has_field(&mut self, class: &str, name: &str, is_static: bool) -> anyhow::Result<bool> {
+        self.env.push_local_frame(LOCAL_FRAME_CAPACITY)?;
+        let result = (|| {
+            let class_jstr = self.env.new_string(class)?;
+            let name_jstr = self.env.new_string(name)?;
+            Ok(self
+                .env
+                .call_static_method(
+                    "appstrument/server/ReflectionUtil",
+                    "hasField",
+                    "(Ljava/lang/String;Ljava/lang/String;Z)Z",
+                    &[
+                        JValue::Object(*class_jstr),
+                        JValue::Object(*name_jstr),
+                        JValue::Bool(is_static as u8),
+                    ],
+                )?
+                .z()?)
+        })();
+        self.env.pop_local_frame(JObject::null())?;
+        result
+    }
+
+    fn methods(&mut self, class: &str, name: &str, is_static: bool) -> anyhow::Result<Vec<Arity>> {
+        self.env.push_local_frame(LOCAL_FRAME_CAPACITY)?;
+        let result = (|| {
+            let class_jstr = self.env.new_string(class)?;
+            let name_jstr = self.env.new_string(name)?;
+            let counts = self
+                .env
+                .call_static_method(
+                    "appstrument/server/ReflectionUtil",
+                    "getParameterCounts",
+                    "(Ljava/lang/String;Ljava/lang/String;Z)[I",
+                    &[
+                        JValue::Object(*class_jstr),
+                        JValue::Object(*name_jstr),
+                        JValue::Bool(is_static as u8),
+                    ],
+                )?
+                .l()?
+                .into_inner() as jintArray;
+            let mut buf = vec![0; self.env.get_array_length(counts)? as usize];
+            self.env.get_int_array_region(counts, 0, &mut buf)?;
+            // variable arity overloads are counted negatively
+            Ok(buf
+                .into_iter()
+                .map(|count| Arity {
+                    parameters: count.unsigned_abs() as usize,
+                    var_args: count < 0,
+                })
+                .collect())
+        })();
+        self.env.pop_local_frame(JObject::null())?;
+        result
+    }
+}
+
+impl Visitor for SlatInterpreter {
+    type Error = anyhow::Error;
+
+    fn visit_token(&mut self, token: &Token) -> anyhow::Result<()> {
+        let result = self.step().and_then(|_| visit::walk_token(self, token));
+        result.map_err(|err| with_span(self.capture_exception(err), token.span))
+    }
+
+    /// Runs a list of statements, discarding the values they leave behind.
+    fn visit_block(&mut self, body: &[Token]) -> anyhow::Result<()> {
+        let stack_height = self.value_stack.len();
+        for token in body {
+            self.visit_token(token)?;
+            if self.return_value.is_some() {
+                break;
             }
+        }
+        self.value_stack.truncate(stack_height);
+        Ok(())
+    }
+
+    fn visit_identifier(&mut self, ident: &str) -> anyhow::Result<()> {
+        // variables named like history bindings shadow them
+        let value = match self.resolve_variable(ident)? {
+            Some(value) => value,
+            None => match self.lookup_result(ident) {
+                Some(Some(value)) => value,
+                Some(None) => return Err(InterpreterError::UnknownResult(ident.to_owned()).into()),
+                None => return Err(InterpreterError::UnknownIdentifier(ident.to_owned()).into()),
+            },
         };
+        self.value_stack.push(InterpreterValue::ObjectRef(value));
         Ok(())
     }
 
-    fn visit_literal(&mut self, literal: Literal) -> anyhow::Result<()> {
+    fn visit_literal(&mut self, literal: &Literal) -> anyhow::Result<()> {
         let java_literal = match literal {
-            Literal::Boolean(b) => JValue::Bool(if b { 1 } else { 0 }),
+            Literal::Boolean(b) => JValue::Bool(if *b { 1 } else { 0 }),
             Literal::String(s) => {
+                // literals are interned as in Java, so equal literals are the same object
                 let jstring = self.env.new_string(s)?;
-                JValue::Object(*jstring)
+                self.env
+                    .call_method(jstring, "intern", "()Ljava/lang/String;", &[])?
             }
-            Literal::Decimal(d) => JValue::Float(d as f32),
-            Literal::Integer(i) => JValue::Int(i as i32),
+            Literal::Int(i) => JValue::Int(*i),
+            Literal::Long(l) => JValue::Long(*l),
+            Literal::Float(f) => JValue::Float(*f),
+            Literal::Double(d) => JValue::Double(*d),
+            Literal::Char(c) => JValue::Char(*c),
+            Literal::Null => JValue::Object(JObject::null()),
         };
         self.value_stack
             .push(InterpreterValue::ObjectRef(java_literal));
         Ok(())
     }
+
+    fn visit_import(&mut self, import: &Import) -> anyhow::Result<()> {
+        let full_name = import.path.join(".");
+        // earlier resolutions may be shadowed by, or made ambiguous by, the new import
+        self.state.resolved_classes.borrow_mut().clear();
+        if import.wildcard {
+            if import.alias.is_some() {
+                return Err(InterpreterError::MalformedSlat(
+                    "an on-demand import cannot be aliased".to_owned(),
+                )
+                .into());
+            }
+            let mut wildcard_imports = self.state.wildcard_imports.borrow_mut();
+            if !wildcard_imports.contains(&full_name) {
+                wildcard_imports.push(full_name);
+            }
+            return Ok(());
+        }
+
+        let class = self
+            .find_class(&full_name)?
+            .ok_or(InterpreterError::NoSuchClass(full_name))?;
+        let name = match &import.alias {
+            Some(alias) => alias.clone(),
+            None => import.path[import.path.len() - 1].clone(),
+        };
+        let mut imports = self.state.imports.borrow_mut();
+        match imports.get(&name) {
+            // importing the same class twice is harmless
+            Some(existing) if *existing != class => {
+                Err(InterpreterError::DuplicateImport(name).into())
+            }
+            _ => {
+                imports.insert(name, class);
+                Ok(())
+            }
+        }
+    }
+
+    fn visit_member_expression(&mut self, member_expr: &MemberExpression) -> anyhow::Result<()> {
+        let members = self.visit_member_owner(&member_expr.owner, &member_expr.members)?;
+        for member in members {
+            self.visit_member(member)?;
+        }
+        Ok(())
+    }
+
+    fn visit_member(&mut self, member: &Member) -> anyhow::Result<()> {
+        let result = match &member.kind {
+            MemberKind::Field(field_name) => {
+                let owner = self.pop_stack_value("field owner")?;
+                self.visit_field_access(owner, field_name.clone())
+            }
+            MemberKind::MethodCall(method_call) => self.visit_member_call(method_call),
+            MemberKind::Index(index) => {
+                let owner = self.pop_stack_value("indexed value")?;
+                self.visit_index(owner, index)
+            }
+            MemberKind::Slice(slice) => {
+                let owner = self.pop_stack_value("sliced value")?;
+                self.visit_slice(owner, slice)
+            }
+        };
+        result.map_err(|err| with_span(err, member.span))
+    }
+
+    fn visit_method_call(&mut self, function_call: &MethodCall) -> anyhow::Result<()> {
+        // methods of `this` shadow functions, like its fields shadow variables
+        if let Some(owner) = self.this_method_owner(&function_call.name)? {
+            self.value_stack.push(owner);
+            return self.visit_member_call(function_call);
+        }
+        let function = self
+            .state
+            .functions
+            .borrow()
+            .get(&function_call.name)
+            .cloned();
+        let function = match function {
+            Some(function) => function,
+            None => match self.state.intrinsics.get(function_call.name.as_str()) {
+                Some(intrinsic) => {
+                    return self.visit_intrinsic_call(*intrinsic, &function_call.args)
+                }
+                None => {
+                    return Err(InterpreterError::NoSuchFunction(function_call.name.clone()).into())
+                }
+            },
+        };
+        if function.parameters.len() != function_call.args.len() {
+            return Err(InterpreterError::ArgumentCount(
+                function.name.clone(),
+                function.parameters.len(),
+                function_call.args.len(),
+            )
+            .into());
+        }
+        self.check_recursion()?;
+        let args_values = self.visit_arguments(&function_call.args)?;
+
+        self.env.push_local_frame(LOCAL_FRAME_CAPACITY)?;
+        let depth = self.locals.len();
+        self.push_scope(Scope::default());
+        let result = self.run_function_body(&function, args_values);
+        self.pop_scopes(depth);
+        self.pop_local_frame()?;
+        result?;
+
+        // functions without a return statement evaluate to void, like Java methods
+        let value = self.return_value.take().unwrap_or(JValue::Void);
+        self.value_stack.push(InterpreterValue::ObjectRef(value));
+        Ok(())
+    }
+
+    fn visit_new(&mut self, new_expr: &NewExpression) -> anyhow::Result<()> {
+        let class = match self.resolve_class(&new_expr.class_name)? {
+            Some(class) => class,
+            None => return Err(InterpreterError::NoSuchClass(new_expr.class_name.clone()).into()),
+        };
+        let args_values = self.visit_java_arguments(&new_expr.args)?;
+        let type_hints = self.create_type_hints(&args_values, &new_expr.args)?;
+
+        let class_name_jstr = self.env.new_string(&class)?;
+        let candidates = self
+            .env
+            .call_static_method(
+                "appstrument/server/ReflectionUtil",
+                "findConstructorCandidates",
+                "(Ljava/lang/String;[Ljava/lang/String;)[Lappstrument/server/JavaMethod;",
+                &[JValue::Object(*class_name_jstr), JValue::Object(type_hints)],
+            )?
+            .l()?;
+        let constructor = match self.resolve_overload(candidates, &new_expr.class_name)? {
+            Some(constructor) => constructor,
+            None => {
+                return Err(InterpreterError::NoSuchConstructor(new_expr.class_name.clone()).into())
+            }
+        };
+        let args_values = self.prepare_arguments(&constructor, args_values, &new_expr.args)?;
+        let instance = self
+            .env
+            .new_object(class, constructor.signature, &args_values)?;
+        self.value_stack
+            .push(InterpreterValue::ObjectRef(JValue::Object(instance)));
+        Ok(())
+    }
+
+    fn visit_new_array(&mut self, new_array: &NewArray) -> anyhow::Result<()> {
+        let element_type = match conversion::primitive_descriptor_by_name(&new_array.class_name) {
+            Some(descriptor) => descriptor.to_string(),
+            None => match self.resolve_class(&new_array.class_name)? {
+                Some(class) => format!("L{};", class),
+                None => {
+                    return Err(InterpreterError::NoSuchClass(new_array.class_name.clone()).into())
+                }
+            },
+        };
+        let mut dimensions = Vec::with_capacity(new_array.dimensions.len());
+        for dimension in &new_array.dimensions {
+            self.visit_token(dimension)?;
+            let length = self.pop_value("array length")?;
+            let length = self.convert_for_assignment(length, "I")?.i()?;
+            dimensions.push(length);
+        }
+        let rank = new_array.dimensions.len() + new_array.unsized_dimensions;
+        let descriptor = format!("{}{}", "[".repeat(rank), element_type);
+        let array = self.allocate_dimensions(&descriptor, &dimensions)?;
+        self.value_stack
+            .push(InterpreterValue::ObjectRef(JValue::Object(array)));
+        Ok(())
+    }
+
+    fn visit_array_literal(&mut self, elements: &[Token]) -> anyhow::Result<()> {
+        let values = self.visit_arguments(elements)?;
+        let component = self.infer_component_type(&values)?;
+        let array = self.new_array(&component, values)?;
+        self.value_stack
+            .push(InterpreterValue::ObjectRef(JValue::Object(array)));
+        Ok(())
+    }
+
+    fn visit_binary_expression(&mut self, binary_expr: &BinaryExpression) -> anyhow::Result<()> {
+        let operator = binary_expr.operator;
+        self.visit_token(&binary_expr.left)?;
+        let left = self.pop_value("left operand")?;
+
+        if matches!(operator, BinaryOperator::And | BinaryOperator::Or) {
+            let left = match self.unbox(left)? {
+                JValue::Bool(b) => b == 1,
+                _ => {
+                    return Err(InterpreterError::InvalidOperands(format!(
+                        "operator '{}' expects boolean operands",
+                        operators::operator_symbol(operator)
+                    ))
+                    .into())
+                }
+            };
+            // the right operand is only evaluated when it decides the result
+            if left == (operator == BinaryOperator::Or) {
+                self.value_stack
+                    .push(InterpreterValue::ObjectRef(JValue::Bool(left as u8)));
+                return Ok(());
+            }
+            self.visit_token(&binary_expr.right)?;
+            let right = self.pop_value("right operand")?;
+            let result = operators::apply_binary_operator(
+                operator,
+                JValue::Bool(left as u8),
+                self.unbox(right)?,
+            )?;
+            self.value_stack.push(InterpreterValue::ObjectRef(result));
+            return Ok(());
+        }
+
+        self.visit_token(&binary_expr.right)?;
+        let right = self.pop_value("right operand")?;
+
+        let result = if operator == BinaryOperator::Add
+            && (self.is_string(left)? || self.is_string(right)?)
+        {
+            let concatenated = self.to_java_string(left)? + &self.to_java_string(right)?;
+            JValue::Object(*self.env.new_string(concatenated)?)
+        } else if let (
+            BinaryOperator::Equal | BinaryOperator::NotEqual,
+            JValue::Object(left),
+            JValue::Object(right),
+        ) = (operator, left, right)
+        {
+            // two references are compared by identity, even if they are boxed primitives
+            let same_object = self.env.is_same_object(left, right)?;
+            JValue::Bool((same_object == (operator == BinaryOperator::Equal)) as u8)
+        } else {
+            operators::apply_binary_operator(operator, self.unbox(left)?, self.unbox(right)?)?
+        };
+        self.value_stack.push(InterpreterValue::ObjectRef(result));
+        Ok(())
+    }
+
+    fn visit_unary_expression(&mut self, unary_expr: &UnaryExpression) -> anyhow::Result<()> {
+        self.visit_token(&unary_expr.operand)?;
+        let operand = self.pop_value("operand")?;
+        let result = operators::apply_unary_operator(unary_expr.operator, self.unbox(operand)?)?;
+        self.value_stack.push(InterpreterValue::ObjectRef(result));
+        Ok(())
+    }
+
+    fn visit_assignment(&mut self, assignment: &Assignment) -> anyhow::Result<()> {
+        let member_expr = match &assignment.target.kind {
+            TokenKind::Identifier(variable) => {
+                let is_local = self
+                    .locals
+                    .last()
+                    .is_some_and(|locals| locals.contains(variable));
+                if !is_local {
+                    if let Some(owner) = self.this_field_owner(variable)? {
+                        return self.visit_field_assignment(owner, variable, &assignment.expr);
+                    }
+                }
+                self.visit_token(&assignment.expr)?;
+                let value = self.pop_value("value")?;
+                return self.set_variable(variable, value);
+            }
+            TokenKind::MemberExpression(member_expr) => member_expr,
+            _ => {
+                return Err(
+                    InterpreterError::MalformedSlat("invalid assignment target".to_owned()).into(),
+                )
+            }
+        };
+
+        // like Java, the target's owner and index are evaluated before the value
+        let (target, owner_members) = member_expr.members.split_last().expect("unreachable");
+        let owner_members = self.visit_member_owner(&member_expr.owner, owner_members)?;
+        for member in owner_members {
+            self.visit_member(member)?;
+        }
+        let owner = self.pop_stack_value("assignment target")?;
+        let result = match &target.kind {
+            MemberKind::Field(field_name) => {
+                self.visit_field_assignment(owner, field_name, &assignment.expr)
+            }
+            MemberKind::Index(index) => {
+                let array = self.expect_object(owner.into_object_ref()?, "[]")?;
+                self.visit_token(index)?;
+                let index = self.pop_value("index")?;
+                self.visit_token(&assignment.expr)?;
+                let value = self.pop_value("value")?;
+                self.assign_index(array, index, value)
+            }
+            MemberKind::Slice(_) => {
+                Err(InterpreterError::MalformedSlat("cannot assign to a slice".to_owned()).into())
+            }
+            MemberKind::MethodCall(method_call) => Err(InterpreterError::MalformedSlat(format!(
+                "cannot assign to the result of '{}()'",
+                method_call.name
+            ))
+            .into()),
+        };
+        result.map_err(|err| with_span(err, target.span))
+    }
+
+    fn visit_directive(&mut self, directive: &Directive) -> anyhow::Result<()> {
+        let handler = match self.state.directives.get(directive.name.as_str()) {
+            Some(handler) => *handler,
+            None => return Err(InterpreterError::UnknownDirective(directive.name.clone()).into()),
+        };
+        self.visit_token(&directive.value)?;
+        let value = self
+            .value_stack
+            .pop()
+            .ok_or(InterpreterError::MalformedSlat(
+                "expecting directive value".to_owned(),
+            ))?
+            .into_object_ref()?;
+        let mut options = self.options();
+        handler(&mut options, value)?;
+        self.state.options.set(options);
+        Ok(())
+    }
+
+    fn visit_if_statement(&mut self, if_statement: &IfStatement) -> anyhow::Result<()> {
+        if self.visit_condition(&if_statement.condition)? {
+            self.visit_block(&if_statement.body)
+        } else if let Some(else_body) = &if_statement.else_body {
+            self.visit_block(else_body)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn visit_while_loop(&mut self, while_loop: &WhileLoop) -> anyhow::Result<()> {
+        let mut iterations = 0;
+        loop {
+            self.env.push_local_frame(LOCAL_FRAME_CAPACITY)?;
+            let result = self.run_while_iteration(while_loop, &mut iterations);
+            self.pop_local_frame()?;
+            if !result? || self.return_value.is_some() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn visit_for_each_loop(&mut self, for_each: &ForEachLoop) -> anyhow::Result<()> {
+        self.visit_token(&for_each.iterable)?;
+        let iterable = self.pop_value("iterable")?;
+        let source = self.get_iteration_source(iterable)?;
+
+        let mut iterations = 0;
+        let mut index = 0;
+        loop {
+            self.env.push_local_frame(LOCAL_FRAME_CAPACITY)?;
+            let result = self.run_for_each_iteration(for_each, &source, index, &mut iterations);
+            self.pop_local_frame()?;
+            if !result? || self.return_value.is_some() {
+                return Ok(());
+            }
+            index += 1;
+        }
+    }
+
+    fn visit_function(&mut self, function: &Rc<FunctionDefinition>) -> anyhow::Result<()> {
+        self.state
+            .functions
+            .borrow_mut()
+            .insert(function.name.clone(), Rc::clone(function));
+        Ok(())
+    }
+
+    fn visit_return(&mut self, return_statement: &ReturnStatement) -> anyhow::Result<()> {
+        if self.locals.is_empty() {
+            return Err(
+                InterpreterError::MalformedSlat("return outside of a function".to_owned()).into(),
+            );
+        }
+        let value = match &return_statement.value {
+            Some(value) => {
+                self.visit_token(value)?;
+                self.pop_value("return value")?
+            }
+            None => JValue::Void,
+        };
+        self.return_value = Some(value);
+        Ok(())
+    }
+
+    fn visit_try_statement(&mut self, try_statement: &TryStatement) -> anyhow::Result<()> {
+        let stack_height = self.value_stack.len();
+        let error = match self.visit_block(&try_statement.body) {
+            Ok(()) => return Ok(()),
+            Err(error) => error,
+        };
+        // only Java exceptions can be caught, errors of the script itself propagate
+        let throwable = match thrown_exception(&error) {
+            Some(exception) => exception.throwable.clone(),
+            None => return Err(error),
+        };
+        self.value_stack.truncate(stack_height);
+
+        let throwable_obj = JObject::from(throwable.as_obj().into_inner());
+        for catch_clause in &try_statement.catch_clauses {
+            if let Some(class_name) = &catch_clause.class_name {
+                let class = self
+                    .resolve_class(class_name)?
+                    .ok_or_else(|| InterpreterError::NoSuchClass(class_name.clone()))?;
+                if !self.env.is_instance_of(throwable_obj, class.as_str())? {
+                    continue;
+                }
+            }
+            self.set_variable(&catch_clause.variable, JValue::Object(throwable_obj))?;
+            return self.visit_block(&catch_clause.body);
+        }
+        Err(error)
+    }
+
+    fn visit_throw_statement(&mut self, throw_statement: &ThrowStatement) -> anyhow::Result<()> {
+        self.visit_token(&throw_statement.value)?;
+        let throwable = match self.pop_value("exception")? {
+            JValue::Object(obj) if obj.is_null() => {
+                return Err(InterpreterError::NullReference("throw".to_owned()).into())
+            }
+            JValue::Object(obj) if self.env.is_instance_of(obj, "java/lang/Throwable")? => obj,
+            JValue::Object(obj) => {
+                return Err(InterpreterError::TypeMismatch(format!(
+                    "cannot throw a value of type '{}'",
+                    self.get_class_name(obj)?
+                ))
+                .into())
+            }
+            other => {
+                return Err(InterpreterError::TypeMismatch(format!(
+                    "cannot throw a value of type {}",
+                    other.type_name()
+                ))
+                .into())
+            }
+        };
+        Err(InterpreterError::JavaException(self.describe_exception(throwable)?).into())
+    }
+
+    fn visit_lambda(&mut self, lambda: &Rc<Lambda>) -> anyhow::Result<()> {
+        let interface = match &lambda.interface {
+            Some(interface) => interface,
+            None => {
+                return Err(InterpreterError::TypeMismatch(
+                    "the functional interface of this lambda cannot be inferred, cast it like '(Runnable) () -> ...'"
+                        .to_owned(),
+                )
+                .into())
+            }
+        };
+        let class = match self.resolve_class(interface)? {
+            Some(class) => class,
+            None => return Err(InterpreterError::NoSuchClass(interface.clone()).into()),
+        };
+        let proxy = self.create_lambda(lambda, &class)?;
+        self.value_stack
+            .push(InterpreterValue::ObjectRef(JValue::Object(proxy)));
+        Ok(())
+    }
+
+    fn visit_object_handle(&mut self, id: usize) -> anyhow::Result<()> {
+        let object = self
+            .state
+            .object_handles
+            .borrow()
+            .get(id)
+            .cloned()
+            .ok_or(InterpreterError::UnknownHandle(id))?;
+        let object = self
+            .env
+            .new_local_ref::<JObject>(JObject::from(object.as_obj().into_inner()))?;
+        self.value_stack
+            .push(InterpreterValue::ObjectRef(JValue::Object(object)));
+        Ok(())
+    }
+
+    /// Evaluates to the variables of the current scope as a sorted `String[]` of
+    /// `name = value` lines.
+    fn visit_vars(&mut self) -> anyhow::Result<()> {
+        let variables = self.variables();
+
+        let lines = self.env.new_object_array(
+            variables.len() as i32,
+            "java/lang/String",
+            JObject::null(),
+        )?;
+        // local references are released as they go, a scope can hold many variables
+        for (i, (name, value)) in variables.into_iter().enumerate() {
+            let boxed = self.box_value(value)?;
+            let display = self
+                .env
+                .call_static_method(
+                    "appstrument/server/ReflectionUtil",
+                    "toDisplayString",
+                    "(Ljava/lang/Object;)Ljava/lang/String;",
+                    &[JValue::Object(boxed)],
+                )?
+                .l()?;
+            let display_string: String = self.env.get_string(JString::from(display))?.into();
+            let line = self
+                .env
+                .new_string(format!("{} = {}", name, display_string))?;
+            self.env.set_object_array_element(lines, i as i32, line)?;
+            self.env.delete_local_ref(line.into())?;
+            self.env.delete_local_ref(display)?;
+            if !matches!(value, JValue::Object(_)) {
+                self.env.delete_local_ref(boxed)?;
+            }
+        }
+        self.value_stack
+            .push(InterpreterValue::ObjectRef(JValue::Object(lines.into())));
+        Ok(())
+    }
+
+    fn visit_delete(&mut self, names: &[String]) -> anyhow::Result<()> {
+        for name in names {
+            let removed = self.with_current_scope(|scope| {
+                scope.object_variables.remove(name).is_some()
+                    || scope.primitive_variables.remove(name).is_some()
+            });
+            if !removed {
+                return Err(InterpreterError::UnknownIdentifier(name.clone()).into());
+            }
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+
+use jni::{
+    objects::{JObject, JString, JValue},
+    JNIEnv,
+};
+
+use super::{conversion, interpreter::InterpreterError};
+
+/// Evaluates an intrinsic with arguments matching its parameters. A class name
+/// passed as an argument, as in `fields(System)`, is given as its `Class` object.
+pub type IntrinsicHandler =
+    fn(JNIEnv<'static>, &[JValue<'static>]) -> anyhow::Result<JValue<'static>>;
+
+/// A function built into SLAT, called like a function defined by the script.
+/// Functions defined by the script take precedence over intrinsics of the same name.
+#[derive(Clone, Copy)]
+pub struct Intrinsic {
+    pub name: &'static str,
+    pub parameters: &'static [&'static str],
+    /// What the intrinsic evaluates to, shown to users looking it up.
+    pub description: &'static str,
+    pub handler: IntrinsicHandler,
+}
+
+impl Intrinsic {
+    /// The intrinsic as it is called, e.g. `len(x)`.
+    pub fn signature(&self) -> String {
+        format!("{}({})", self.name, self.parameters.join(", "))
+    }
+}
+
+const INTRINSICS: &[Intrinsic] = &[
+    Intrinsic {
+        name: "typeof",
+        parameters: &["x"],
+        description: "The name of the type of x: a primitive type such as \"int\", \
+            \"null\", or the binary name of the class of an object.",
+        handler: type_of,
+    },
+    Intrinsic {
+        name: "classOf",
+        parameters: &["x"],
+        description: "The Class of x, which is the primitive class, such as int.class, \
+            for primitives.",
+        handler: class_of,
+    },
+    Intrinsic {
+        name: "len",
+        parameters: &["x"],
+        description: "The length of an array or CharSequence, or the size of a \
+            Collection or Map, as an int.",
+        handler: len,
+    },
+    Intrinsic {
+        name: "str",
+        parameters: &["x"],
+        description: "x converted to a String like String.valueOf does, except that \
+            the elements of arrays are spelled out.",
+        handler: str,
+    },
+    Intrinsic {
+        name: "identity",
+        parameters: &["x"],
+        description: "The identity hash code of an object, which tells apart objects \
+            that are equal but not the same. It is 0 for null.",
+        handler: identity,
+    },
+    Intrinsic {
+        name: "fields",
+        parameters: &["x"],
+        description: "The instance fields of an object, or the static fields of a \
+            class, with their values, as a String[].",
+        handler: fields,
+    },
+    Intrinsic {
+        name: "methods",
+        parameters: &["x"],
+        description: "The instance methods of an object, or the static methods of a \
+            class, as a String[]. Overridden methods are listed once.",
+        handler: methods,
+    },
+    Intrinsic {
+        name: "supers",
+        parameters: &["x"],
+        description: "The superclasses of the class of an object, or of a class, \
+            followed by the interfaces it implements, as a String[].",
+        handler: supers,
+    },
+    Intrinsic {
+        name: "describe",
+        parameters: &["x"],
+        description: "A summary of an object or a class listing its supertypes, \
+            fields and methods, as a String.",
+        handler: describe,
+    },
+    Intrinsic {
+        name: "sleep",
+        parameters: &["ms"],
+        description: "Pauses the evaluating thread for the given number of milliseconds.",
+        handler: sleep,
+    },
+    Intrinsic {
+        name: "time",
+        parameters: &[],
+        description: "The current time in milliseconds since the Unix epoch, as a long.",
+        handler: time,
+    },
+    Intrinsic {
+        name: "gc",
+        parameters: &[],
+        description: "Suggests that the Java virtual machine runs the garbage collector.",
+        handler: gc,
+    },
+];
+
+pub fn default_intrinsics() -> HashMap<&'static str, Intrinsic> {
+    INTRINSICS
+        .iter()
+        .map(|intrinsic| (intrinsic.name, *intrinsic))
+        .collect()
+}
+
+fn new_string(env: JNIEnv<'static>, value: &str) -> anyhow::Result<JValue<'static>> {
+    Ok(JValue::Object(*env.new_string(value)?))
+}
+
+fn class_name(env: JNIEnv<'static>, obj: JObject<'static>) -> anyhow::Result<String> {
+    let class = env.get_object_class(obj)?;
+    let name = env
+        .call_method(class, "getName", "()Ljava/lang/String;", &[])?
+        .l()?;
+    Ok(env.get_string(JString::from(name))?.into())
+}
+
+/// The argument as a non-null object, boxing primitives.
+fn object_argument(
+    env: JNIEnv<'static>,
+    intrinsic: &str,
+    value: JValue<'static>,
+) -> anyhow::Result<JObject<'static>> {
+    let obj = conversion::box_value(env, value)?;
+    if obj.is_null() {
+        return Err(InterpreterError::NullReference(format!("{}()", intrinsic)).into());
+    }
+    Ok(obj)
+}
+
+fn integer_argument(
+    env: JNIEnv<'static>,
+    intrinsic: &str,
+    value: JValue<'static>,
+) -> anyhow::Result<i64> {
+    let integer = match value {
+        JValue::Byte(b) => Some(b as i64),
+        JValue::Short(s) => Some(s as i64),
+        JValue::Int(i) => Some(i as i64),
+        JValue::Long(l) => Some(l),
+        JValue::Object(obj)
+            if !obj.is_null()
+                && [
+                    "java/lang/Byte",
+                    "java/lang/Short",
+                    "java/lang/Integer",
+                    "java/lang/Long",
+                ]
+                .iter()
+                .map(|class| env.is_instance_of(obj, *class))
+                .collect::<Result<Vec<_>, _>>()?
+                .contains(&true) =>
+        {
+            Some(env.call_method(obj, "longValue", "()J", &[])?.j()?)
+        }
+        _ => None,
+    };
+    integer.ok_or_else(|| {
+        InterpreterError::TypeMismatch(format!(
+            "{}() expects an integer, found {}",
+            intrinsic,
+            value.type_name()
+        ))
+        .into()
+    })
+}
+
+fn call_reflection_util(
+    env: JNIEnv<'static>,
+    method: &str,
+    return_type: &str,
+    target: JObject<'static>,
+) -> anyhow::Result<JValue<'static>> {
+    Ok(env.call_static_method(
+        "appstrument/server/ReflectionUtil",
+        method,
+        format!("(Ljava/lang/Object;){}", return_type),
+        &[JValue::Object(target)],
+    )?)
+}
+
+fn type_of(env: JNIEnv<'static>, args: &[JValue<'static>]) -> anyhow::Result<JValue<'static>> {
+    match args[0] {
+        JValue::Object(obj) if obj.is_null() => new_string(env, "null"),
+        JValue::Object(obj) => new_string(env, &class_name(env, obj)?),
+        JValue::Void => new_string(env, "void"),
+        primitive => {
+            let descriptor = conversion::primitive_descriptor(primitive).expect("unreachable");
+            new_string(env, conversion::primitive_name(descriptor))
+        }
+    }
+}
+
+fn class_of(env: JNIEnv<'static>, args: &[JValue<'static>]) -> anyhow::Result<JValue<'static>> {
+    if let Some(descriptor) = conversion::primitive_descriptor(args[0]) {
+        return Ok(env.get_static_field(
+            conversion::box_class(descriptor),
+            "TYPE",
+            "Ljava/lang/Class;",
+        )?);
+    }
+    let obj = object_argument(env, "classOf", args[0])?;
+    Ok(JValue::Object(*env.get_object_class(obj)?))
+}
+
+fn len(env: JNIEnv<'static>, args: &[JValue<'static>]) -> anyhow::Result<JValue<'static>> {
+    let obj = object_argument(env, "len", args[0])?;
+    let length = if class_name(env, obj)?.starts_with('[') {
+        env.get_array_length(obj.into_inner())?
+    } else if env.is_instance_of(obj, "java/lang/CharSequence")? {
+        env.call_method(obj, "length", "()I", &[])?.i()?
+    } else if env.is_instance_of(obj, "java/util/Collection")?
+        || env.is_instance_of(obj, "java/util/Map")?
+    {
+        env.call_method(obj, "size", "()I", &[])?.i()?
+    } else {
+        return Err(InterpreterError::TypeMismatch(format!(
+            "cannot take the length of a value of type '{}'",
+            class_name(env, obj)?
+        ))
+        .into());
+    };
+    Ok(JValue::Int(length))
+}
+
+fn str(env: JNIEnv<'static>, args: &[JValue<'static>]) -> anyhow::Result<JValue<'static>> {
+    let value = conversion::box_value(env, args[0])?;
+    call_reflection_util(env, "toDisplayString", "Ljava/lang/String;", value)
+}
+
+fn identity(env: JNIEnv<'static>, args: &[JValue<'static>]) -> anyhow::Result<JValue<'static>> {
+    let obj = match args[0] {
+        JValue::Object(obj) => obj,
+        primitive => {
+            return Err(InterpreterError::TypeMismatch(format!(
+                "identity() expects an object, found {}",
+                primitive.type_name()
+            ))
+            .into())
+        }
+    };
+    Ok(env.call_static_method(
+        "java/lang/System",
+        "identityHashCode",
+        "(Ljava/lang/Object;)I",
+        &[JValue::Object(obj)],
+    )?)
+}
+
+fn fields(env: JNIEnv<'static>, args: &[JValue<'static>]) -> anyhow::Result<JValue<'static>> {
+    let target = object_argument(env, "fields", args[0])?;
+    call_reflection_util(env, "describeFields", "[Ljava/lang/String;", target)
+}
+
+fn methods(env: JNIEnv<'static>, args: &[JValue<'static>]) -> anyhow::Result<JValue<'static>> {
+    let target = object_argument(env, "methods", args[0])?;
+    call_reflection_util(env, "describeMethods", "[Ljava/lang/String;", target)
+}
+
+fn supers(env: JNIEnv<'static>, args: &[JValue<'static>]) -> anyhow::Result<JValue<'static>> {
+    let target = object_argument(env, "supers", args[0])?;
+    call_reflection_util(env, "getSupertypes", "[Ljava/lang/String;", target)
+}
+
+fn describe(env: JNIEnv<'static>, args: &[JValue<'static>]) -> anyhow::Result<JValue<'static>> {
+    let target = object_argument(env, "describe", args[0])?;
+    call_reflection_util(env, "describe", "Ljava/lang/String;", target)
+}
+
+fn sleep(env: JNIEnv<'static>, args: &[JValue<'static>]) -> anyhow::Result<JValue<'static>> {
+    let millis = integer_argument(env, "sleep", args[0])?;
+    if millis < 0 {
+        return Err(InterpreterError::TypeMismatch(format!(
+            "sleep() expects a non-negative duration, found {}",
+            millis
+        ))
+        .into());
+    }
+    env.call_static_method("java/lang/Thread", "sleep", "(J)V", &[JValue::Long(millis)])?;
+    Ok(JValue::Void)
+}
+
+fn time(env: JNIEnv<'static>, _args: &[JValue<'static>]) -> anyhow::Result<JValue<'static>> {
+    Ok(env.call_static_method("java/lang/System", "currentTimeMillis", "()J", &[])?)
+}
+
+fn gc(env: JNIEnv<'static>, _args: &[JValue<'static>]) -> anyhow::Result<JValue<'static>> {
+    env.call_static_method("java/lang/System", "gc", "()V", &[])?;
+    Ok(JValue::Void)
+}
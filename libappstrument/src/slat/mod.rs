@@ -1,3 +1,12 @@
 pub mod parser;
 pub mod ast;
+pub mod cancel;
+pub mod check;
+pub mod completion;
+pub mod conversion;
+pub mod diagnostic;
 pub mod interpreter;
+pub mod intrinsics;
+pub mod operators;
+pub mod printer;
+pub mod visit;
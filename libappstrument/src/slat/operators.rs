@@ -0,0 +1,264 @@
+use jni::objects::JValue;
+
+use super::{
+    ast::{BinaryOperator, UnaryOperator},
+    interpreter::InterpreterError,
+};
+
+/// A primitive after numeric promotion, see JLS §5.6.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Numeric {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+}
+
+impl Numeric {
+    fn as_long(self) -> i64 {
+        match self {
+            Numeric::Int(i) => i as i64,
+            Numeric::Long(l) => l,
+            Numeric::Float(f) => f as i64,
+            Numeric::Double(d) => d as i64,
+        }
+    }
+
+    fn as_float(self) -> f32 {
+        match self {
+            Numeric::Int(i) => i as f32,
+            Numeric::Long(l) => l as f32,
+            Numeric::Float(f) => f,
+            Numeric::Double(d) => d as f32,
+        }
+    }
+
+    fn as_double(self) -> f64 {
+        match self {
+            Numeric::Int(i) => i as f64,
+            Numeric::Long(l) => l as f64,
+            Numeric::Float(f) => f as f64,
+            Numeric::Double(d) => d,
+        }
+    }
+}
+
+pub fn operator_symbol(operator: BinaryOperator) -> &'static str {
+    match operator {
+        BinaryOperator::Add => "+",
+        BinaryOperator::Subtract => "-",
+        BinaryOperator::Multiply => "*",
+        BinaryOperator::Divide => "/",
+        BinaryOperator::Remainder => "%",
+        BinaryOperator::Equal => "==",
+        BinaryOperator::NotEqual => "!=",
+        BinaryOperator::Less => "<",
+        BinaryOperator::LessEqual => "<=",
+        BinaryOperator::Greater => ">",
+        BinaryOperator::GreaterEqual => ">=",
+        BinaryOperator::And => "&&",
+        BinaryOperator::Or => "||",
+    }
+}
+
+/// Unary numeric promotion: `byte`, `short` and `char` widen to `int`.
+fn unary_promotion(value: JValue) -> Option<Numeric> {
+    Some(match value {
+        JValue::Byte(b) => Numeric::Int(b as i32),
+        JValue::Short(s) => Numeric::Int(s as i32),
+        JValue::Char(c) => Numeric::Int(c as i32),
+        JValue::Int(i) => Numeric::Int(i),
+        JValue::Long(l) => Numeric::Long(l),
+        JValue::Float(f) => Numeric::Float(f),
+        JValue::Double(d) => Numeric::Double(d),
+        _ => return None,
+    })
+}
+
+/// Binary numeric promotion: both operands widen to the wider of `double`,
+/// `float`, `long` and `int`, in that order.
+fn binary_promotion(left: JValue, right: JValue) -> Option<(Numeric, Numeric)> {
+    let left = unary_promotion(left)?;
+    let right = unary_promotion(right)?;
+    Some(match (left, right) {
+        (Numeric::Double(_), _) | (_, Numeric::Double(_)) => (
+            Numeric::Double(left.as_double()),
+            Numeric::Double(right.as_double()),
+        ),
+        (Numeric::Float(_), _) | (_, Numeric::Float(_)) => (
+            Numeric::Float(left.as_float()),
+            Numeric::Float(right.as_float()),
+        ),
+        (Numeric::Long(_), _) | (_, Numeric::Long(_)) => (
+            Numeric::Long(left.as_long()),
+            Numeric::Long(right.as_long()),
+        ),
+        _ => (left, right),
+    })
+}
+
+fn type_name(value: JValue) -> &'static str {
+    match value {
+        JValue::Object(_) => "object",
+        JValue::Byte(_) => "byte",
+        JValue::Short(_) => "short",
+        JValue::Int(_) => "int",
+        JValue::Long(_) => "long",
+        JValue::Float(_) => "float",
+        JValue::Double(_) => "double",
+        JValue::Char(_) => "char",
+        JValue::Bool(_) => "boolean",
+        JValue::Void => "void",
+    }
+}
+
+fn invalid_operands(operator: BinaryOperator, left: JValue, right: JValue) -> InterpreterError {
+    InterpreterError::InvalidOperands(format!(
+        "operator '{}' cannot be applied to {} and {}",
+        operator_symbol(operator),
+        type_name(left),
+        type_name(right)
+    ))
+}
+
+fn arithmetic(
+    operator: BinaryOperator,
+    left: Numeric,
+    right: Numeric,
+) -> Result<JValue<'static>, InterpreterError> {
+    Ok(match (left, right) {
+        (Numeric::Int(a), Numeric::Int(b)) => JValue::Int(match operator {
+            BinaryOperator::Add => a.wrapping_add(b),
+            BinaryOperator::Subtract => a.wrapping_sub(b),
+            BinaryOperator::Multiply => a.wrapping_mul(b),
+            BinaryOperator::Divide if b == 0 => return Err(InterpreterError::DivisionByZero),
+            BinaryOperator::Divide => a.wrapping_div(b),
+            BinaryOperator::Remainder if b == 0 => return Err(InterpreterError::DivisionByZero),
+            BinaryOperator::Remainder => a.wrapping_rem(b),
+            _ => unreachable!(),
+        }),
+        (Numeric::Long(a), Numeric::Long(b)) => JValue::Long(match operator {
+            BinaryOperator::Add => a.wrapping_add(b),
+            BinaryOperator::Subtract => a.wrapping_sub(b),
+            BinaryOperator::Multiply => a.wrapping_mul(b),
+            BinaryOperator::Divide if b == 0 => return Err(InterpreterError::DivisionByZero),
+            BinaryOperator::Divide => a.wrapping_div(b),
+            BinaryOperator::Remainder if b == 0 => return Err(InterpreterError::DivisionByZero),
+            BinaryOperator::Remainder => a.wrapping_rem(b),
+            _ => unreachable!(),
+        }),
+        (Numeric::Float(a), Numeric::Float(b)) => JValue::Float(match operator {
+            BinaryOperator::Add => a + b,
+            BinaryOperator::Subtract => a - b,
+            BinaryOperator::Multiply => a * b,
+            BinaryOperator::Divide => a / b,
+            BinaryOperator::Remainder => a % b,
+            _ => unreachable!(),
+        }),
+        (Numeric::Double(a), Numeric::Double(b)) => JValue::Double(match operator {
+            BinaryOperator::Add => a + b,
+            BinaryOperator::Subtract => a - b,
+            BinaryOperator::Multiply => a * b,
+            BinaryOperator::Divide => a / b,
+            BinaryOperator::Remainder => a % b,
+            _ => unreachable!(),
+        }),
+        _ => unreachable!("operands are promoted to the same type"),
+    })
+}
+
+fn compare(operator: BinaryOperator, left: Numeric, right: Numeric) -> bool {
+    // comparing as doubles would lose precision for large longs
+    let ordering = match (left, right) {
+        (Numeric::Int(a), Numeric::Int(b)) => a.partial_cmp(&b),
+        (Numeric::Long(a), Numeric::Long(b)) => a.partial_cmp(&b),
+        (Numeric::Float(a), Numeric::Float(b)) => a.partial_cmp(&b),
+        (Numeric::Double(a), Numeric::Double(b)) => a.partial_cmp(&b),
+        _ => unreachable!("operands are promoted to the same type"),
+    };
+    // NaN is unordered, which makes every comparison but != false
+    match ordering {
+        Some(ordering) => match operator {
+            BinaryOperator::Equal => ordering.is_eq(),
+            BinaryOperator::NotEqual => ordering.is_ne(),
+            BinaryOperator::Less => ordering.is_lt(),
+            BinaryOperator::LessEqual => ordering.is_le(),
+            BinaryOperator::Greater => ordering.is_gt(),
+            BinaryOperator::GreaterEqual => ordering.is_ge(),
+            _ => unreachable!(),
+        },
+        None => operator == BinaryOperator::NotEqual,
+    }
+}
+
+fn boolean(value: bool) -> JValue<'static> {
+    JValue::Bool(if value { 1 } else { 0 })
+}
+
+/// Applies a binary operator to two primitive values following Java's
+/// binary numeric promotion. String concatenation, reference equality and
+/// the short-circuiting `&&` and `||` are handled by the interpreter.
+pub fn apply_binary_operator(
+    operator: BinaryOperator,
+    left: JValue,
+    right: JValue,
+) -> Result<JValue<'static>, InterpreterError> {
+    match operator {
+        BinaryOperator::Add
+        | BinaryOperator::Subtract
+        | BinaryOperator::Multiply
+        | BinaryOperator::Divide
+        | BinaryOperator::Remainder => {
+            let (a, b) =
+                binary_promotion(left, right).ok_or(invalid_operands(operator, left, right))?;
+            arithmetic(operator, a, b)
+        }
+        BinaryOperator::Equal | BinaryOperator::NotEqual => {
+            if let (JValue::Bool(a), JValue::Bool(b)) = (left, right) {
+                return Ok(boolean((a == b) == (operator == BinaryOperator::Equal)));
+            }
+            let (a, b) =
+                binary_promotion(left, right).ok_or(invalid_operands(operator, left, right))?;
+            Ok(boolean(compare(operator, a, b)))
+        }
+        BinaryOperator::Less
+        | BinaryOperator::LessEqual
+        | BinaryOperator::Greater
+        | BinaryOperator::GreaterEqual => {
+            let (a, b) =
+                binary_promotion(left, right).ok_or(invalid_operands(operator, left, right))?;
+            Ok(boolean(compare(operator, a, b)))
+        }
+        BinaryOperator::And | BinaryOperator::Or => match (left, right) {
+            (JValue::Bool(a), JValue::Bool(b)) => Ok(boolean(if operator == BinaryOperator::And {
+                a == 1 && b == 1
+            } else {
+                a == 1 || b == 1
+            })),
+            _ => Err(invalid_operands(operator, left, right)),
+        },
+    }
+}
+
+pub fn apply_unary_operator(
+    operator: UnaryOperator,
+    operand: JValue,
+) -> Result<JValue<'static>, InterpreterError> {
+    match (operator, operand) {
+        (UnaryOperator::Not, JValue::Bool(b)) => Ok(boolean(b == 0)),
+        (UnaryOperator::Negate, operand) => match unary_promotion(operand) {
+            Some(Numeric::Int(i)) => Ok(JValue::Int(i.wrapping_neg())),
+            Some(Numeric::Long(l)) => Ok(JValue::Long(l.wrapping_neg())),
+            Some(Numeric::Float(f)) => Ok(JValue::Float(-f)),
+            Some(Numeric::Double(d)) => Ok(JValue::Double(-d)),
+            None => Err(InterpreterError::InvalidOperands(format!(
+                "operator '-' cannot be applied to {}",
+                type_name(operand)
+            ))),
+        },
+        (UnaryOperator::Not, operand) => Err(InterpreterError::InvalidOperands(format!(
+            "operator '!' cannot be applied to {}",
+            type_name(operand)
+        ))),
+    }
+}
@@ -1,5 +1,15 @@
-use super::ast::{ArrayExpression, Assignment, Literal, MemberExpression, MethodCall, Token};
-use pest::{iterators::Pair, Parser};
+use super::ast::{
+    Assignment, BinaryExpression, BinaryOperator, CatchClause, Directive, ForEachLoop,
+    FunctionDefinition, IfStatement, Import, Lambda, LambdaBody, Literal, Member, MemberExpression,
+    MemberKind, MethodCall, NewArray, NewExpression, Position, ReturnStatement, Slice, Span,
+    ThrowStatement, Token, TokenKind, TryStatement, UnaryExpression, UnaryOperator, WhileLoop,
+};
+use pest::{
+    error::{Error, InputLocation, LineColLocation},
+    iterators::{Pair, Pairs},
+    Parser,
+};
+use std::{iter::Peekable, num::IntErrorKind, rc::Rc};
 
 #[derive(pest_derive::Parser)]
 #[grammar = "slat/syntax.pest"]
@@ -7,134 +17,852 @@ pub struct SlatParser;
 
 #[derive(thiserror::Error, Debug)]
 pub enum ParserError {
-    #[error("{0}")]
-    InvalidSyntax(String),
+    #[error("{message}")]
+    InvalidSyntax { message: String, span: Span },
 }
 
-fn parse_import(pair: Pair<Rule>) -> Token {
-    let qualifiers = pair
-        .into_inner()
-        .map(|pair| pair.as_str().to_owned())
-        .collect();
-    Token::Import(qualifiers)
+impl ParserError {
+    pub fn span(&self) -> Span {
+        match self {
+            ParserError::InvalidSyntax { span, .. } => *span,
+        }
+    }
 }
 
-fn parse_string(pair: Pair<Rule>) -> Token {
-    let str = pair.as_str().as_bytes();
-    Token::Literal(Literal::String(
-        String::from_utf8(str[1..str.len() - 1].to_vec()).expect("invalid string"),
-    ))
+impl From<Error<Rule>> for ParserError {
+    fn from(error: Error<Rule>) -> Self {
+        let (start_offset, end_offset) = match error.location {
+            InputLocation::Pos(offset) => (offset, offset),
+            InputLocation::Span(span) => span,
+        };
+        let ((start_line, start_column), (end_line, end_column)) = match error.line_col {
+            LineColLocation::Pos(line_col) => (line_col, line_col),
+            LineColLocation::Span(start, end) => (start, end),
+        };
+        ParserError::InvalidSyntax {
+            message: error.variant.message().into_owned(),
+            span: Span {
+                start: Position {
+                    offset: start_offset,
+                    line: start_line,
+                    column: start_column,
+                },
+                end: Position {
+                    offset: end_offset,
+                    line: end_line,
+                    column: end_column,
+                },
+            },
+        }
+    }
+}
+
+type ParseResult<T> = Result<T, ParserError>;
+
+fn position(position: pest::Position) -> Position {
+    let (line, column) = position.line_col();
+    Position {
+        offset: position.pos(),
+        line,
+        column,
+    }
 }
 
-fn parse_boolean(pair: Pair<Rule>) -> Token {
-    Token::Literal(Literal::Boolean(pair.as_str() == "true"))
+fn span(pair: &Pair<Rule>) -> Span {
+    let span = pair.as_span();
+    Span {
+        start: position(span.start_pos()),
+        end: position(span.end_pos()),
+    }
 }
 
-fn parse_integer(pair: Pair<Rule>) -> Token {
+fn token(kind: TokenKind, span: Span) -> Token {
+    Token { kind, span }
+}
+
+fn parse_import(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
+    let mut import = Import {
+        path: Vec::new(),
+        wildcard: false,
+        alias: None,
+    };
+    for pair in pair.into_inner() {
+        match pair.as_rule() {
+            Rule::ident => import.path.push(pair.as_str().to_owned()),
+            Rule::wildcard => import.wildcard = true,
+            Rule::alias => {
+                let alias = pair.into_inner().next().expect("unreachable");
+                import.alias = Some(alias.as_str().to_owned());
+            }
+            _ => unreachable!(),
+        }
+    }
+    Ok(token(TokenKind::Import(import), span))
+}
+
+/// Resolves the escape sequences in the contents of a string or character literal
+/// to UTF-16 code units, since `\u` escapes may spell out surrogates.
+fn unescape(text: &str) -> Vec<u16> {
+    let mut units = Vec::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 2];
+            units.extend_from_slice(c.encode_utf16(&mut buffer));
+            continue;
+        }
+        let unit = match chars.next().expect("unreachable") {
+            'b' => 0x08,
+            't' => 0x09,
+            'n' => 0x0a,
+            'f' => 0x0c,
+            'r' => 0x0d,
+            'u' => {
+                while chars.peek() == Some(&'u') {
+                    chars.next();
+                }
+                let code: String = chars.by_ref().take(4).collect();
+                u16::from_str_radix(&code, 16).expect("unreachable")
+            }
+            // the grammar limits octal escapes to \377
+            digit @ '0'..='7' => {
+                let mut value = digit.to_digit(8).expect("unreachable");
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(8)) {
+                    if value * 8 + digit > 0o377 {
+                        break;
+                    }
+                    value = value * 8 + digit;
+                    chars.next();
+                }
+                value as u16
+            }
+            escaped => escaped as u16,
+        };
+        units.push(unit);
+    }
+    units
+}
+
+fn parse_string(pair: Pair<Rule>) -> ParseResult<Token> {
     let str = pair.as_str();
-    Token::Literal(Literal::Integer(
-        str.parse::<i64>().expect("invalid integer"),
+    let string = String::from_utf16(&unescape(&str[1..str.len() - 1])).map_err(|_| {
+        ParserError::InvalidSyntax {
+            message: "string literal contains an unpaired surrogate".to_owned(),
+            span: span(&pair),
+        }
+    })?;
+    Ok(token(
+        TokenKind::Literal(Literal::String(string)),
+        span(&pair),
     ))
 }
 
-fn parse_decimal(pair: Pair<Rule>) -> Token {
+fn parse_character(pair: Pair<Rule>) -> ParseResult<Token> {
     let str = pair.as_str();
-    Token::Literal(Literal::Decimal(
-        str.parse::<f64>().expect("invalid decimal"),
+    let character = match unescape(&str[1..str.len() - 1])[..] {
+        [unit] => unit,
+        _ => {
+            return Err(ParserError::InvalidSyntax {
+                message: format!("character literal {} does not fit in a char", str),
+                span: span(&pair),
+            })
+        }
+    };
+    Ok(token(
+        TokenKind::Literal(Literal::Char(character)),
+        span(&pair),
+    ))
+}
+
+fn parse_boolean(pair: Pair<Rule>) -> ParseResult<Token> {
+    Ok(token(
+        TokenKind::Literal(Literal::Boolean(pair.as_str() == "true")),
+        span(&pair),
     ))
 }
 
-fn parse_ident(pair: Pair<Rule>) -> Token {
-    Token::Identifier(pair.as_str().to_string())
+/// Parses an integer literal following Java's rules: decimal literals must fit in
+/// the signed range of their type (the minimum only when negated), while hex,
+/// octal and binary literals may use all of its bits.
+fn parse_integer(pair: &Pair<Rule>, negative: bool) -> ParseResult<Literal> {
+    let text = pair.as_str();
+    let digits = text.replace('_', "");
+    let (digits, long) = match digits.strip_suffix(['L', 'l']) {
+        Some(digits) => (digits, true),
+        None => (digits.as_str(), false),
+    };
+    let (radix, digits) = match digits.get(..2) {
+        Some("0x" | "0X") => (16, &digits[2..]),
+        Some("0b" | "0B") => (2, &digits[2..]),
+        _ if digits.len() > 1 && digits.starts_with('0') => (8, &digits[1..]),
+        _ => (10, digits),
+    };
+    let error = |message: &str| ParserError::InvalidSyntax {
+        message: format!("integer literal {} {}", text, message),
+        span: span(pair),
+    };
+    let magnitude = u64::from_str_radix(digits, radix).map_err(|err| match err.kind() {
+        IntErrorKind::PosOverflow => error("is too large"),
+        _ => error("is malformed"),
+    })?;
+
+    let (max_signed, max_bits) = if long {
+        (i64::MAX as u64, u64::MAX)
+    } else {
+        (i32::MAX as u64, u32::MAX as u64)
+    };
+    let in_range = if radix == 10 {
+        magnitude <= max_signed + negative as u64
+    } else {
+        magnitude <= max_bits
+    };
+    if !in_range {
+        return Err(error("is too large"));
+    }
+    Ok(if long {
+        let value = magnitude as i64;
+        Literal::Long(if negative {
+            value.wrapping_neg()
+        } else {
+            value
+        })
+    } else {
+        let value = magnitude as u32 as i32;
+        Literal::Int(if negative {
+            value.wrapping_neg()
+        } else {
+            value
+        })
+    })
+}
+
+/// Parses a floating point literal, a `float` when suffixed with `f` and a
+/// `double` otherwise. Like Java, literals that round to infinity or to zero
+/// without being zero are rejected.
+fn parse_decimal(pair: &Pair<Rule>, negative: bool) -> ParseResult<Literal> {
+    let text = pair.as_str();
+    let digits = text.replace('_', "");
+    let (digits, float) = match digits.strip_suffix(['f', 'F']) {
+        Some(digits) => (digits, true),
+        None => (digits.strip_suffix(['d', 'D']).unwrap_or(&digits), false),
+    };
+    let (value, is_infinite, is_zero) = if float {
+        let value = digits.parse::<f32>().expect("unreachable");
+        (value as f64, value.is_infinite(), value == 0.0)
+    } else {
+        let value = digits.parse::<f64>().expect("unreachable");
+        (value, value.is_infinite(), value == 0.0)
+    };
+    let mantissa = digits.split(['e', 'E']).next().unwrap_or_default();
+    let error = |message: &str| ParserError::InvalidSyntax {
+        message: format!("floating point literal {} {}", text, message),
+        span: span(pair),
+    };
+    if is_infinite {
+        return Err(error("is too large"));
+    }
+    if is_zero && mantissa.contains(|c: char| matches!(c, '1'..='9')) {
+        return Err(error("is too small"));
+    }
+    let value = if negative { -value } else { value };
+    Ok(if float {
+        Literal::Float(value as f32)
+    } else {
+        Literal::Double(value)
+    })
+}
+
+/// Parses a numeric literal, which may have a folded minus sign in front so that
+/// e.g. `-2147483648` stays in range.
+fn parse_number(pair: &Pair<Rule>, negative: bool) -> ParseResult<Literal> {
+    match pair.as_rule() {
+        Rule::integer => parse_integer(pair, negative),
+        Rule::decimal => parse_decimal(pair, negative),
+        _ => unreachable!(),
+    }
+}
+
+fn parse_ident(pair: Pair<Rule>) -> ParseResult<Token> {
+    Ok(token(
+        TokenKind::Identifier(pair.as_str().to_string()),
+        span(&pair),
+    ))
 }
 
-fn parse_method_call(pair: Pair<Rule>) -> Token {
+fn method_call(pair: Pair<Rule>) -> ParseResult<MethodCall> {
     let mut tokens = pair.into_inner();
     let method_name = tokens.next().expect("unreachable").as_str();
 
     let mut args = Vec::new();
     for param in tokens {
-        args.push(parse_expr(param));
+        args.push(parse_expr(param)?);
     }
 
-    Token::MethodCall(MethodCall {
+    Ok(MethodCall {
         name: method_name.to_string(),
         args,
     })
 }
 
-fn parse_inner_member_expr(pair: Pair<Rule>) -> Token {
-    match pair.as_rule() {
-        Rule::method_call => parse_method_call(pair),
-        Rule::ident => parse_ident(pair),
-        // Rule::member_expr => parse_member_expr(pair),
+fn parse_method_call(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
+    Ok(token(TokenKind::MethodCall(method_call(pair)?), span))
+}
+
+fn parse_new(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
+    // a qualified class name is given as the identifiers before the constructor call
+    let mut qualifiers = Vec::new();
+    let mut inner = pair.into_inner();
+    let constructor_call = loop {
+        let pair = inner.next().expect("unreachable");
+        match pair.as_rule() {
+            Rule::ident => qualifiers.push(pair.as_str()),
+            _ => break pair,
+        }
+    };
+    let mut tokens = constructor_call.into_inner();
+    qualifiers.push(tokens.next().expect("unreachable").as_str());
+
+    Ok(token(
+        TokenKind::New(NewExpression {
+            class_name: qualifiers.join("."),
+            args: tokens.map(parse_expr).collect::<ParseResult<_>>()?,
+        }),
+        span,
+    ))
+}
+
+fn parse_new_array(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
+    let mut inner = pair.into_inner();
+    let class_name = parse_type_name(inner.next().expect("unreachable"));
+    let mut dimensions = Vec::new();
+    let mut unsized_dimensions = 0;
+    for pair in inner {
+        match pair.as_rule() {
+            Rule::unsized_dimension => unsized_dimensions += 1,
+            _ => dimensions.push(parse_expr(pair)?),
+        }
+    }
+
+    Ok(token(
+        TokenKind::NewArray(NewArray {
+            class_name,
+            dimensions,
+            unsized_dimensions,
+        }),
+        span,
+    ))
+}
+
+fn parse_array_literal(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
+    let elements = pair
+        .into_inner()
+        .map(parse_expr)
+        .collect::<ParseResult<_>>()?;
+    Ok(token(TokenKind::ArrayLiteral(elements), span))
+}
+
+fn parse_member(pair: Pair<Rule>) -> ParseResult<Member> {
+    let span = span(&pair);
+    let inner = pair.into_inner().next().expect("unreachable");
+    let kind = match inner.as_rule() {
+        Rule::ident => MemberKind::Field(inner.as_str().to_owned()),
+        Rule::method_call => MemberKind::MethodCall(method_call(inner)?),
+        Rule::expr => MemberKind::Index(Box::new(parse_expr(inner)?)),
+        Rule::slice => MemberKind::Slice(parse_slice(inner)?),
         _ => unreachable!(),
+    };
+    Ok(Member { kind, span })
+}
+
+fn parse_slice(pair: Pair<Rule>) -> ParseResult<Slice> {
+    let mut slice = Slice {
+        start: None,
+        end: None,
+    };
+    for bound in pair.into_inner() {
+        let rule = bound.as_rule();
+        let expr = Some(Box::new(parse_expr(
+            bound.into_inner().next().expect("unreachable"),
+        )?));
+        match rule {
+            Rule::slice_start => slice.start = expr,
+            _ => slice.end = expr,
+        }
     }
+    Ok(slice)
 }
 
-fn parse_array_expr(pair: Pair<Rule>) -> Token {
+fn parse_member_expr(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
     let mut tokens = pair.into_inner();
 
-    let array = tokens.next().expect("unreachable");
-    let array = match array.as_rule() {
-        Rule::member_expr => parse_member_expr(array),
-        Rule::method_call => parse_method_call(array),
-        Rule::ident => parse_ident(array),
+    Ok(token(
+        TokenKind::MemberExpression(MemberExpression {
+            owner: Box::new(parse_primary(tokens.next().expect("unreachable"))?),
+            members: tokens.map(parse_member).collect::<ParseResult<_>>()?,
+        }),
+        span,
+    ))
+}
+
+fn parse_primary(pair: Pair<Rule>) -> ParseResult<Token> {
+    match pair.as_rule() {
+        Rule::integer | Rule::decimal => Ok(token(
+            TokenKind::Literal(parse_number(&pair, false)?),
+            span(&pair),
+        )),
+        Rule::boolean => parse_boolean(pair),
+        Rule::null => Ok(token(TokenKind::Literal(Literal::Null), span(&pair))),
+        Rule::string => parse_string(pair),
+        Rule::character => parse_character(pair),
+        Rule::ident => parse_ident(pair),
+        Rule::method_call => parse_method_call(pair),
+        Rule::member_expr => parse_member_expr(pair),
+        Rule::new => parse_new(pair),
+        Rule::new_array => parse_new_array(pair),
+        Rule::array_literal => parse_array_literal(pair),
+        Rule::object_handle => parse_object_handle(pair),
+        Rule::lambda => parse_lambda(pair, None),
+        Rule::cast => parse_cast(pair),
+        Rule::expr => parse_expr(pair),
         _ => unreachable!(),
+    }
+}
+
+fn parse_operand(pairs: &mut Peekable<Pairs<Rule>>) -> ParseResult<Token> {
+    let mut operators = Vec::new();
+    let mut operand = loop {
+        let pair = pairs.next().expect("unreachable");
+        match pair.as_rule() {
+            Rule::negate => operators.push((UnaryOperator::Negate, span(&pair))),
+            Rule::not => operators.push((UnaryOperator::Not, span(&pair))),
+            Rule::integer | Rule::decimal
+                if matches!(operators.last(), Some((UnaryOperator::Negate, _))) =>
+            {
+                let (_, operator_span) = operators.pop().expect("unreachable");
+                let literal = parse_number(&pair, true)?;
+                break token(TokenKind::Literal(literal), operator_span.to(span(&pair)));
+            }
+            _ => break parse_primary(pair)?,
+        }
     };
-    Token::ArrayExpression(ArrayExpression {
-        array: Box::new(array),
-        index: Box::new(parse_expr(tokens.next().expect("unreachable"))),
+
+    // unary operators bind right-to-left, so the innermost one is applied first
+    for (operator, operator_span) in operators.into_iter().rev() {
+        let span = operator_span.to(operand.span);
+        let kind = TokenKind::UnaryExpression(UnaryExpression {
+            operator,
+            operand: Box::new(operand),
+        });
+        operand = token(kind, span);
+    }
+    Ok(operand)
+}
+
+fn binary_operator(rule: Rule) -> Option<BinaryOperator> {
+    Some(match rule {
+        Rule::add => BinaryOperator::Add,
+        Rule::subtract => BinaryOperator::Subtract,
+        Rule::multiply => BinaryOperator::Multiply,
+        Rule::divide => BinaryOperator::Divide,
+        Rule::remainder => BinaryOperator::Remainder,
+        Rule::equal => BinaryOperator::Equal,
+        Rule::not_equal => BinaryOperator::NotEqual,
+        Rule::less => BinaryOperator::Less,
+        Rule::less_equal => BinaryOperator::LessEqual,
+        Rule::greater => BinaryOperator::Greater,
+        Rule::greater_equal => BinaryOperator::GreaterEqual,
+        Rule::and => BinaryOperator::And,
+        Rule::or => BinaryOperator::Or,
+        _ => return None,
     })
 }
 
-fn parse_member_expr(pair: Pair<Rule>) -> Token {
+/// Java operator precedence; a higher value binds tighter.
+pub fn precedence(operator: BinaryOperator) -> u8 {
+    match operator {
+        BinaryOperator::Or => 1,
+        BinaryOperator::And => 2,
+        BinaryOperator::Equal | BinaryOperator::NotEqual => 3,
+        BinaryOperator::Less
+        | BinaryOperator::LessEqual
+        | BinaryOperator::Greater
+        | BinaryOperator::GreaterEqual => 4,
+        BinaryOperator::Add | BinaryOperator::Subtract => 5,
+        BinaryOperator::Multiply | BinaryOperator::Divide | BinaryOperator::Remainder => 6,
+    }
+}
+
+fn parse_binary_expr(pairs: &mut Peekable<Pairs<Rule>>, min_precedence: u8) -> ParseResult<Token> {
+    let mut left = parse_operand(pairs)?;
+    while let Some(operator) = pairs
+        .peek()
+        .and_then(|pair| binary_operator(pair.as_rule()))
+    {
+        let operator_precedence = precedence(operator);
+        if operator_precedence < min_precedence {
+            break;
+        }
+        pairs.next();
+
+        // all binary operators are left-associative
+        let right = parse_binary_expr(pairs, operator_precedence + 1)?;
+        let span = left.span.to(right.span);
+        left = token(
+            TokenKind::BinaryExpression(BinaryExpression {
+                operator,
+                left: Box::new(left),
+                right: Box::new(right),
+            }),
+            span,
+        );
+    }
+    Ok(left)
+}
+
+fn parse_expr(pair: Pair<Rule>) -> ParseResult<Token> {
+    parse_binary_expr(&mut pair.into_inner().peekable(), 0)
+}
+
+fn parse_assignment(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
     let mut tokens = pair.into_inner();
 
-    Token::MemberExpression(MemberExpression {
-        owner: Box::new(parse_inner_member_expr(tokens.next().expect("unreachable"))),
-        members: tokens.map(parse_inner_member_expr).collect(),
-    })
+    Ok(token(
+        TokenKind::Assignment(Assignment {
+            target: Box::new(parse_primary(tokens.next().expect("unreachable"))?),
+            expr: Box::new(parse_expr(tokens.next().expect("unreachable"))?),
+        }),
+        span,
+    ))
 }
 
-fn parse_expr(pair: Pair<Rule>) -> Token {
-    let inner_expr = pair.into_inner().next().expect("unreachable");
-    match inner_expr.as_rule() {
-        Rule::integer => parse_integer(inner_expr),
-        Rule::boolean => parse_boolean(inner_expr),
-        Rule::decimal => parse_decimal(inner_expr),
-        Rule::string => parse_string(inner_expr),
-        Rule::ident => parse_ident(inner_expr),
-        Rule::array_expr => parse_array_expr(inner_expr),
-        Rule::method_call => parse_method_call(inner_expr),
-        Rule::member_expr => parse_member_expr(inner_expr),
-        _ => unreachable!(),
+fn parse_directive(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
+    let mut tokens = pair.into_inner();
+    let name = tokens
+        .next()
+        .expect("unreachable")
+        .into_inner()
+        .next()
+        .expect("unreachable");
+
+    Ok(token(
+        TokenKind::Directive(Directive {
+            name: name.as_str().to_owned(),
+            value: Box::new(parse_expr(tokens.next().expect("unreachable"))?),
+        }),
+        span,
+    ))
+}
+
+/// The comments of `pair`, a program or block, that aren't inside one of its nested
+/// blocks, which have their own.
+fn parse_comments(pair: &Pair<Rule>) -> Vec<Token> {
+    let span = pair.as_span();
+    let nested_blocks: Vec<_> = pair
+        .clone()
+        .into_inner()
+        .flatten()
+        .filter(|pair| pair.as_rule() == Rule::block)
+        .map(|pair| pair.as_span().start()..pair.as_span().end())
+        .collect();
+    let at = |offset| position(pest::Position::new(span.get_input(), offset).expect("unreachable"));
+    SlatParser::parse(Rule::comments, span.as_str())
+        .expect("any source splits into comments, literals and characters")
+        .next()
+        .expect("unreachable")
+        .into_inner()
+        .filter(|pair| pair.as_rule() == Rule::comment)
+        .filter_map(|comment| {
+            let text = comment.as_str().trim_end_matches('\r');
+            let start = span.start() + comment.as_span().start();
+            if nested_blocks.iter().any(|block| block.contains(&start)) {
+                return None;
+            }
+            Some(token(
+                TokenKind::Comment(text.to_owned()),
+                Span {
+                    start: at(start),
+                    end: at(start + text.len()),
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Places each comment before the first statement that ends after it starts, so
+/// comments inside a statement come just before it.
+fn with_comments(input: &str, mut statements: Vec<Token>, comments: Vec<Token>) -> Vec<Token> {
+    let mut result = Vec::with_capacity(statements.len() + comments.len());
+    for statement in &mut statements {
+        // pest ends a rule after the whitespace and comments skipped while trying to
+        // match more of it
+        let start = statement.span.start.offset;
+        let mut end = statement.span.end.offset;
+        loop {
+            end = start + input[start..end].trim_end().len();
+            match comments.iter().find(|comment| {
+                comment.span.end.offset == end && comment.span.start.offset >= start
+            }) {
+                Some(comment) => end = comment.span.start.offset,
+                None => break,
+            }
+        }
+        statement.span.end = position(pest::Position::new(input, end).expect("unreachable"));
     }
+    let mut comments = comments.into_iter().peekable();
+    for statement in statements {
+        while let Some(comment) =
+            comments.next_if(|comment| comment.span.start.offset < statement.span.end.offset)
+        {
+            result.push(comment);
+        }
+        result.push(statement);
+    }
+    result.extend(comments);
+    result
+}
+
+fn parse_block(pair: Pair<Rule>) -> ParseResult<Vec<Token>> {
+    let comments = parse_comments(&pair);
+    let input = pair.as_span().get_input();
+    let statements = pair
+        .into_inner()
+        .map(parse_statement)
+        .collect::<ParseResult<_>>()?;
+    Ok(with_comments(input, statements, comments))
+}
+
+fn parse_if_statement(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
+    let mut tokens = pair.into_inner();
+    let condition = parse_expr(tokens.next().expect("unreachable"))?;
+    let body = parse_block(tokens.next().expect("unreachable"))?;
+    let else_body = match tokens.next() {
+        // `else if` is an else block holding a single if statement
+        Some(else_pair) if else_pair.as_rule() == Rule::if_statement => {
+            Some(vec![parse_if_statement(else_pair)?])
+        }
+        Some(else_pair) => Some(parse_block(else_pair)?),
+        None => None,
+    };
+
+    Ok(token(
+        TokenKind::If(IfStatement {
+            condition: Box::new(condition),
+            body,
+            else_body,
+        }),
+        span,
+    ))
+}
+
+fn parse_while_loop(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
+    let mut tokens = pair.into_inner();
+
+    Ok(token(
+        TokenKind::While(WhileLoop {
+            condition: Box::new(parse_expr(tokens.next().expect("unreachable"))?),
+            body: parse_block(tokens.next().expect("unreachable"))?,
+        }),
+        span,
+    ))
+}
+
+fn parse_for_loop(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
+    let mut tokens = pair.into_inner();
+
+    Ok(token(
+        TokenKind::ForEach(ForEachLoop {
+            variable: tokens.next().expect("unreachable").as_str().to_owned(),
+            iterable: Box::new(parse_expr(tokens.next().expect("unreachable"))?),
+            body: parse_block(tokens.next().expect("unreachable"))?,
+        }),
+        span,
+    ))
+}
+
+fn parse_type_name(pair: Pair<Rule>) -> String {
+    let qualifiers = pair
+        .into_inner()
+        .map(|pair| pair.as_str())
+        .collect::<Vec<_>>();
+    qualifiers.join(".")
 }
 
-fn parse_assignment(pair: Pair<Rule>) -> Token {
+fn parse_lambda(pair: Pair<Rule>, interface: Option<String>) -> ParseResult<Token> {
+    let span = span(&pair);
     let mut tokens = pair.into_inner();
+    let parameters = tokens.next().expect("unreachable");
+    let parameters = match parameters.as_rule() {
+        Rule::parameters => parameters
+            .into_inner()
+            .map(|parameter| parameter.as_str().to_owned())
+            .collect(),
+        _ => vec![parameters.as_str().to_owned()],
+    };
+    let body = tokens.next().expect("unreachable");
+    let body = match body.as_rule() {
+        Rule::block => LambdaBody::Block(parse_block(body)?),
+        Rule::assignment => LambdaBody::Expression(Box::new(parse_assignment(body)?)),
+        _ => LambdaBody::Expression(Box::new(parse_expr(body)?)),
+    };
+
+    Ok(token(
+        TokenKind::Lambda(Rc::new(Lambda {
+            parameters,
+            body,
+            interface,
+        })),
+        span,
+    ))
+}
+
+fn parse_cast(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
+    let mut tokens = pair.into_inner();
+    let interface = parse_type_name(tokens.next().expect("unreachable"));
+    let lambda = parse_lambda(tokens.next().expect("unreachable"), Some(interface))?;
+    Ok(token(lambda.kind, span))
+}
+
+fn parse_function(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
+    let mut tokens = pair.into_inner();
+    let name = tokens.next().expect("unreachable").as_str().to_owned();
+    let parameters = tokens
+        .next()
+        .expect("unreachable")
+        .into_inner()
+        .map(|parameter| parameter.as_str().to_owned())
+        .collect();
+
+    Ok(token(
+        TokenKind::Function(Rc::new(FunctionDefinition {
+            name,
+            parameters,
+            body: parse_block(tokens.next().expect("unreachable"))?,
+        })),
+        span,
+    ))
+}
+
+fn parse_return_statement(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
+    let mut tokens = pair.into_inner();
+    tokens.next().expect("unreachable");
+
+    let value = match tokens.next() {
+        Some(value) => Some(Box::new(parse_expr(value)?)),
+        None => None,
+    };
+    Ok(token(TokenKind::Return(ReturnStatement { value }), span))
+}
+
+fn parse_catch_clause(pair: Pair<Rule>) -> ParseResult<CatchClause> {
+    let mut tokens = pair.into_inner();
+    let mut class_name = None;
+    let mut variable = tokens.next().expect("unreachable");
+    if variable.as_rule() == Rule::type_name {
+        class_name = Some(parse_type_name(variable));
+        variable = tokens.next().expect("unreachable");
+    }
 
-    Token::Assignment(Assignment {
-        variable: tokens.next().expect("unreachable").as_str().to_owned(),
-        expr: Box::new(parse_expr(tokens.next().expect("unreachable"))),
+    Ok(CatchClause {
+        class_name,
+        variable: variable.as_str().to_owned(),
+        body: parse_block(tokens.next().expect("unreachable"))?,
     })
 }
 
+fn parse_try_statement(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
+    let mut tokens = pair.into_inner();
+
+    Ok(token(
+        TokenKind::Try(TryStatement {
+            body: parse_block(tokens.next().expect("unreachable"))?,
+            catch_clauses: tokens.map(parse_catch_clause).collect::<ParseResult<_>>()?,
+        }),
+        span,
+    ))
+}
+
+fn parse_throw_statement(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
+    let mut tokens = pair.into_inner();
+    tokens.next().expect("unreachable");
+
+    Ok(token(
+        TokenKind::Throw(ThrowStatement {
+            value: Box::new(parse_expr(tokens.next().expect("unreachable"))?),
+        }),
+        span,
+    ))
+}
+
+fn parse_del_statement(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
+    let mut tokens = pair.into_inner();
+    tokens.next().expect("unreachable");
+
+    Ok(token(
+        TokenKind::Delete(tokens.map(|ident| ident.as_str().to_owned()).collect()),
+        span,
+    ))
+}
+
+fn parse_object_handle(pair: Pair<Rule>) -> ParseResult<Token> {
+    let span = span(&pair);
+    let digits = pair.into_inner().next().expect("unreachable");
+    match digits.as_str().replace('_', "").parse() {
+        Ok(id) => Ok(token(TokenKind::ObjectHandle(id), span)),
+        Err(_) => Err(ParserError::InvalidSyntax {
+            message: format!("object handle @{} is too large", digits.as_str()),
+            span,
+        }),
+    }
+}
+
+fn parse_statement(pair: Pair<Rule>) -> ParseResult<Token> {
+    match pair.as_rule() {
+        Rule::import => parse_import(pair),
+        Rule::expr => parse_expr(pair),
+        Rule::assignment => parse_assignment(pair),
+        Rule::directive => parse_directive(pair),
+        Rule::if_statement => parse_if_statement(pair),
+        Rule::while_loop => parse_while_loop(pair),
+        Rule::for_loop => parse_for_loop(pair),
+        Rule::function => parse_function(pair),
+        Rule::return_statement => parse_return_statement(pair),
+        Rule::try_statement => parse_try_statement(pair),
+        Rule::throw_statement => parse_throw_statement(pair),
+        Rule::vars_statement => Ok(token(TokenKind::Vars, span(&pair))),
+        Rule::del_statement => parse_del_statement(pair),
+        _ => unreachable!(),
+    }
+}
+
 pub fn parse(slat_code: &str) -> anyhow::Result<Vec<Token>> {
     let mut result = Vec::new();
-    let inner_program = match SlatParser::parse(Rule::program, slat_code) {
-        Ok(mut pairs) => pairs.next().expect("unreachable").into_inner(),
-        Err(e) => return Err(ParserError::InvalidSyntax(format!("{}", e)).into()),
+    let program = match SlatParser::parse(Rule::program, slat_code) {
+        Ok(mut pairs) => pairs.next().expect("unreachable"),
+        Err(e) => return Err(ParserError::from(e).into()),
     };
-    for pair in inner_program {
+    let comments = parse_comments(&program);
+    for pair in program.into_inner() {
         match pair.as_rule() {
-            Rule::import => result.push(parse_import(pair)),
-            Rule::expr => result.push(parse_expr(pair)),
-            Rule::assignment => result.push(parse_assignment(pair)),
             Rule::EOI => (),
-            _ => unreachable!(),
+            _ => result.push(parse_statement(pair)?),
         }
     }
-    Ok(result)
+    Ok(with_comments(slat_code, result, comments))
 }
@@ -0,0 +1,432 @@
+use super::{
+    ast::{
+        CatchClause, Lambda, LambdaBody, Literal, Member, MemberKind, Token, TokenKind,
+        UnaryOperator,
+    },
+    operators::operator_symbol,
+    parser::precedence,
+};
+
+const INDENT: &str = "    ";
+
+/// Where an expression is printed, which decides whether it needs parentheses.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Position {
+    /// A whole expression, such as an argument or the value of an assignment.
+    Free,
+    /// An operand of a binary operator of the given precedence, or of a unary
+    /// operator when it is `u8::MAX`.
+    Operand(u8),
+    /// The right operand of a binary operator, which binds to the left.
+    RightOperand(u8),
+    /// The owner of a postfix chain, `owner.member`.
+    Owner,
+}
+
+struct Printer {
+    output: String,
+    indent: usize,
+}
+
+fn escape_char(c: char, quote: char, output: &mut String) {
+    match c {
+        '\\' => output.push_str("\\\\"),
+        '\n' => output.push_str("\\n"),
+        '\r' => output.push_str("\\r"),
+        '\t' => output.push_str("\\t"),
+        '\u{8}' => output.push_str("\\b"),
+        '\u{c}' => output.push_str("\\f"),
+        c if c == quote => {
+            output.push('\\');
+            output.push(c);
+        }
+        c if c.is_control() => {
+            let mut buffer = [0; 2];
+            for unit in c.encode_utf16(&mut buffer) {
+                output.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+        c => output.push(c),
+    }
+}
+
+fn print_literal(literal: &Literal, output: &mut String) {
+    match literal {
+        Literal::Int(i) => output.push_str(&i.to_string()),
+        Literal::Long(l) => output.push_str(&format!("{}L", l)),
+        // debug formatting is the shortest that reads back the same and keeps a
+        // decimal point or exponent
+        Literal::Float(f) => output.push_str(&format!("{:?}f", f)),
+        Literal::Double(d) => output.push_str(&format!("{:?}", d)),
+        Literal::Char(c) => {
+            output.push('\'');
+            match char::from_u32(*c as u32) {
+                Some(c) => escape_char(c, '\'', output),
+                // a lone surrogate
+                None => output.push_str(&format!("\\u{:04x}", c)),
+            }
+            output.push('\'');
+        }
+        Literal::String(s) => {
+            output.push('"');
+            for c in s.chars() {
+                escape_char(c, '"', output);
+            }
+            output.push('"');
+        }
+        Literal::Boolean(b) => output.push_str(&b.to_string()),
+        Literal::Null => output.push_str("null"),
+    }
+}
+
+fn is_numeric(literal: &Literal) -> bool {
+    matches!(
+        literal,
+        Literal::Int(_) | Literal::Long(_) | Literal::Float(_) | Literal::Double(_)
+    )
+}
+
+/// Whether `kind` has to be parenthesized to be read back as the same node at
+/// `position`.
+fn needs_parentheses(kind: &TokenKind, position: Position) -> bool {
+    match (kind, position) {
+        (_, Position::Free) => false,
+        // lambda bodies extend as far to the right as they can
+        (TokenKind::Lambda(_), _) => true,
+        (TokenKind::BinaryExpression(binary), Position::Operand(parent)) => {
+            precedence(binary.operator) < parent
+        }
+        (TokenKind::BinaryExpression(binary), Position::RightOperand(parent)) => {
+            precedence(binary.operator) <= parent
+        }
+        // strings are the only literals the grammar lets own a postfix chain
+        (TokenKind::Literal(Literal::String(_)), Position::Owner) => false,
+        (
+            TokenKind::ArrayLiteral(_)
+            | TokenKind::NewArray(_)
+            | TokenKind::New(_)
+            | TokenKind::MethodCall(_)
+            | TokenKind::ObjectHandle(_)
+            | TokenKind::Identifier(_),
+            Position::Owner,
+        ) => false,
+        (_, Position::Owner) => true,
+        _ => false,
+    }
+}
+
+/// Whether `token` stays on the line `previous` ended on: comments that followed a
+/// statement on its line do, unless a line comment already takes the rest of it.
+fn continues_line(previous: Option<&Token>, token: &Token) -> bool {
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return false,
+    };
+    matches!(token.kind, TokenKind::Comment(_))
+        && previous.span.end.line == token.span.start.line
+        && !matches!(&previous.kind, TokenKind::Comment(text) if text.starts_with("//"))
+}
+
+impl Printer {
+    fn new_line(&mut self) {
+        self.output.push('\n');
+        for _ in 0..self.indent {
+            self.output.push_str(INDENT);
+        }
+    }
+
+    fn print_list(&mut self, tokens: &[Token]) {
+        for (index, token) in tokens.iter().enumerate() {
+            if index > 0 {
+                self.output.push_str(", ");
+            }
+            self.print_expr(token, Position::Free);
+        }
+    }
+
+    fn print_block(&mut self, body: &[Token]) {
+        self.output.push('{');
+        if body.is_empty() {
+            self.output.push('}');
+            return;
+        }
+        self.indent += 1;
+        self.print_statements(body);
+        self.indent -= 1;
+        self.new_line();
+        self.output.push('}');
+    }
+
+    fn print_lambda(&mut self, lambda: &Lambda) {
+        if let Some(interface) = &lambda.interface {
+            self.output.push_str(&format!("({}) ", interface));
+        }
+        match lambda.parameters.as_slice() {
+            [parameter] => self.output.push_str(parameter),
+            parameters => self
+                .output
+                .push_str(&format!("({})", parameters.join(", "))),
+        }
+        self.output.push_str(" -> ");
+        match &lambda.body {
+            LambdaBody::Expression(body) => self.print_expr(body, Position::Free),
+            LambdaBody::Block(body) => self.print_block(body),
+        }
+    }
+
+    fn print_member(&mut self, member: &Member) {
+        match &member.kind {
+            MemberKind::Field(name) => {
+                self.output.push('.');
+                self.output.push_str(name);
+            }
+            MemberKind::MethodCall(method_call) => {
+                self.output.push('.');
+                self.output.push_str(&method_call.name);
+                self.output.push('(');
+                self.print_list(&method_call.args);
+                self.output.push(')');
+            }
+            MemberKind::Index(index) => {
+                self.output.push('[');
+                self.print_expr(index, Position::Free);
+                self.output.push(']');
+            }
+            MemberKind::Slice(slice) => {
+                self.output.push('[');
+                if let Some(start) = &slice.start {
+                    self.print_expr(start, Position::Free);
+                }
+                self.output.push(':');
+                if let Some(end) = &slice.end {
+                    self.print_expr(end, Position::Free);
+                }
+                self.output.push(']');
+            }
+        }
+    }
+
+    fn print_expr(&mut self, token: &Token, position: Position) {
+        if needs_parentheses(&token.kind, position) {
+            self.output.push('(');
+            self.print_expr(token, Position::Free);
+            self.output.push(')');
+            return;
+        }
+        match &token.kind {
+            TokenKind::Identifier(name) => self.output.push_str(name),
+            TokenKind::Literal(literal) => print_literal(literal, &mut self.output),
+            TokenKind::MemberExpression(member_expression) => {
+                self.print_expr(&member_expression.owner, Position::Owner);
+                for member in &member_expression.members {
+                    self.print_member(member);
+                }
+            }
+            TokenKind::MethodCall(method_call) => {
+                self.output.push_str(&method_call.name);
+                self.output.push('(');
+                self.print_list(&method_call.args);
+                self.output.push(')');
+            }
+            TokenKind::New(new) => {
+                self.output.push_str("new ");
+                self.output.push_str(&new.class_name);
+                self.output.push('(');
+                self.print_list(&new.args);
+                self.output.push(')');
+            }
+            TokenKind::NewArray(new_array) => {
+                self.output.push_str("new ");
+                self.output.push_str(&new_array.class_name);
+                for dimension in &new_array.dimensions {
+                    self.output.push('[');
+                    self.print_expr(dimension, Position::Free);
+                    self.output.push(']');
+                }
+                self.output
+                    .push_str(&"[]".repeat(new_array.unsized_dimensions));
+            }
+            TokenKind::ArrayLiteral(elements) => {
+                self.output.push('[');
+                self.print_list(elements);
+                self.output.push(']');
+            }
+            TokenKind::BinaryExpression(binary) => {
+                let precedence = precedence(binary.operator);
+                self.print_expr(&binary.left, Position::Operand(precedence));
+                self.output.push(' ');
+                self.output.push_str(operator_symbol(binary.operator));
+                self.output.push(' ');
+                self.print_expr(&binary.right, Position::RightOperand(precedence));
+            }
+            TokenKind::UnaryExpression(unary) => {
+                self.output.push(match unary.operator {
+                    UnaryOperator::Negate => '-',
+                    UnaryOperator::Not => '!',
+                });
+                match &unary.operand.kind {
+                    // `-1` is a literal rather than a negation, and `--1` one inside a negation
+                    TokenKind::Literal(literal)
+                        if unary.operator == UnaryOperator::Negate && is_numeric(literal) =>
+                    {
+                        self.output.push('(');
+                        print_literal(literal, &mut self.output);
+                        self.output.push(')');
+                    }
+                    _ => self.print_expr(&unary.operand, Position::Operand(u8::MAX)),
+                }
+            }
+            TokenKind::Lambda(lambda) => self.print_lambda(lambda),
+            TokenKind::ObjectHandle(id) => self.output.push_str(&format!("@{}", id)),
+            TokenKind::Assignment(assignment) => {
+                self.print_expr(&assignment.target, Position::Free);
+                self.output.push_str(" = ");
+                self.print_expr(&assignment.expr, Position::Free);
+            }
+            _ => self.print_statement(token),
+        }
+    }
+
+    fn print_catch_clause(&mut self, catch_clause: &CatchClause) {
+        self.output.push_str(" catch (");
+        if let Some(class_name) = &catch_clause.class_name {
+            self.output.push_str(class_name);
+            self.output.push(' ');
+        }
+        self.output.push_str(&catch_clause.variable);
+        self.output.push_str(") ");
+        self.print_block(&catch_clause.body);
+    }
+
+    fn print_statements(&mut self, statements: &[Token]) {
+        let mut previous = None;
+        for statement in statements {
+            if continues_line(previous, statement) {
+                self.output.push(' ');
+            } else {
+                self.new_line();
+            }
+            self.print_statement(statement);
+            previous = Some(statement);
+        }
+    }
+
+    fn print_statement(&mut self, token: &Token) {
+        match &token.kind {
+            TokenKind::Import(import) => {
+                self.output.push_str("import ");
+                self.output.push_str(&import.path.join("."));
+                if import.wildcard {
+                    self.output.push_str(".*");
+                }
+                if let Some(alias) = &import.alias {
+                    self.output.push_str(" as ");
+                    self.output.push_str(alias);
+                }
+            }
+            TokenKind::Directive(directive) => {
+                self.output.push('#');
+                self.output.push_str(&directive.name);
+                self.output.push(' ');
+                self.print_expr(&directive.value, Position::Free);
+            }
+            TokenKind::If(if_statement) => {
+                self.output.push_str("if ");
+                self.print_expr(&if_statement.condition, Position::Free);
+                self.output.push(' ');
+                self.print_block(&if_statement.body);
+                match if_statement.else_body.as_deref() {
+                    Some(
+                        [else_if @ Token {
+                            kind: TokenKind::If(_),
+                            ..
+                        }],
+                    ) => {
+                        self.output.push_str(" else ");
+                        self.print_statement(else_if);
+                    }
+                    Some(else_body) => {
+                        self.output.push_str(" else ");
+                        self.print_block(else_body);
+                    }
+                    None => {}
+                }
+            }
+            TokenKind::While(while_loop) => {
+                self.output.push_str("while ");
+                self.print_expr(&while_loop.condition, Position::Free);
+                self.output.push(' ');
+                self.print_block(&while_loop.body);
+            }
+            TokenKind::ForEach(for_loop) => {
+                self.output.push_str("for ");
+                self.output.push_str(&for_loop.variable);
+                self.output.push_str(" in ");
+                self.print_expr(&for_loop.iterable, Position::Free);
+                self.output.push(' ');
+                self.print_block(&for_loop.body);
+            }
+            TokenKind::Function(function) => {
+                self.output.push_str(&format!(
+                    "fn {}({}) ",
+                    function.name,
+                    function.parameters.join(", ")
+                ));
+                self.print_block(&function.body);
+            }
+            TokenKind::Return(return_statement) => {
+                self.output.push_str("return");
+                if let Some(value) = &return_statement.value {
+                    self.output.push(' ');
+                    self.print_expr(value, Position::Free);
+                }
+            }
+            TokenKind::Try(try_statement) => {
+                self.output.push_str("try ");
+                self.print_block(&try_statement.body);
+                for catch_clause in &try_statement.catch_clauses {
+                    self.print_catch_clause(catch_clause);
+                }
+            }
+            TokenKind::Throw(throw_statement) => {
+                self.output.push_str("throw ");
+                self.print_expr(&throw_statement.value, Position::Free);
+            }
+            TokenKind::Vars => self.output.push_str("vars"),
+            TokenKind::Delete(names) => {
+                self.output.push_str("del ");
+                self.output.push_str(&names.join(", "));
+            }
+            TokenKind::Comment(text) => self.output.push_str(text),
+            _ => self.print_expr(token, Position::Free),
+        }
+    }
+}
+
+/// Prints statements back to canonical SLAT source: one statement per line, blocks
+/// indented by four spaces, comments kept and only the parentheses needed to read
+/// back the same tree. Printing the parse of printed source gives the same source
+/// again.
+pub fn print(tokens: &[Token]) -> String {
+    let mut printer = Printer {
+        output: String::new(),
+        indent: 0,
+    };
+    printer.print_statements(tokens);
+    if !tokens.is_empty() {
+        // every statement is printed after a newline
+        printer.output.remove(0);
+        printer.output.push('\n');
+    }
+    printer.output
+}
+
+/// Prints a single statement or expression without a trailing newline.
+pub fn print_token(token: &Token) -> String {
+    let mut printer = Printer {
+        output: String::new(),
+        indent: 0,
+    };
+    printer.print_statement(token);
+    printer.output
+}
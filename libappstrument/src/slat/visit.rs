@@ -0,0 +1,697 @@
+//! Traversal of the SLAT AST. Each `visit_*` method of a visitor defaults to the
+//! `walk_*` function of the same node, which visits the node's children, so an
+//! implementation only overrides the nodes it cares about and calls the walk
+//! function itself to keep descending.
+
+use std::rc::Rc;
+
+use super::ast::{
+    Assignment, BinaryExpression, CatchClause, Directive, ForEachLoop, FunctionDefinition,
+    IfStatement, Import, Lambda, LambdaBody, Literal, Member, MemberExpression, MemberKind,
+    MethodCall, NewArray, NewExpression, ReturnStatement, Slice, ThrowStatement, Token, TokenKind,
+    TryStatement, UnaryExpression, WhileLoop,
+};
+
+/// Visits a borrowed AST. Visitors that can't fail use `std::convert::Infallible`
+/// as their error.
+pub trait Visitor {
+    type Error;
+
+    fn visit_token(&mut self, token: &Token) -> Result<(), Self::Error> {
+        walk_token(self, token)
+    }
+
+    /// A list of statements, such as the body of a loop or a function.
+    fn visit_block(&mut self, body: &[Token]) -> Result<(), Self::Error> {
+        walk_block(self, body)
+    }
+
+    fn visit_identifier(&mut self, _name: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_literal(&mut self, _literal: &Literal) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_import(&mut self, _import: &Import) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_member_expression(
+        &mut self,
+        member_expr: &MemberExpression,
+    ) -> Result<(), Self::Error> {
+        walk_member_expression(self, member_expr)
+    }
+
+    fn visit_member(&mut self, member: &Member) -> Result<(), Self::Error> {
+        walk_member(self, member)
+    }
+
+    /// A call of a SLAT function or intrinsic, `f(x)`. Method calls on an owner
+    /// are members instead.
+    fn visit_method_call(&mut self, method_call: &MethodCall) -> Result<(), Self::Error> {
+        walk_method_call(self, method_call)
+    }
+
+    fn visit_new(&mut self, new_expr: &NewExpression) -> Result<(), Self::Error> {
+        walk_new(self, new_expr)
+    }
+
+    fn visit_new_array(&mut self, new_array: &NewArray) -> Result<(), Self::Error> {
+        walk_new_array(self, new_array)
+    }
+
+    fn visit_array_literal(&mut self, elements: &[Token]) -> Result<(), Self::Error> {
+        walk_array_literal(self, elements)
+    }
+
+    fn visit_binary_expression(
+        &mut self,
+        binary_expr: &BinaryExpression,
+    ) -> Result<(), Self::Error> {
+        walk_binary_expression(self, binary_expr)
+    }
+
+    fn visit_unary_expression(&mut self, unary_expr: &UnaryExpression) -> Result<(), Self::Error> {
+        walk_unary_expression(self, unary_expr)
+    }
+
+    fn visit_assignment(&mut self, assignment: &Assignment) -> Result<(), Self::Error> {
+        walk_assignment(self, assignment)
+    }
+
+    fn visit_directive(&mut self, directive: &Directive) -> Result<(), Self::Error> {
+        walk_directive(self, directive)
+    }
+
+    fn visit_if_statement(&mut self, if_statement: &IfStatement) -> Result<(), Self::Error> {
+        walk_if_statement(self, if_statement)
+    }
+
+    fn visit_while_loop(&mut self, while_loop: &WhileLoop) -> Result<(), Self::Error> {
+        walk_while_loop(self, while_loop)
+    }
+
+    fn visit_for_each_loop(&mut self, for_each: &ForEachLoop) -> Result<(), Self::Error> {
+        walk_for_each_loop(self, for_each)
+    }
+
+    fn visit_function(&mut self, function: &Rc<FunctionDefinition>) -> Result<(), Self::Error> {
+        walk_function(self, function)
+    }
+
+    fn visit_return(&mut self, return_statement: &ReturnStatement) -> Result<(), Self::Error> {
+        walk_return(self, return_statement)
+    }
+
+    fn visit_try_statement(&mut self, try_statement: &TryStatement) -> Result<(), Self::Error> {
+        walk_try_statement(self, try_statement)
+    }
+
+    fn visit_catch_clause(&mut self, catch_clause: &CatchClause) -> Result<(), Self::Error> {
+        walk_catch_clause(self, catch_clause)
+    }
+
+    fn visit_throw_statement(
+        &mut self,
+        throw_statement: &ThrowStatement,
+    ) -> Result<(), Self::Error> {
+        walk_throw_statement(self, throw_statement)
+    }
+
+    fn visit_lambda(&mut self, lambda: &Rc<Lambda>) -> Result<(), Self::Error> {
+        walk_lambda(self, lambda)
+    }
+
+    fn visit_object_handle(&mut self, _id: usize) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_vars(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_delete(&mut self, _names: &[String]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_comment(&mut self, _text: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+pub fn walk_token<V: Visitor + ?Sized>(visitor: &mut V, token: &Token) -> Result<(), V::Error> {
+    match &token.kind {
+        TokenKind::Identifier(name) => visitor.visit_identifier(name),
+        TokenKind::Literal(literal) => visitor.visit_literal(literal),
+        TokenKind::Import(import) => visitor.visit_import(import),
+        TokenKind::MemberExpression(member_expr) => visitor.visit_member_expression(member_expr),
+        TokenKind::MethodCall(method_call) => visitor.visit_method_call(method_call),
+        TokenKind::New(new_expr) => visitor.visit_new(new_expr),
+        TokenKind::NewArray(new_array) => visitor.visit_new_array(new_array),
+        TokenKind::ArrayLiteral(elements) => visitor.visit_array_literal(elements),
+        TokenKind::BinaryExpression(binary_expr) => visitor.visit_binary_expression(binary_expr),
+        TokenKind::UnaryExpression(unary_expr) => visitor.visit_unary_expression(unary_expr),
+        TokenKind::Assignment(assignment) => visitor.visit_assignment(assignment),
+        TokenKind::Directive(directive) => visitor.visit_directive(directive),
+        TokenKind::If(if_statement) => visitor.visit_if_statement(if_statement),
+        TokenKind::While(while_loop) => visitor.visit_while_loop(while_loop),
+        TokenKind::ForEach(for_each) => visitor.visit_for_each_loop(for_each),
+        TokenKind::Function(function) => visitor.visit_function(function),
+        TokenKind::Return(return_statement) => visitor.visit_return(return_statement),
+        TokenKind::Try(try_statement) => visitor.visit_try_statement(try_statement),
+        TokenKind::Throw(throw_statement) => visitor.visit_throw_statement(throw_statement),
+        TokenKind::Lambda(lambda) => visitor.visit_lambda(lambda),
+        TokenKind::ObjectHandle(id) => visitor.visit_object_handle(*id),
+        TokenKind::Vars => visitor.visit_vars(),
+        TokenKind::Delete(names) => visitor.visit_delete(names),
+        TokenKind::Comment(text) => visitor.visit_comment(text),
+    }
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, body: &[Token]) -> Result<(), V::Error> {
+    for token in body {
+        visitor.visit_token(token)?;
+    }
+    Ok(())
+}
+
+pub fn walk_member_expression<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    member_expr: &MemberExpression,
+) -> Result<(), V::Error> {
+    visitor.visit_token(&member_expr.owner)?;
+    for member in &member_expr.members {
+        visitor.visit_member(member)?;
+    }
+    Ok(())
+}
+
+pub fn walk_member<V: Visitor + ?Sized>(visitor: &mut V, member: &Member) -> Result<(), V::Error> {
+    match &member.kind {
+        MemberKind::Field(_) => Ok(()),
+        MemberKind::MethodCall(method_call) => walk_block(visitor, &method_call.args),
+        MemberKind::Index(index) => visitor.visit_token(index),
+        MemberKind::Slice(Slice { start, end }) => {
+            for bound in [start, end].into_iter().flatten() {
+                visitor.visit_token(bound)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+pub fn walk_method_call<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    method_call: &MethodCall,
+) -> Result<(), V::Error> {
+    walk_block(visitor, &method_call.args)
+}
+
+pub fn walk_new<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    new_expr: &NewExpression,
+) -> Result<(), V::Error> {
+    walk_block(visitor, &new_expr.args)
+}
+
+pub fn walk_new_array<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    new_array: &NewArray,
+) -> Result<(), V::Error> {
+    walk_block(visitor, &new_array.dimensions)
+}
+
+pub fn walk_array_literal<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    elements: &[Token],
+) -> Result<(), V::Error> {
+    walk_block(visitor, elements)
+}
+
+pub fn walk_binary_expression<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    binary_expr: &BinaryExpression,
+) -> Result<(), V::Error> {
+    visitor.visit_token(&binary_expr.left)?;
+    visitor.visit_token(&binary_expr.right)
+}
+
+pub fn walk_unary_expression<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    unary_expr: &UnaryExpression,
+) -> Result<(), V::Error> {
+    visitor.visit_token(&unary_expr.operand)
+}
+
+pub fn walk_assignment<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    assignment: &Assignment,
+) -> Result<(), V::Error> {
+    visitor.visit_token(&assignment.target)?;
+    visitor.visit_token(&assignment.expr)
+}
+
+pub fn walk_directive<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    directive: &Directive,
+) -> Result<(), V::Error> {
+    visitor.visit_token(&directive.value)
+}
+
+pub fn walk_if_statement<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    if_statement: &IfStatement,
+) -> Result<(), V::Error> {
+    visitor.visit_token(&if_statement.condition)?;
+    visitor.visit_block(&if_statement.body)?;
+    if let Some(else_body) = &if_statement.else_body {
+        visitor.visit_block(else_body)?;
+    }
+    Ok(())
+}
+
+pub fn walk_while_loop<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    while_loop: &WhileLoop,
+) -> Result<(), V::Error> {
+    visitor.visit_token(&while_loop.condition)?;
+    visitor.visit_block(&while_loop.body)
+}
+
+pub fn walk_for_each_loop<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    for_each: &ForEachLoop,
+) -> Result<(), V::Error> {
+    visitor.visit_token(&for_each.iterable)?;
+    visitor.visit_block(&for_each.body)
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    function: &FunctionDefinition,
+) -> Result<(), V::Error> {
+    visitor.visit_block(&function.body)
+}
+
+pub fn walk_return<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    return_statement: &ReturnStatement,
+) -> Result<(), V::Error> {
+    match &return_statement.value {
+        Some(value) => visitor.visit_token(value),
+        None => Ok(()),
+    }
+}
+
+pub fn walk_try_statement<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    try_statement: &TryStatement,
+) -> Result<(), V::Error> {
+    visitor.visit_block(&try_statement.body)?;
+    for catch_clause in &try_statement.catch_clauses {
+        visitor.visit_catch_clause(catch_clause)?;
+    }
+    Ok(())
+}
+
+pub fn walk_catch_clause<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    catch_clause: &CatchClause,
+) -> Result<(), V::Error> {
+    visitor.visit_block(&catch_clause.body)
+}
+
+pub fn walk_throw_statement<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    throw_statement: &ThrowStatement,
+) -> Result<(), V::Error> {
+    visitor.visit_token(&throw_statement.value)
+}
+
+pub fn walk_lambda<V: Visitor + ?Sized>(visitor: &mut V, lambda: &Lambda) -> Result<(), V::Error> {
+    match &lambda.body {
+        LambdaBody::Expression(expr) => visitor.visit_token(expr),
+        LambdaBody::Block(body) => visitor.visit_block(body),
+    }
+}
+
+/// Visits an AST in place, so it can be rewritten. Shared nodes, functions and
+/// lambdas, are copied on write.
+pub trait VisitorMut {
+    type Error;
+
+    fn visit_token_mut(&mut self, token: &mut Token) -> Result<(), Self::Error> {
+        walk_token_mut(self, token)
+    }
+
+    /// A list of statements, which may be added to or removed from.
+    fn visit_block_mut(&mut self, body: &mut Vec<Token>) -> Result<(), Self::Error> {
+        walk_block_mut(self, body)
+    }
+
+    fn visit_identifier_mut(&mut self, _name: &mut String) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_literal_mut(&mut self, _literal: &mut Literal) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_import_mut(&mut self, _import: &mut Import) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_member_expression_mut(
+        &mut self,
+        member_expr: &mut MemberExpression,
+    ) -> Result<(), Self::Error> {
+        walk_member_expression_mut(self, member_expr)
+    }
+
+    fn visit_member_mut(&mut self, member: &mut Member) -> Result<(), Self::Error> {
+        walk_member_mut(self, member)
+    }
+
+    fn visit_method_call_mut(&mut self, method_call: &mut MethodCall) -> Result<(), Self::Error> {
+        walk_method_call_mut(self, method_call)
+    }
+
+    fn visit_new_mut(&mut self, new_expr: &mut NewExpression) -> Result<(), Self::Error> {
+        walk_new_mut(self, new_expr)
+    }
+
+    fn visit_new_array_mut(&mut self, new_array: &mut NewArray) -> Result<(), Self::Error> {
+        walk_new_array_mut(self, new_array)
+    }
+
+    fn visit_array_literal_mut(&mut self, elements: &mut Vec<Token>) -> Result<(), Self::Error> {
+        walk_array_literal_mut(self, elements)
+    }
+
+    fn visit_binary_expression_mut(
+        &mut self,
+        binary_expr: &mut BinaryExpression,
+    ) -> Result<(), Self::Error> {
+        walk_binary_expression_mut(self, binary_expr)
+    }
+
+    fn visit_unary_expression_mut(
+        &mut self,
+        unary_expr: &mut UnaryExpression,
+    ) -> Result<(), Self::Error> {
+        walk_unary_expression_mut(self, unary_expr)
+    }
+
+    fn visit_assignment_mut(&mut self, assignment: &mut Assignment) -> Result<(), Self::Error> {
+        walk_assignment_mut(self, assignment)
+    }
+
+    fn visit_directive_mut(&mut self, directive: &mut Directive) -> Result<(), Self::Error> {
+        walk_directive_mut(self, directive)
+    }
+
+    fn visit_if_statement_mut(
+        &mut self,
+        if_statement: &mut IfStatement,
+    ) -> Result<(), Self::Error> {
+        walk_if_statement_mut(self, if_statement)
+    }
+
+    fn visit_while_loop_mut(&mut self, while_loop: &mut WhileLoop) -> Result<(), Self::Error> {
+        walk_while_loop_mut(self, while_loop)
+    }
+
+    fn visit_for_each_loop_mut(&mut self, for_each: &mut ForEachLoop) -> Result<(), Self::Error> {
+        walk_for_each_loop_mut(self, for_each)
+    }
+
+    fn visit_function_mut(
+        &mut self,
+        function: &mut Rc<FunctionDefinition>,
+    ) -> Result<(), Self::Error> {
+        walk_function_mut(self, function)
+    }
+
+    fn visit_return_mut(
+        &mut self,
+        return_statement: &mut ReturnStatement,
+    ) -> Result<(), Self::Error> {
+        walk_return_mut(self, return_statement)
+    }
+
+    fn visit_try_statement_mut(
+        &mut self,
+        try_statement: &mut TryStatement,
+    ) -> Result<(), Self::Error> {
+        walk_try_statement_mut(self, try_statement)
+    }
+
+    fn visit_catch_clause_mut(
+        &mut self,
+        catch_clause: &mut CatchClause,
+    ) -> Result<(), Self::Error> {
+        walk_catch_clause_mut(self, catch_clause)
+    }
+
+    fn visit_throw_statement_mut(
+        &mut self,
+        throw_statement: &mut ThrowStatement,
+    ) -> Result<(), Self::Error> {
+        walk_throw_statement_mut(self, throw_statement)
+    }
+
+    fn visit_lambda_mut(&mut self, lambda: &mut Rc<Lambda>) -> Result<(), Self::Error> {
+        walk_lambda_mut(self, lambda)
+    }
+
+    fn visit_object_handle_mut(&mut self, _id: &mut usize) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_vars_mut(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_delete_mut(&mut self, _names: &mut Vec<String>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_comment_mut(&mut self, _text: &mut String) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+pub fn walk_token_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    token: &mut Token,
+) -> Result<(), V::Error> {
+    match &mut token.kind {
+        TokenKind::Identifier(name) => visitor.visit_identifier_mut(name),
+        TokenKind::Literal(literal) => visitor.visit_literal_mut(literal),
+        TokenKind::Import(import) => visitor.visit_import_mut(import),
+        TokenKind::MemberExpression(member_expr) => {
+            visitor.visit_member_expression_mut(member_expr)
+        }
+        TokenKind::MethodCall(method_call) => visitor.visit_method_call_mut(method_call),
+        TokenKind::New(new_expr) => visitor.visit_new_mut(new_expr),
+        TokenKind::NewArray(new_array) => visitor.visit_new_array_mut(new_array),
+        TokenKind::ArrayLiteral(elements) => visitor.visit_array_literal_mut(elements),
+        TokenKind::BinaryExpression(binary_expr) => {
+            visitor.visit_binary_expression_mut(binary_expr)
+        }
+        TokenKind::UnaryExpression(unary_expr) => visitor.visit_unary_expression_mut(unary_expr),
+        TokenKind::Assignment(assignment) => visitor.visit_assignment_mut(assignment),
+        TokenKind::Directive(directive) => visitor.visit_directive_mut(directive),
+        TokenKind::If(if_statement) => visitor.visit_if_statement_mut(if_statement),
+        TokenKind::While(while_loop) => visitor.visit_while_loop_mut(while_loop),
+        TokenKind::ForEach(for_each) => visitor.visit_for_each_loop_mut(for_each),
+        TokenKind::Function(function) => visitor.visit_function_mut(function),
+        TokenKind::Return(return_statement) => visitor.visit_return_mut(return_statement),
+        TokenKind::Try(try_statement) => visitor.visit_try_statement_mut(try_statement),
+        TokenKind::Throw(throw_statement) => visitor.visit_throw_statement_mut(throw_statement),
+        TokenKind::Lambda(lambda) => visitor.visit_lambda_mut(lambda),
+        TokenKind::ObjectHandle(id) => visitor.visit_object_handle_mut(id),
+        TokenKind::Vars => visitor.visit_vars_mut(),
+        TokenKind::Delete(names) => visitor.visit_delete_mut(names),
+        TokenKind::Comment(text) => visitor.visit_comment_mut(text),
+    }
+}
+
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    body: &mut [Token],
+) -> Result<(), V::Error> {
+    for token in body {
+        visitor.visit_token_mut(token)?;
+    }
+    Ok(())
+}
+
+pub fn walk_member_expression_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    member_expr: &mut MemberExpression,
+) -> Result<(), V::Error> {
+    visitor.visit_token_mut(&mut member_expr.owner)?;
+    for member in &mut member_expr.members {
+        visitor.visit_member_mut(member)?;
+    }
+    Ok(())
+}
+
+pub fn walk_member_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    member: &mut Member,
+) -> Result<(), V::Error> {
+    match &mut member.kind {
+        MemberKind::Field(_) => Ok(()),
+        MemberKind::MethodCall(method_call) => walk_block_mut(visitor, &mut method_call.args),
+        MemberKind::Index(index) => visitor.visit_token_mut(index),
+        MemberKind::Slice(Slice { start, end }) => {
+            for bound in [start, end].into_iter().flatten() {
+                visitor.visit_token_mut(bound)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+pub fn walk_method_call_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    method_call: &mut MethodCall,
+) -> Result<(), V::Error> {
+    walk_block_mut(visitor, &mut method_call.args)
+}
+
+pub fn walk_new_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    new_expr: &mut NewExpression,
+) -> Result<(), V::Error> {
+    walk_block_mut(visitor, &mut new_expr.args)
+}
+
+pub fn walk_new_array_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    new_array: &mut NewArray,
+) -> Result<(), V::Error> {
+    walk_block_mut(visitor, &mut new_array.dimensions)
+}
+
+pub fn walk_array_literal_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    elements: &mut [Token],
+) -> Result<(), V::Error> {
+    walk_block_mut(visitor, elements)
+}
+
+pub fn walk_binary_expression_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    binary_expr: &mut BinaryExpression,
+) -> Result<(), V::Error> {
+    visitor.visit_token_mut(&mut binary_expr.left)?;
+    visitor.visit_token_mut(&mut binary_expr.right)
+}
+
+pub fn walk_unary_expression_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    unary_expr: &mut UnaryExpression,
+) -> Result<(), V::Error> {
+    visitor.visit_token_mut(&mut unary_expr.operand)
+}
+
+pub fn walk_assignment_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    assignment: &mut Assignment,
+) -> Result<(), V::Error> {
+    visitor.visit_token_mut(&mut assignment.target)?;
+    visitor.visit_token_mut(&mut assignment.expr)
+}
+
+pub fn walk_directive_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    directive: &mut Directive,
+) -> Result<(), V::Error> {
+    visitor.visit_token_mut(&mut directive.value)
+}
+
+pub fn walk_if_statement_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    if_statement: &mut IfStatement,
+) -> Result<(), V::Error> {
+    visitor.visit_token_mut(&mut if_statement.condition)?;
+    visitor.visit_block_mut(&mut if_statement.body)?;
+    if let Some(else_body) = &mut if_statement.else_body {
+        visitor.visit_block_mut(else_body)?;
+    }
+    Ok(())
+}
+
+pub fn walk_while_loop_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    while_loop: &mut WhileLoop,
+) -> Result<(), V::Error> {
+    visitor.visit_token_mut(&mut while_loop.condition)?;
+    visitor.visit_block_mut(&mut while_loop.body)
+}
+
+pub fn walk_for_each_loop_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    for_each: &mut ForEachLoop,
+) -> Result<(), V::Error> {
+    visitor.visit_token_mut(&mut for_each.iterable)?;
+    visitor.visit_block_mut(&mut for_each.body)
+}
+
+pub fn walk_function_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    function: &mut Rc<FunctionDefinition>,
+) -> Result<(), V::Error> {
+    visitor.visit_block_mut(&mut Rc::make_mut(function).body)
+}
+
+pub fn walk_return_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    return_statement: &mut ReturnStatement,
+) -> Result<(), V::Error> {
+    match &mut return_statement.value {
+        Some(value) => visitor.visit_token_mut(value),
+        None => Ok(()),
+    }
+}
+
+pub fn walk_try_statement_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    try_statement: &mut TryStatement,
+) -> Result<(), V::Error> {
+    visitor.visit_block_mut(&mut try_statement.body)?;
+    for catch_clause in &mut try_statement.catch_clauses {
+        visitor.visit_catch_clause_mut(catch_clause)?;
+    }
+    Ok(())
+}
+
+pub fn walk_catch_clause_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    catch_clause: &mut CatchClause,
+) -> Result<(), V::Error> {
+    visitor.visit_block_mut(&mut catch_clause.body)
+}
+
+pub fn walk_throw_statement_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    throw_statement: &mut ThrowStatement,
+) -> Result<(), V::Error> {
+    visitor.visit_token_mut(&mut throw_statement.value)
+}
+
+pub fn walk_lambda_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    lambda: &mut Rc<Lambda>,
+) -> Result<(), V::Error> {
+    match &mut Rc::make_mut(lambda).body {
+        LambdaBody::Expression(expr) => visitor.visit_token_mut(expr),
+        LambdaBody::Block(body) => visitor.visit_block_mut(body),
+    }
+}
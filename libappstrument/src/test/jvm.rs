@@ -1,8 +1,10 @@
 use jni::sys::*;
 use std::alloc::Layout;
 use std::collections::HashMap;
-use std::ffi::{c_char, c_void, CStr};
+use std::ffi::{c_char, c_void, CStr, CString};
 
+/// The objects the mock JVM knows, which is just enough to evaluate scripts
+/// working with primitives and strings.
 #[derive(Debug)]
 enum TestJvmObject {
     String(String),
@@ -12,79 +14,144 @@ enum TestJvmObject {
 impl TestJvmObject {
     fn as_string(&self) -> &String {
         match self {
-            Self::String(str) => &str,
+            Self::String(str) => str,
             _ => panic!("object is not a string"),
         }
     }
 
     fn as_class(&self) -> &String {
         match self {
-            Self::Class(str) => &str,
+            Self::Class(str) => str,
             _ => panic!("object is not a class"),
         }
     }
+
+    /// The JNI name of the object's class.
+    fn class_name(&self) -> &str {
+        match self {
+            Self::String(_) => "java/lang/String",
+            Self::Class(_) => "java/lang/Class",
+        }
+    }
 }
 
 #[derive(Debug)]
 struct TestJvm {
     objects: HashMap<usize, TestJvmObject>,
     methods: Vec<String>,
+    interned: HashMap<String, jobject>,
     object_nonce: usize,
 }
 
-unsafe fn take_jvm(env: *mut JNIEnv) -> Box<TestJvm> {
-    let ptr = (**env).reserved0;
-    Box::from_raw(ptr as *mut TestJvm)
-}
+impl TestJvm {
+    fn add_object(&mut self, obj: TestJvmObject) -> jobject {
+        let object_nonce = self.object_nonce;
+        self.objects.insert(object_nonce, obj);
+        self.object_nonce += 1;
+        // objects are referred to by their nonce, which is never dereferenced
+        object_nonce as jobject
+    }
+
+    fn object(&self, obj: jobject) -> &TestJvmObject {
+        self.objects
+            .get(&(obj as usize))
+            .expect("invalid object reference")
+    }
 
-unsafe fn restore_jvm(jvm: Box<TestJvm>) {
-    Box::into_raw(jvm);
+    fn method(&self, method_id: jmethodID) -> &str {
+        &self.methods[method_id as usize - 1]
+    }
 }
 
-unsafe fn create_jvm_ptr(val: usize) -> *mut usize {
-    Box::into_raw(Box::new(val))
+unsafe fn jvm<'a>(env: *mut JNIEnv) -> &'a mut TestJvm {
+    &mut *((**env).reserved0 as *mut TestJvm)
 }
 
-unsafe fn deref_jvm_ptr(ptr: *mut usize) -> usize {
-    let val = Box::from_raw(ptr);
-    let deref = *val;
-    Box::into_raw(val);
-    deref
+unsafe fn string_arg(jvm: &TestJvm, args: *const jvalue, index: usize) -> &String {
+    jvm.object((*args.add(index)).l).as_string()
 }
 
-unsafe extern "system" fn new_string_utf(env: *mut JNIEnv, str: *const i8) -> jstring {
+unsafe extern "system" fn new_string_utf(env: *mut JNIEnv, str: *const c_char) -> jstring {
     let cstr = CStr::from_ptr(str).to_str().expect("cstr to &str");
-    let obj = TestJvmObject::String(cstr.to_string());
-
-    let mut jvm = take_jvm(env);
-    let object_nonce = jvm.object_nonce;
-    jvm.objects.insert(object_nonce, obj);
-    jvm.object_nonce += 1;
+    jvm(env).add_object(TestJvmObject::String(cstr.to_string()))
+}
 
-    restore_jvm(jvm);
+unsafe extern "system" fn get_string_utf_chars(
+    env: *mut JNIEnv,
+    str: jstring,
+    _is_copy: *mut jboolean,
+) -> *const c_char {
+    let str = jvm(env).object(str).as_string();
+    CString::new(str.as_str()).expect("no nul").into_raw()
+}
 
-    create_jvm_ptr(object_nonce) as *mut _jobject
+unsafe extern "system" fn release_string_utf_chars(
+    _env: *mut JNIEnv,
+    _str: jstring,
+    chars: *const c_char,
+) {
+    drop(CString::from_raw(chars as *mut c_char));
 }
 
 unsafe extern "system" fn exception_check(_env: *mut JNIEnv) -> jboolean {
     0
 }
 
+unsafe extern "system" fn push_local_frame(_env: *mut JNIEnv, _capacity: jint) -> jint {
+    JNI_OK
+}
+
+unsafe extern "system" fn pop_local_frame(_env: *mut JNIEnv, result: jobject) -> jobject {
+    result
+}
+
+/// References are never released, so any reference is the object itself.
+unsafe extern "system" fn new_ref(_env: *mut JNIEnv, obj: jobject) -> jobject {
+    obj
+}
+
+unsafe extern "system" fn delete_ref(_env: *mut JNIEnv, _obj: jobject) {}
+
+unsafe extern "system" fn get_java_vm(env: *mut JNIEnv, vm: *mut *mut JavaVM) -> jint {
+    *vm = (**env).reserved1 as *mut JavaVM;
+    JNI_OK
+}
+
+unsafe extern "system" fn get_env(vm: *mut JavaVM, penv: *mut *mut c_void, _version: jint) -> jint {
+    *penv = (**vm).reserved0;
+    JNI_OK
+}
+
 unsafe extern "system" fn find_class(env: *mut JNIEnv, name: *const c_char) -> jclass {
     let cstr = CStr::from_ptr(name).to_str().expect("cstr to &str");
-    let obj = TestJvmObject::Class(cstr.to_string());
+    jvm(env).add_object(TestJvmObject::Class(cstr.to_string()))
+}
 
-    let mut jvm = take_jvm(env);
-    let object_nonce = jvm.object_nonce;
-    jvm.objects.insert(object_nonce, obj);
-    jvm.object_nonce += 1;
+unsafe extern "system" fn get_object_class(env: *mut JNIEnv, obj: jobject) -> jclass {
+    let jvm = jvm(env);
+    let class_name = jvm.object(obj).class_name().to_owned();
+    jvm.add_object(TestJvmObject::Class(class_name))
+}
 
-    restore_jvm(jvm);
+unsafe extern "system" fn is_instance_of(
+    env: *mut JNIEnv,
+    obj: jobject,
+    clazz: jclass,
+) -> jboolean {
+    let jvm = jvm(env);
+    let class_name = jvm.object(clazz).as_class();
+    (class_name == "java/lang/Object" || class_name == jvm.object(obj).class_name()) as jboolean
+}
 
-    create_jvm_ptr(object_nonce) as *mut _jobject
+unsafe extern "system" fn is_same_object(
+    _env: *mut JNIEnv,
+    obj1: jobject,
+    obj2: jobject,
+) -> jboolean {
+    (obj1 == obj2) as jboolean
 }
 
-unsafe extern "system" fn get_static_method_id(
+unsafe extern "system" fn get_method_id(
     env: *mut JNIEnv,
     clazz: jclass,
     name: *const c_char,
@@ -99,18 +166,71 @@ unsafe extern "system" fn get_static_method_id(
         .expect("cstr to &str")
         .to_string();
 
-    let mut jvm = take_jvm(env);
-    let class_name = jvm
-        .objects
-        .get(&deref_jvm_ptr(clazz as *mut usize))
-        .expect("invalid class object")
-        .as_class();
+    let jvm = jvm(env);
+    let class_name = jvm.object(clazz).as_class();
     let full_method_name = format!("{}.{}{}", class_name, method_name, method_signature);
-    let method_nonce = jvm.methods.len();
     jvm.methods.push(full_method_name);
-    restore_jvm(jvm);
+    jvm.methods.len() as jmethodID
+}
 
-    create_jvm_ptr(method_nonce) as *mut _jmethodID
+unsafe extern "system" fn call_object_method_a(
+    env: *mut JNIEnv,
+    obj: jobject,
+    method_id: jmethodID,
+    args: *const jvalue,
+) -> jobject {
+    let jvm = jvm(env);
+    let result = match (jvm.method(method_id), jvm.object(obj)) {
+        ("java/lang/Class.getName()Ljava/lang/String;", TestJvmObject::Class(name)) => {
+            name.replace('/', ".")
+        }
+        ("java/lang/String.substring(II)Ljava/lang/String;", TestJvmObject::String(str)) => {
+            str[(*args).i as usize..(*args.add(1)).i as usize].to_owned()
+        }
+        ("java/lang/String.intern()Ljava/lang/String;", TestJvmObject::String(str)) => {
+            let str = str.clone();
+            return *jvm.interned.entry(str).or_insert(obj);
+        }
+        (method, _) => panic!("the mock JVM can't call {}", method),
+    };
+    jvm.add_object(TestJvmObject::String(result))
+}
+
+unsafe extern "system" fn call_int_method_a(
+    env: *mut JNIEnv,
+    obj: jobject,
+    method_id: jmethodID,
+    _args: *const jvalue,
+) -> jint {
+    let jvm = jvm(env);
+    match jvm.method(method_id) {
+        "java/lang/String.length()I" => jvm.object(obj).as_string().len() as jint,
+        method => panic!("the mock JVM can't call {}", method),
+    }
+}
+
+unsafe extern "system" fn call_char_method_a(
+    env: *mut JNIEnv,
+    obj: jobject,
+    method_id: jmethodID,
+    args: *const jvalue,
+) -> jchar {
+    let jvm = jvm(env);
+    match jvm.method(method_id) {
+        "java/lang/String.charAt(I)C" => {
+            jvm.object(obj).as_string().as_bytes()[(*args).i as usize] as jchar
+        }
+        method => panic!("the mock JVM can't call {}", method),
+    }
+}
+
+unsafe extern "system" fn get_static_method_id(
+    env: *mut JNIEnv,
+    clazz: jclass,
+    name: *const c_char,
+    sig: *const c_char,
+) -> jmethodID {
+    get_method_id(env, clazz, name, sig)
 }
 
 unsafe extern "system" fn call_static_object_method_a(
@@ -119,29 +239,20 @@ unsafe extern "system" fn call_static_object_method_a(
     method_id: jmethodID,
     args: *const jvalue,
 ) -> jobject {
-    let jvm = take_jvm(env);
+    let jvm = jvm(env);
 
-    let method = &jvm.methods[deref_jvm_ptr(method_id as *mut usize)];
+    let method = jvm.method(method_id);
     if method == "appstrument/server/ReflectionUtil.findStaticFieldSignature(Ljava/lang/String;Ljava/lang/String;)Ljava/lang/String;" {
-        let args = std::slice::from_raw_parts(args, 2);
-        let arg0 = jvm
-            .objects
-            .get(&deref_jvm_ptr(args[0].l as *mut usize))
-            .expect("invalid class object")
-            .as_string();
-        let arg1 = jvm
-            .objects
-            .get(&deref_jvm_ptr(args[1].l as *mut usize))
-            .expect("invalid class object")
-            .as_string();
+        let arg0 = string_arg(jvm, args, 0);
+        let arg1 = string_arg(jvm, args, 1);
         println!("findStaticFieldSignature: {}.{}", arg0, arg1);
         if arg0 == "appstrument/server/ReflectionUtil" && arg1 == "testFieldObj" {
-            return create_jvm_ptr(100) as *mut jni::sys::_jobject;
+            return jvm.add_object(TestJvmObject::String(
+                "Lappstrument/server/JavaField;".to_owned(),
+            ));
         }
     }
 
-    restore_jvm(jvm);
-
     std::ptr::null_mut()
 }
 
@@ -150,19 +261,44 @@ pub fn create_mock_jvm<'a>() -> jni::JNIEnv<'a> {
         let test_jvm = TestJvm {
             objects: HashMap::new(),
             methods: Vec::new(),
+            interned: HashMap::new(),
             object_nonce: 1,
         };
 
-        let env_memory = std::alloc::alloc(Layout::new::<JNINativeInterface_>());
-        let mut sys_env = env_memory as *mut JNINativeInterface_;
+        // functions the mock doesn't implement are left null, which jni reports as an error
+        let vm_memory = std::alloc::alloc_zeroed(Layout::new::<JNIInvokeInterface_>());
+        let sys_vm = vm_memory as *mut JNIInvokeInterface_;
+        (*sys_vm).GetEnv = Some(get_env);
+        let env_memory = std::alloc::alloc_zeroed(Layout::new::<JNINativeInterface_>());
+        let sys_env = env_memory as *mut JNINativeInterface_;
         (*sys_env).reserved0 = Box::into_raw(Box::new(test_jvm)) as *mut c_void;
         (*sys_env).NewStringUTF = Some(new_string_utf);
+        (*sys_env).GetStringUTFChars = Some(get_string_utf_chars);
+        (*sys_env).ReleaseStringUTFChars = Some(release_string_utf_chars);
         (*sys_env).ExceptionCheck = Some(exception_check);
+        (*sys_env).PushLocalFrame = Some(push_local_frame);
+        (*sys_env).PopLocalFrame = Some(pop_local_frame);
+        (*sys_env).NewLocalRef = Some(new_ref);
+        (*sys_env).NewGlobalRef = Some(new_ref);
+        (*sys_env).DeleteLocalRef = Some(delete_ref);
+        (*sys_env).DeleteGlobalRef = Some(delete_ref);
+        (*sys_env).GetJavaVM = Some(get_java_vm);
         (*sys_env).FindClass = Some(find_class);
+        (*sys_env).GetObjectClass = Some(get_object_class);
+        (*sys_env).IsInstanceOf = Some(is_instance_of);
+        (*sys_env).IsSameObject = Some(is_same_object);
+        (*sys_env).GetMethodID = Some(get_method_id);
+        (*sys_env).CallObjectMethodA = Some(call_object_method_a);
+        (*sys_env).CallIntMethodA = Some(call_int_method_a);
+        (*sys_env).CallCharMethodA = Some(call_char_method_a);
         (*sys_env).GetStaticMethodID = Some(get_static_method_id);
         (*sys_env).CallStaticObjectMethodA = Some(call_static_object_method_a);
 
-        let sys_env_ptr = &mut (sys_env as *const JNINativeInterface_);
+        // the environment and VM live as long as the test process
+        let sys_env_ptr = Box::into_raw(Box::new(sys_env as *const JNINativeInterface_));
+        let sys_vm_ptr = Box::into_raw(Box::new(sys_vm as *const JNIInvokeInterface_));
+        (*sys_env).reserved1 = sys_vm_ptr as *mut c_void;
+        (*sys_vm).reserved0 = sys_env_ptr as *mut c_void;
         jni::JNIEnv::from_raw(sys_env_ptr).unwrap()
     }
 }